@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE transactions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id BIGINT NOT NULL,
+    counterparty BIGINT,
+    source TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX transactions_user_id ON transactions (user_id, created_at);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE tax_contributions (
+    user_id BIGINT NOT NULL PRIMARY KEY,
+    total INTEGER NOT NULL DEFAULT 0
+)
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE guild_config (
+    guild_id BIGINT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (guild_id, key)
+)
@@ -0,0 +1,31 @@
+-- This file should undo anything in `up.sql`
+
+-- Only one guild's worth of rows can survive this
+
+CREATE TABLE users_old (
+    id BIGINT NOT NULL PRIMARY KEY,
+    points INTEGER NOT NULL DEFAULT 0
+);
+INSERT OR IGNORE INTO users_old (id, points) SELECT id, points FROM users ORDER BY guild_id;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE TABLE state_old (
+    key TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL
+);
+INSERT OR IGNORE INTO state_old (key, value) SELECT key, value FROM state ORDER BY guild_id;
+DROP TABLE state;
+ALTER TABLE state_old RENAME TO state;
+
+CREATE TABLE tax_contributions_old (
+    user_id BIGINT NOT NULL PRIMARY KEY,
+    total INTEGER NOT NULL DEFAULT 0
+);
+INSERT OR IGNORE INTO tax_contributions_old (user_id, total) SELECT user_id, total FROM tax_contributions ORDER BY guild_id;
+DROP TABLE tax_contributions;
+ALTER TABLE tax_contributions_old RENAME TO tax_contributions;
+
+DROP INDEX transactions_user_id;
+ALTER TABLE transactions DROP COLUMN guild_id;
+CREATE INDEX transactions_user_id ON transactions (user_id, created_at);
@@ -0,0 +1,38 @@
+-- Your SQL goes here
+
+-- Existing rows belong to guild 0 until they're adopted by
+-- the legacy `GUILD_ID` at startup
+
+CREATE TABLE users_new (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    id BIGINT NOT NULL,
+    points INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, id)
+);
+INSERT INTO users_new (guild_id, id, points) SELECT 0, id, points FROM users;
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+
+CREATE TABLE state_new (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (guild_id, key)
+);
+INSERT INTO state_new (guild_id, key, value) SELECT 0, key, value FROM state;
+DROP TABLE state;
+ALTER TABLE state_new RENAME TO state;
+
+CREATE TABLE tax_contributions_new (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    user_id BIGINT NOT NULL,
+    total INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, user_id)
+);
+INSERT INTO tax_contributions_new (guild_id, user_id, total) SELECT 0, user_id, total FROM tax_contributions;
+DROP TABLE tax_contributions;
+ALTER TABLE tax_contributions_new RENAME TO tax_contributions;
+
+ALTER TABLE transactions ADD COLUMN guild_id BIGINT NOT NULL DEFAULT 0;
+DROP INDEX transactions_user_id;
+CREATE INDEX transactions_user_id ON transactions (guild_id, user_id, created_at);
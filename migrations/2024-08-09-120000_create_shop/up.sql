@@ -0,0 +1,30 @@
+-- Your SQL goes here
+CREATE TABLE shop_items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    price INTEGER NOT NULL,
+    role_id BIGINT,
+    duration BIGINT,
+    enabled BOOLEAN NOT NULL DEFAULT 1
+);
+
+-- Removed items are only disabled, so old purchases still point somewhere
+CREATE UNIQUE INDEX shop_items_guild_id_name ON shop_items (guild_id, name) WHERE enabled;
+
+CREATE TABLE purchases (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    item_id INTEGER NOT NULL REFERENCES shop_items (id),
+    user_id BIGINT NOT NULL,
+    price INTEGER NOT NULL,
+    target TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    role_id BIGINT,
+    expires_at BIGINT,
+    expired BOOLEAN NOT NULL DEFAULT 0,
+    refunded BOOLEAN NOT NULL DEFAULT 0
+);
+
+CREATE INDEX purchases_guild_id_user_id ON purchases (guild_id, user_id);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE starboard (
+    message_id BIGINT PRIMARY KEY NOT NULL,
+    guild_id BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    author_id BIGINT NOT NULL,
+    stars INTEGER NOT NULL DEFAULT 0,
+    repost_id BIGINT
+)
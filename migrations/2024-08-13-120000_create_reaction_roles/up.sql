@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE reaction_roles (
+    message_id BIGINT NOT NULL,
+    emoji TEXT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    role_id BIGINT NOT NULL,
+    cost INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (message_id, emoji)
+)
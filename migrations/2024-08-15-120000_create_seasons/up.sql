@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE seasons (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    number INTEGER NOT NULL,
+    ended_at BIGINT NOT NULL,
+    -- Everything in the ledger up to (and including) this belongs to the season
+    last_transaction_id INTEGER NOT NULL,
+    UNIQUE (guild_id, number)
+);
+
+CREATE TABLE season_standings (
+    season_id INTEGER NOT NULL REFERENCES seasons (id),
+    user_id BIGINT NOT NULL,
+    rank INTEGER NOT NULL,
+    earned BIGINT NOT NULL,
+    points INTEGER NOT NULL,
+    PRIMARY KEY (season_id, user_id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE counting_cycles;
+DROP TABLE counting_stats;
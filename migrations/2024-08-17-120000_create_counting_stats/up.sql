@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE counting_stats (
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    correct INTEGER NOT NULL DEFAULT 0,
+    mistakes INTEGER NOT NULL DEFAULT 0,
+    cycles INTEGER NOT NULL DEFAULT 0,
+    -- Correct counts since their last mistake
+    current_run INTEGER NOT NULL DEFAULT 0,
+    longest_run INTEGER NOT NULL DEFAULT 0,
+    last_count_at BIGINT,
+    PRIMARY KEY (guild_id, user_id)
+);
+
+CREATE TABLE counting_cycles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    -- Unknown for the cycle that was running when this was added
+    started_at BIGINT,
+    completed_at BIGINT NOT NULL,
+    completed_by BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE counting_runs;
+
+ALTER TABLE counting_stats DROP COLUMN saves;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE counting_stats ADD COLUMN saves INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE counting_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    -- Unknown for the run that was going when hardcore mode was added
+    started_at BIGINT,
+    ended_at BIGINT NOT NULL,
+    reached INTEGER NOT NULL,
+    ruined_by BIGINT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE counting_milestones (
+    guild_id BIGINT NOT NULL,
+    number BIGINT NOT NULL,
+    reward INTEGER NOT NULL DEFAULT 0,
+    role_id BIGINT,
+    PRIMARY KEY (guild_id, number)
+);
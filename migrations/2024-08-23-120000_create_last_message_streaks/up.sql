@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE last_message_streaks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    started_at BIGINT NOT NULL,
+    ended_at BIGINT NOT NULL,
+    broken_by BIGINT
+);
+
+CREATE INDEX last_message_streaks_guild_id ON last_message_streaks (guild_id);
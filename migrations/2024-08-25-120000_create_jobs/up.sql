@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    -- Which `Job` runs it
+    kind TEXT NOT NULL,
+    -- Whatever else the job needs to know
+    payload TEXT NOT NULL DEFAULT '',
+    schedule TEXT NOT NULL,
+    next_run BIGINT NOT NULL,
+    last_run BIGINT,
+    cancelled BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX jobs_next_run ON jobs (next_run) WHERE NOT cancelled;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE aura_claims (
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    -- 'daily' or 'weekly'
+    period TEXT NOT NULL,
+    last_claim BIGINT NOT NULL,
+    -- Claims in a row, each within the grace window of the one before
+    streak INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (guild_id, user_id, period)
+);
@@ -0,0 +1,55 @@
+-- This file should undo anything in `up.sql`
+
+-- Balances that no longer fit are clamped
+
+CREATE TABLE users_old (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    id BIGINT NOT NULL,
+    points INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, id)
+);
+INSERT INTO users_old (guild_id, id, points)
+    SELECT guild_id, id, MAX(MIN(points, 2147483647), -2147483648) FROM users;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE TABLE transactions_old (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id BIGINT NOT NULL,
+    counterparty BIGINT,
+    source TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL DEFAULT 0
+);
+INSERT INTO transactions_old (id, user_id, counterparty, source, amount, reason, created_at, guild_id)
+    SELECT id, user_id, counterparty, source, MAX(MIN(amount, 2147483647), -2147483648), reason, created_at, guild_id
+    FROM transactions;
+DROP TABLE transactions;
+ALTER TABLE transactions_old RENAME TO transactions;
+CREATE INDEX transactions_user_id ON transactions (guild_id, user_id, created_at);
+
+CREATE TABLE tax_contributions_old (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    user_id BIGINT NOT NULL,
+    total INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, user_id)
+);
+INSERT INTO tax_contributions_old (guild_id, user_id, total)
+    SELECT guild_id, user_id, MAX(MIN(total, 2147483647), -2147483648) FROM tax_contributions;
+DROP TABLE tax_contributions;
+ALTER TABLE tax_contributions_old RENAME TO tax_contributions;
+
+CREATE TABLE season_standings_old (
+    season_id INTEGER NOT NULL REFERENCES seasons (id),
+    user_id BIGINT NOT NULL,
+    rank INTEGER NOT NULL,
+    earned BIGINT NOT NULL,
+    points INTEGER NOT NULL,
+    PRIMARY KEY (season_id, user_id)
+);
+INSERT INTO season_standings_old (season_id, user_id, rank, earned, points)
+    SELECT season_id, user_id, rank, earned, MAX(MIN(points, 2147483647), -2147483648) FROM season_standings;
+DROP TABLE season_standings;
+ALTER TABLE season_standings_old RENAME TO season_standings;
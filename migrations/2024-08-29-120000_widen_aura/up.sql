@@ -0,0 +1,53 @@
+-- Your SQL goes here
+
+-- Aura used to be 32-bit; SQLite stores it as 64-bit either way,
+-- so this only changes what the columns are declared as
+
+CREATE TABLE users_new (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    id BIGINT NOT NULL,
+    points BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, id)
+);
+INSERT INTO users_new (guild_id, id, points) SELECT guild_id, id, points FROM users;
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+
+CREATE TABLE transactions_new (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id BIGINT NOT NULL,
+    counterparty BIGINT,
+    source TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL DEFAULT 0
+);
+INSERT INTO transactions_new (id, user_id, counterparty, source, amount, reason, created_at, guild_id)
+    SELECT id, user_id, counterparty, source, amount, reason, created_at, guild_id FROM transactions;
+DROP TABLE transactions;
+ALTER TABLE transactions_new RENAME TO transactions;
+CREATE INDEX transactions_user_id ON transactions (guild_id, user_id, created_at);
+
+CREATE TABLE tax_contributions_new (
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    user_id BIGINT NOT NULL,
+    total BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, user_id)
+);
+INSERT INTO tax_contributions_new (guild_id, user_id, total) SELECT guild_id, user_id, total FROM tax_contributions;
+DROP TABLE tax_contributions;
+ALTER TABLE tax_contributions_new RENAME TO tax_contributions;
+
+CREATE TABLE season_standings_new (
+    season_id INTEGER NOT NULL REFERENCES seasons (id),
+    user_id BIGINT NOT NULL,
+    rank INTEGER NOT NULL,
+    earned BIGINT NOT NULL,
+    points BIGINT NOT NULL,
+    PRIMARY KEY (season_id, user_id)
+);
+INSERT INTO season_standings_new (season_id, user_id, rank, earned, points)
+    SELECT season_id, user_id, rank, earned, points FROM season_standings;
+DROP TABLE season_standings;
+ALTER TABLE season_standings_new RENAME TO season_standings;
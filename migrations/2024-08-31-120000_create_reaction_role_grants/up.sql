@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Roles members got by reacting, so unreacting only takes
+-- back roles that were given that way
+CREATE TABLE reaction_role_grants (
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    role_id BIGINT NOT NULL,
+    PRIMARY KEY (guild_id, user_id, role_id)
+)
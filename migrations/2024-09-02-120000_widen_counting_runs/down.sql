@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+
+-- Runs that no longer fit are clamped
+
+CREATE TABLE counting_runs_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    started_at BIGINT,
+    ended_at BIGINT NOT NULL,
+    reached INTEGER NOT NULL,
+    ruined_by BIGINT NOT NULL
+);
+INSERT INTO counting_runs_old (id, guild_id, started_at, ended_at, reached, ruined_by)
+    SELECT id, guild_id, started_at, ended_at, MIN(reached, 2147483647), ruined_by FROM counting_runs;
+DROP TABLE counting_runs;
+ALTER TABLE counting_runs_old RENAME TO counting_runs;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- Counting can go on forever now, past what 32 bits can hold
+
+CREATE TABLE counting_runs_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    -- Unknown for the run that was going when hardcore mode was added
+    started_at BIGINT,
+    ended_at BIGINT NOT NULL,
+    reached BIGINT NOT NULL,
+    ruined_by BIGINT NOT NULL
+);
+INSERT INTO counting_runs_new (id, guild_id, started_at, ended_at, reached, ruined_by)
+    SELECT id, guild_id, started_at, ended_at, reached, ruined_by FROM counting_runs;
+DROP TABLE counting_runs;
+ALTER TABLE counting_runs_new RENAME TO counting_runs;
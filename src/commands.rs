@@ -1,19 +1,44 @@
 use std::collections::HashSet;
 use std::fmt::Write;
 
-use poise::{CreateReply, FrameworkError};
-use serenity::all::{parse_message_url, Member, Timestamp, UserId};
+use poise::{ChoiceParameter, CreateReply, FrameworkError};
+use serenity::all::{
+    parse_message_url, ChannelId, FullEvent, GuildChannel, GuildId, Member, MessageId, ReactionType,
+    Role, Timestamp, UserId,
+};
 use serenity::builder::{CreateEmbed, CreateEmbedFooter};
-
-use crate::subsystems::{Counting, LastMessage};
-use crate::env_snowflake;
-use crate::db::{db_conn, DbUser};
+use tracing::info;
+
+use crate::subsystems::{
+    Counting, CountingMode, Job, LastMessage, SeasonEnd, SeasonEndPayload, Shop, ShopOrder, Tax, TaxPayoutMode,
+};
+use crate::{env_snowflake, hms};
+use crate::db::{
+    AuraClaim, AuraSource, Claim, ClaimPeriod, ConfigKey, CountingCycle, CountingMilestone, CountingRun, CountingStats, DbConn,
+    DbPool, DbTransaction, DbUser, GuildConfig, LastMessageStreak, Purchase, ReactionRole,
+    Schedule, ScheduledJob, Season, ShopItem, ShopItemKind,
+};
 use crate::error::{DungeonBotError, Result};
 
 #[derive(Debug)]
-pub struct Data;
+pub struct Data {
+    pub pool: DbPool,
+}
+
+impl Data {
+    /// Gets a connection from the database pool.
+    pub fn conn(&self) -> Result<DbConn> {
+        self.pool.get().map_err(DungeonBotError::from)
+    }
+}
+
 type Context<'a> = poise::Context<'a, Data, DungeonBotError>;
 
+/// The guild a (guild only) command was used in
+fn guild_id(ctx: Context<'_>) -> Result<GuildId> {
+    ctx.guild_id().ok_or(DungeonBotError::NotInGuildError)
+}
+
 /// Displays the leaderboard of the users with the
 /// highest aura in the server
 #[poise::command(
@@ -25,24 +50,34 @@ pub async fn leaderboard(
     #[description = "Page number"] 
     #[min=1]
     #[max=10000]
-    page: Option<i64>
+    page: Option<i64>,
+    #[description = "Season number (defaults to lifetime aura)"]
+    #[min=1]
+    season: Option<i32>,
 ) -> Result<()> {
     let page = page.unwrap_or(1);
+    let guild_id = guild_id(ctx)?;
+
+    if let Some(season) = season {
+        return season_leaderboard(ctx, guild_id, season, page).await
+    }
 
-    let lmstate = LastMessage::state(
-        ctx.serenity_context()
+    let winner = LastMessage::get_winner(
+        ctx.serenity_context(),
+        guild_id
     ).await?;
 
-    let connection = &mut db_conn()?;
+    let connection = &mut ctx.data().conn()?;
 
     let mut fields = vec![];
 
     let offset = (page-1) * 10;
     let mut i = offset + 1;
-    for user in DbUser::top(connection, 10, offset) {
+    for user in DbUser::top(connection, guild_id.into(), 10, offset) {
         let DbUser {
             id: user_id,
-            points: pts
+            points: pts,
+            ..
         } = user;
         let user_id = user_id as u64;
 
@@ -53,27 +88,18 @@ pub async fn leaderboard(
                 continue
             };
 
-        let mut title = format!("{}. {}", i, user.name.to_string());
-        let mut body = format!("{} aura", pts);
-
-        if let Some((winner, streak)) = &lmstate {
-            if winner.user.id == user.id {
-                title.push_str(" ⭐");
+        let mut title = format!("{}. {}", i, user.name);
+        let body = format!("{} aura", pts);
 
-                let streak_pts = streak/5;
-                body = format!("{} ({} total + {} current streak)", 
-                            pts as i64 + streak_pts,
-                            pts, 
-                            streak_pts
-                            )
-            }
+        if winner.as_ref().is_some_and(|winner| winner.user.id == user.id) {
+            title.push_str(" ⭐");
         }
 
         fields.push((title, body, false));
         i += 1;
     }
 
-    let npages = DbUser::count(connection)?/10;
+    let npages = DbUser::count(connection, guild_id.into())?/10;
     let footer = CreateEmbedFooter::new(format!("Page {}/{}", page, npages));
     let embed = CreateEmbed::new()
         .title("The Friendship Dungeon Aura Leaderboard")
@@ -89,31 +115,186 @@ pub async fn leaderboard(
     Ok(())
 }
 
+/// Displays the standings of season `number`: the aura earned
+/// so far if it's the current one, or the archive if it's over.
+async fn season_leaderboard(ctx: Context<'_>, guild_id: GuildId, number: i32, page: i64) -> Result<()> {
+    let connection = &mut ctx.data().conn()?;
+    let current = Season::current(connection, guild_id.into())?;
+    let offset = (page-1) * 10;
+
+    let (standings, count) = if number == current {
+        let earnings = Season::earnings(connection, guild_id.into(), 10, offset)?
+            .into_iter()
+            .map(|(user_id, earned)| (user_id, format!("{} aura earned", earned)))
+            .collect::<Vec<_>>();
+        (earnings, Season::count_earners(connection, guild_id.into())?)
+    } else if let Some(season) = Season::get(connection, guild_id.into(), number)? {
+        let standings = season.standings(connection, 10, offset)?
+            .into_iter()
+            .map(|standing| (
+                standing.user_id,
+                format!("{} aura earned ({} aura at the end)", standing.earned, standing.points)
+            ))
+            .collect::<Vec<_>>();
+        (standings, season.count_standings(connection)?)
+    } else {
+        ctx.say(format!("Season {} hasn't started yet.", number)).await?;
+        return Ok(())
+    };
+
+    let mut fields = vec![];
+    for (i, (user_id, body)) in standings.into_iter().enumerate() {
+        let Ok(user) = UserId::new(user_id as u64).to_user(&ctx.http()).await else { continue };
+        fields.push((format!("{}. {}", offset + i as i64 + 1, user.name), body, false));
+    }
+
+    let title = if number == current {
+        format!("The Friendship Dungeon Aura Leaderboard — Season {} (so far)", number)
+    } else {
+        format!("The Friendship Dungeon Aura Leaderboard — Season {}", number)
+    };
+    let footer = CreateEmbedFooter::new(format!("Page {}/{}", page, (count + 9) / 10));
+    let embed = CreateEmbed::new()
+        .title(title)
+        .fields(fields)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Displays who has the last message, and the longest
+/// last message streaks of all time
+#[poise::command(
+    slash_command,
+    guild_only,
+    on_error="error_handler",
+)]
+pub async fn lastmessage(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    let lmstate = LastMessage::state(ctx.serenity_context(), guild_id).await?;
+
+    let connection = &mut ctx.data().conn()?;
+
+    let mut description = match lmstate {
+        Some((winner, streak)) => format!(
+            "⭐ {} has had the last message for {}.",
+            winner.display_name(),
+            hms(streak)
+        ),
+        None => "Nobody has the last message.".to_string(),
+    };
+
+    let top = LastMessageStreak::top(connection, guild_id.into(), 10)?;
+    if !top.is_empty() {
+        description.push_str("\n\n**Longest streaks**");
+    }
+    for (i, streak) in top.into_iter().enumerate() {
+        write!(description, "\n{}. <@{}>: {}", i + 1, streak.user_id, hms(streak.duration())).unwrap();
+        if let Some(broken_by) = streak.broken_by {
+            write!(description, " (broken by <@{}> <t:{}:R>)", broken_by, streak.ended_at).unwrap();
+        }
+    }
+
+    let embed = CreateEmbed::new()
+        .title("The Friendship Dungeon Last Message")
+        .description(description)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Claims your daily aura
+#[poise::command(
+    slash_command,
+    guild_only,
+    on_error="error_handler",
+)]
+pub async fn daily(ctx: Context<'_>) -> Result<()> {
+    claim(ctx, ClaimPeriod::Daily, ConfigKey::DailyReward).await
+}
+
+/// Claims your weekly aura
+#[poise::command(
+    slash_command,
+    guild_only,
+    on_error="error_handler",
+)]
+pub async fn weekly(ctx: Context<'_>) -> Result<()> {
+    claim(ctx, ClaimPeriod::Weekly, ConfigKey::WeeklyReward).await
+}
+
+/// Claims the author's `period` aura, worth `key` (times their streak).
+async fn claim(ctx: Context<'_>, period: ClaimPeriod, key: ConfigKey) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let reward = GuildConfig::get_as::<i64>(ctx.serenity_context(), guild_id, key).await?
+        .unwrap_or(period.default_reward());
+
+    let connection = &mut ctx.data().conn()?;
+    let claim = AuraClaim::claim(
+        connection,
+        guild_id.into(),
+        ctx.author().id.into(),
+        period,
+        reward,
+        Timestamp::now().unix_timestamp()
+    )?;
+
+    let reply = match claim {
+        Claim::Claimed { amount, streak, multiplier } => format!(
+            "🎁 You claimed {} aura! That's {} {} in a row ({}x).",
+            amount,
+            streak,
+            if period == ClaimPeriod::Daily { "days" } else { "weeks" },
+            multiplier
+        ),
+        Claim::TooSoon { next_claim } => format!(
+            "You already claimed your {} aura. Come back <t:{}:R>.",
+            period.as_str(),
+            next_claim
+        ),
+        Claim::Overflow => "Sorry, this would cause an integer overflow lol.".to_string(),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
 
 #[poise::command(
     slash_command,
     guild_only,
-    subcommands("aura_show", "aura_give", "aura_add")
+    subcommands("aura_show", "aura_give", "aura_add", "aura_history")
 )]
 pub async fn aura(_: Context<'_>) -> Result<()> { Ok(()) }
 
 /// Displays your aura.
 #[poise::command(
     slash_command,
+    guild_only,
     rename="show",
     on_error="error_handler",
 )]
 async fn aura_show(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
     let user_id: u64 = ctx.author().id.into();
-    let connection = &mut db_conn()?;
-
-    let lmstate = LastMessage::state(ctx.serenity_context()).await?;
+    let connection = &mut ctx.data().conn()?;
 
     // Retrieve points from db
     let DbUser {
-        id:_,
-        points
-    } = DbUser::get(connection, user_id)?
+        points,
+        ..
+    } = DbUser::get(connection, guild_id.into(), user_id)?
         .ok_or(DungeonBotError::DbUserNotFoundError(user_id))?;
 
     let name = ctx.author_member().await
@@ -121,17 +302,7 @@ async fn aura_show(ctx: Context<'_>) -> Result<()> {
         .display_name()
         .to_string();
 
-
-    let reply = match lmstate {
-        Some((winner, streak)) if winner.user.id == user_id 
-        => format!("{}, you have {} aura. ({} total + {} current streak)", 
-                   name, 
-                   points as i64 + streak/5, 
-                   points, 
-                   streak/5),
-        _ => format!("{}, you have {} aura.", name, points),
-    };
-    ctx.say(reply).await?;
+    ctx.say(format!("{}, you have {} aura.", name, points)).await?;
 
     Ok(())
 }
@@ -148,8 +319,9 @@ async fn aura_give(
     #[description="Recipient"] to: Member,
     #[description="Amount of aura to give"] 
     #[min=1]
-    pts: i32,
+    pts: i64,
 ) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
     let to_id: u64 = to.user.id.into();
     let from_id: u64 = ctx.author().id.into();
 
@@ -158,34 +330,33 @@ async fn aura_give(
         return Ok(())
     }
 
-    let connection = &mut db_conn()?;
-    let to_db = DbUser::new(connection, to_id)?;
-    let from_db = DbUser::new(connection, from_id)?;
-
-    if from_db.points < 0 {
-        ctx.say("You are in aura debt.").await?;
-        return Ok(())
-    }
-
-    if from_db.points < pts {
-        let reply = format!(
-            "Not enough points to complete this transaction!\nYou have {}, but you are trying to give {}",
-            from_db.points, 
-            pts
-            );
-        ctx.say(reply).await?;
-        return Ok(())
-    }
+    let connection = &mut ctx.data().conn()?;
+    DbUser::new(connection, guild_id.into(), to_id)?;
+    DbUser::new(connection, guild_id.into(), from_id)?;
 
-    // Overflow check
-    if let None = to_db.points.checked_add(pts) {
-        let reply = format!("Sorry, this would cause an integer overflow lol.");
-        ctx.say(reply).await?;
-        return Ok(())
+    match DbUser::xfer_points(connection, guild_id.into(), to_id, from_id, pts, "/aura give") {
+        Ok(()) => (),
+        Err(DungeonBotError::InsufficientAuraError { balance, .. }) if balance < 0 => {
+            ctx.say("You are in aura debt.").await?;
+            return Ok(())
+        }
+        Err(DungeonBotError::InsufficientAuraError { balance, needed, .. }) => {
+            let reply = format!(
+                "Not enough points to complete this transaction!\nYou have {}, but you are trying to give {}",
+                balance, 
+                needed
+                );
+            ctx.say(reply).await?;
+            return Ok(())
+        }
+        Err(DungeonBotError::AuraOverflowError(_)) => {
+            let reply = "Sorry, this would cause an integer overflow lol.";
+            ctx.say(reply).await?;
+            return Ok(())
+        }
+        Err(err) => return Err(err),
     }
 
-    DbUser::xfer_points(connection, to_id, from_id, pts)?; 
-
     let from = ctx.author_member().await
         .ok_or(DungeonBotError::DiscordUserNotFoundError(from_id))?;
 
@@ -210,36 +381,44 @@ async fn aura_add(
     ctx: Context<'_>,
     #[description="Recipient"] to: Member,
     #[description="Amount of aura to give"] 
-    pts: i32,
+    pts: i64,
     #[description="Reason"]
     why: Option<String>,
 ) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
     let to_id: u64 = to.user.id.into();
 
-    let connection = &mut db_conn()?;
-    let to_db = DbUser::new(connection, to_id)?;
+    let connection = &mut ctx.data().conn()?;
+    DbUser::new(connection, guild_id.into(), to_id)?;
 
-    // Overflow check
-    if let None = to_db.points.checked_add(pts) {
-        let reply = format!("Sorry, this would cause an integer overflow lol.");
+    let why = why
+        .unwrap_or("No reason given".to_string());
+    let added = DbUser::add_points(
+        connection,
+        guild_id.into(),
+        to_id,
+        pts,
+        AuraSource::Add,
+        Some(ctx.author().id.into()),
+        &why
+    );
+    if let Err(DungeonBotError::AuraOverflowError(_)) = added {
+        let reply = "Sorry, this would cause an integer overflow lol.";
         ctx.say(reply).await?;
         return Ok(())
     }
-
-    DbUser::add_points(connection, to_id, pts)?; 
+    added?;
 
     let prefix = match pts {
-        ..=-1 => format!("<:minus:1265458447541997609>"),
-        0 => format!(""),
-        1.. => format!("<:plus:1265458429502291979>")
+        ..=-1 => "<:minus:1265458447541997609>",
+        0 => "",
+        1.. => "<:plus:1265458429502291979>"
     };
     let verb = match pts {
-        ..=-1 => format!("loses"),
-        0 => format!(""),
-        1.. => format!("gains"),
+        ..=-1 => "loses",
+        0 => "",
+        1.. => "gains",
     };
-    let why = why
-        .unwrap_or("No reason given".to_string());
     let reply = format!(
         "{} | **{}** {} **{}** aura `{}`", 
         prefix,
@@ -252,11 +431,74 @@ async fn aura_add(
     Ok(())
 }
 
+/// Displays recent changes to someone's aura
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="history",
+    on_error="error_handler",
+)]
+async fn aura_history(
+    ctx: Context<'_>,
+    #[description="Member (defaults to you)"] 
+    member: Option<Member>,
+    #[description="Only show aura from this source"] 
+    source: Option<AuraSource>,
+    #[description = "Page number"] 
+    #[min=1]
+    #[max=10000]
+    page: Option<i64>,
+) -> Result<()> {
+    let page = page.unwrap_or(1);
+    let guild_id = guild_id(ctx)?;
+
+    let member = match member {
+        Some(member) => member,
+        None => ctx.author_member().await
+            .ok_or(DungeonBotError::DiscordUserNotFoundError(ctx.author().id.into()))?
+            .into_owned(),
+    };
+    let user_id: u64 = member.user.id.into();
+
+    let connection = &mut ctx.data().conn()?;
+
+    let offset = (page-1) * 10;
+    let mut fields = vec![];
+    for txn in DbTransaction::history(connection, guild_id.into(), user_id, source, 10, offset)? {
+        let title = format!("{:+} aura · {}", txn.amount, txn.source()?.name());
+
+        let mut body = format!("{} <t:{}:R>", txn.reason, txn.created_at);
+        if let Some(counterparty) = txn.counterparty {
+            write!(body, " (<@{}>)", counterparty).unwrap();
+        }
+
+        fields.push((title, body, false));
+    }
+
+    let npages = (DbTransaction::count_for(connection, guild_id.into(), user_id, source)? + 9)/10;
+    let footer = CreateEmbedFooter::new(format!("Page {}/{}", page, npages.max(1)));
+    let mut embed = CreateEmbed::new()
+        .title(format!("{}'s aura history", member.display_name()))
+        .fields(fields)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+    if let Some(source) = source {
+        embed = embed.description(format!("Only showing aura from {}", source.name()));
+    }
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
 
 #[poise::command(
     slash_command,
     guild_only,
-    subcommands("count_show", "count_set")
+    subcommands("count_show", "count_set", "count_mode", "count_consecutive", "count_expressions", "count_milestone", "count_stats", "count_leaderboard")
 )]
 pub async fn count(_: Context<'_>) -> Result<()> { Ok(()) }
 
@@ -268,9 +510,18 @@ pub async fn count(_: Context<'_>) -> Result<()> { Ok(()) }
     on_error="error_handler",
 )]
 async fn count_show(ctx: Context<'_>) -> Result<()> {
-    let ct = Counting::get_lock_ct(ctx.serenity_context()).await?;
-
-    let reply = format!("The current count is {}", ct);
+    let guild_id = guild_id(ctx)?;
+    let ct = Counting::get_lock_ct(ctx.serenity_context(), guild_id).await?;
+
+    let connection = &mut ctx.data().conn()?;
+    let reply = match Counting::get_db_mode(connection, guild_id)? {
+        CountingMode::Casual => format!("The current count is {}", ct),
+        CountingMode::Hardcore => format!(
+            "The current count is {} (hardcore, {} server saves left)",
+            ct,
+            Counting::get_db_save_pool(connection, guild_id)?
+        ),
+    };
     ctx.say(reply).await?;
 
     Ok(())
@@ -287,14 +538,17 @@ async fn count_show(ctx: Context<'_>) -> Result<()> {
 async fn count_set(
     ctx: Context<'_>,
     #[description="Number to set"] 
-    #[max=1000]
     #[min=1]
     count: u64,
 ) -> Result<()> {
 
-    let conn = &mut db_conn()?;
-    Counting::set_lock_ct(ctx.serenity_context(), count).await?;
-    Counting::set_db_ct(conn, count)?;
+    let guild_id = guild_id(ctx)?;
+
+    let conn = &mut ctx.data().conn()?;
+    Counting::set_lock_ct(ctx.serenity_context(), guild_id, count).await?;
+    Counting::set_db_ct(conn, guild_id, count)?;
+    Counting::reset_db_cycle_start(conn, guild_id)?;
+    Counting::set_db_last_message(conn, guild_id, None)?;
 
     let reply = format!("Successfully set count to {}", count);
     ctx.say(reply).await?;
@@ -302,156 +556,1294 @@ async fn count_set(
     Ok(())
 }
 
-/// Pins a message (500 aura)
+/// [JASPER ONLY] Sets what happens when someone gets the count wrong
 #[poise::command(
     slash_command,
+    owners_only,
     guild_only,
+    rename="mode",
     on_error="error_handler",
 )]
-async fn pin(
+async fn count_mode(
     ctx: Context<'_>,
-    #[description="Link to message"] 
-    msg: String,
+    #[description="Counting mode"] 
+    mode: CountingMode,
 ) -> Result<()> {
-    let user_id: u64 = ctx.author().id.into();
+    let connection = &mut ctx.data().conn()?;
+    Counting::set_db_mode(connection, guild_id(ctx)?, mode)?;
 
-    let conn = &mut db_conn()?;
-    let pts = DbUser::get_points(conn, user_id)?.unwrap();
-
-    if pts >= 500 {
-        if let Some((_, channel_id, message_id)) = parse_message_url(&msg) {
-            // TODO: Bug: check against guild_id, channel_id
-            let message = ctx.http().get_message(channel_id, message_id).await?;
-            message.pin(ctx.http()).await?;
-            ctx.reply("Success.").await?;
-            DbUser::add_points(conn, user_id, -500)?;
-        } else {
-            ctx.reply("Failure.").await?;
-        }
-    } else {
-        ctx.reply("Insufficient aura.").await?;
-    }
+    let reply = format!("Successfully set counting mode to {}", mode.name());
+    ctx.say(reply).await?;
 
     Ok(())
 }
 
-/// Unpins a message (1000 aura)
+/// [JASPER ONLY] Sets whether someone can count twice in a row
 #[poise::command(
     slash_command,
+    owners_only,
     guild_only,
+    rename="consecutive",
     on_error="error_handler",
 )]
-async fn unpin(
+async fn count_consecutive(
     ctx: Context<'_>,
-    #[description="Link to message"] 
-    msg: String,
+    #[description="Allow counting twice in a row"] 
+    allowed: bool,
 ) -> Result<()> {
-    let user_id: u64 = ctx.author().id.into();
+    let connection = &mut ctx.data().conn()?;
+    Counting::set_db_allow_consecutive(connection, guild_id(ctx)?, allowed)?;
 
-    let conn = &mut db_conn()?;
-    let pts = DbUser::get_points(conn, user_id)?.unwrap();
-
-    if pts >= 1000 {
-        if let Some((_, channel_id, message_id)) = parse_message_url(&msg) {
-            // TODO: Bug: check against guild_id
-            let message = ctx.http().get_message(channel_id, message_id).await?;
-            message.unpin(ctx.http()).await?;
-            ctx.reply("Success.").await?;
-            DbUser::add_points(conn, user_id, -1000)?;
-        } else {
-            ctx.reply("Failure.").await?;
-        }
+    let reply = if allowed {
+        "Members can now count twice in a row."
     } else {
-        ctx.reply("Insufficient aura.").await?;
-    }
+        "Members can no longer count twice in a row."
+    };
+    ctx.say(reply).await?;
 
     Ok(())
 }
 
-/// Displays this help message
+/// [JASPER ONLY] Sets whether counts can be arithmetic expressions
 #[poise::command(
     slash_command,
+    owners_only,
     guild_only,
+    rename="expressions",
     on_error="error_handler",
 )]
-pub async fn help(
+async fn count_expressions(
     ctx: Context<'_>,
-    #[description = "Specific command to show help about"] command: Option<String>,
+    #[description="Allow expressions like 6*7"] 
+    allowed: bool,
 ) -> Result<()> {
-    let config = poise::builtins::HelpConfiguration {
-        extra_text_at_bottom: "Meow!",
-        show_subcommands: true,
-        include_description: true,
-        ..Default::default()
+    let connection = &mut ctx.data().conn()?;
+    Counting::set_db_expressions(connection, guild_id(ctx)?, allowed)?;
+
+    let reply = if allowed {
+        "Counts can now be arithmetic expressions."
+    } else {
+        "Counts can no longer be arithmetic expressions."
     };
-    poise::builtins::help(ctx, command.as_deref(), config).await?;
+    ctx.say(reply).await?;
+
     Ok(())
 }
 
-async fn error_handler(framework_error: poise::FrameworkError<'_, Data, DungeonBotError>) {
-    let header = 
-        "Oh noes, an error \
-        <:flabbergasted:1250998996596555817>. \
-        Please let Jasper know about this immediately.\n";
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="milestone",
+    subcommands("count_milestone_set", "count_milestone_remove", "count_milestone_list")
+)]
+async fn count_milestone(_: Context<'_>) -> Result<()> { Ok(()) }
 
-    match framework_error {
-        FrameworkError::NotAnOwner { ctx, .. } => {
-            let reply = "Sorry, only Jasper is allowed to use this command";
-            ctx.say(reply).await
-                .expect("Unable to send error handler reply");
-        }
-        FrameworkError::Command { ref error, ctx, .. } => {
-            let mut reply = header.to_string();
+/// [JASPER ONLY] Makes a number worth an aura reward and/or a role
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="set",
+    on_error="error_handler",
+)]
+async fn count_milestone_set(
+    ctx: Context<'_>,
+    #[description="Number to count to"] 
+    #[min=1]
+    number: u64,
+    #[description="Aura for counting it (instead of the usual reward)"]
+    reward: i32,
+    #[description="Role for counting it"]
+    role: Option<Role>,
+) -> Result<()> {
+    let milestone = CountingMilestone {
+        guild_id: guild_id(ctx)?.get() as i64,
+        number: number as i64,
+        reward,
+        role_id: role.map(|role| role.id.get() as i64),
+    };
+    let connection = &mut ctx.data().conn()?;
+    milestone.save(connection)?;
 
-            reply.push_str("```\n");
-            reply.push_str("[Command Error]\n");
-            write!(reply, "{}\n", error).unwrap();
-            reply.push_str("```");
+    let reply = match milestone.role_id {
+        Some(role_id) => format!("Counting to {} is now worth {} aura and <@&{}>", number, reward, role_id),
+        None => format!("Counting to {} is now worth {} aura", number, reward),
+    };
+    ctx.say(reply).await?;
 
-            ctx.say(reply).await
-                .expect("Unable to send error handler reply");
-        }
-        FrameworkError::CommandPanic { payload, ctx, .. } => {
-            let mut reply = header.to_string();
+    Ok(())
+}
 
-            reply.push_str("```\n");
-            reply.push_str("[Command Panic]\n");
-            write!(reply, "{:?}\n", payload).unwrap();
-            reply.push_str("```");
+/// [JASPER ONLY] Makes a number an ordinary count again
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="remove",
+    on_error="error_handler",
+)]
+async fn count_milestone_remove(
+    ctx: Context<'_>,
+    #[description="Number to count to"] 
+    #[min=1]
+    number: u64,
+) -> Result<()> {
+    let connection = &mut ctx.data().conn()?;
 
-            ctx.say(reply).await
-                .expect("Unable to send error handler reply");
-        }
-        _ => {
-        }
-    }
+    let reply = if CountingMilestone::delete(connection, guild_id(ctx)?.into(), number)? > 0 {
+        format!("{} is no longer a milestone", number)
+    } else {
+        format!("{} isn't a milestone", number)
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
 }
 
-use serenity::all::GuildId;
+/// [JASPER ONLY] Displays this server's counting milestones
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="list",
+    on_error="error_handler",
+)]
+async fn count_milestone_list(ctx: Context<'_>) -> Result<()> {
+    let connection = &mut ctx.data().conn()?;
+    let guild_id = guild_id(ctx)?;
+
+    let mut reply = String::new();
+    for milestone in Counting::milestones(connection, guild_id)? {
+        write!(reply, "**{}** → {} aura", milestone.number, milestone.reward).unwrap();
+        if let Some(role_id) = milestone.role_id {
+            write!(reply, " and <@&{}>", role_id).unwrap();
+        }
+        reply.push('\n');
+    }
+    if CountingMilestone::list(connection, guild_id.get())?.is_empty() {
+        reply.push_str("*(the default, until one is set)*");
+    }
 
-/// Wrapper for the framework building
-pub fn dungeonbot_framework(guild_id: GuildId) -> poise::Framework<Data, DungeonBotError>{
+    let embed = CreateEmbed::new()
+        .title("Counting Milestones")
+        .description(reply)
+        .timestamp(Timestamp::now());
 
-    let jasper_id: UserId = env_snowflake("JASPER_ID")
-        .expect("JASPER_ID should be in environment");
+    let builder = CreateReply::default()
+        .embed(embed);
 
-    let mut owners = HashSet::new();
-    owners.insert(jasper_id);
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Displays someone's counting stats
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="stats",
+    on_error="error_handler",
+)]
+async fn count_stats(
+    ctx: Context<'_>,
+    #[description="Member (defaults to you)"] 
+    member: Option<Member>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    let member = match member {
+        Some(member) => member,
+        None => ctx.author_member().await
+            .ok_or(DungeonBotError::DiscordUserNotFoundError(ctx.author().id.into()))?
+            .into_owned(),
+    };
+
+    let connection = &mut ctx.data().conn()?;
+    let Some(stats) = CountingStats::get(connection, guild_id.into(), member.user.id.into())? else {
+        ctx.say(format!("{} hasn't counted yet.", member.display_name())).await?;
+        return Ok(())
+    };
+
+    let accuracy = 100.0 * stats.correct as f64 / (stats.correct + stats.mistakes).max(1) as f64;
+    let mut fields = vec![
+        ("Correct counts", stats.correct.to_string(), true),
+        ("Mistakes", format!("{} ({:.1}% accuracy)", stats.mistakes, accuracy), true),
+        ("Cycles finished", stats.cycles.to_string(), true),
+        ("Current run", stats.current_run.to_string(), true),
+        ("Longest run", stats.longest_run.to_string(), true),
+        ("Saves", stats.saves.to_string(), true),
+    ];
+    if let Some(last_count_at) = stats.last_count_at {
+        fields.push(("Last counted", format!("<t:{}:R>", last_count_at), true));
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("{}'s counting stats", member.display_name()))
+        .fields(fields)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Displays the best counters, and the server's counting record
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="leaderboard",
+    on_error="error_handler",
+)]
+async fn count_leaderboard(
+    ctx: Context<'_>,
+    #[description = "Page number"] 
+    #[min=1]
+    #[max=10000]
+    page: Option<i64>,
+) -> Result<()> {
+    let page = page.unwrap_or(1);
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let offset = (page-1) * 10;
+    let mut fields = vec![];
+    for (i, stats) in CountingStats::top(connection, guild_id.into(), 10, offset)?.into_iter().enumerate() {
+        let Ok(user) = UserId::new(stats.user_id as u64).to_user(&ctx.http()).await else { continue };
+        fields.push((
+            format!("{}. {}", offset + i as i64 + 1, user.name),
+            format!(
+                "{} correct · {} mistakes · longest run {}",
+                stats.correct,
+                stats.mistakes,
+                stats.longest_run
+            ),
+            false
+        ));
+    }
+
+    let mut record = format!(
+        "The server has finished **{}** counting cycles.",
+        CountingCycle::count(connection, guild_id.into())?
+    );
+    if let Some(fastest) = CountingCycle::fastest(connection, guild_id.into())? {
+        write!(
+            record,
+            "\nFastest: {} (finished by <@{}> <t:{}:R>)",
+            hms(fastest.duration().unwrap_or_default()),
+            fastest.completed_by,
+            fastest.completed_at
+        ).unwrap();
+    }
+
+    if let Some(best) = CountingRun::best(connection, guild_id.into())? {
+        write!(
+            record,
+            "\nBest hardcore run: {} (ruined by <@{}> <t:{}:R>)",
+            best.reached,
+            best.ruined_by,
+            best.ended_at
+        ).unwrap();
+    }
+
+    let npages = (CountingStats::count(connection, guild_id.into())? + 9)/10;
+    let footer = CreateEmbedFooter::new(format!("Page {}/{}", page, npages.max(1)));
+    let embed = CreateEmbed::new()
+        .title("The Friendship Dungeon Counting Leaderboard")
+        .description(record)
+        .fields(fields)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("tax_show", "tax_mode", "tax_payout", "tax_grant")
+)]
+pub async fn tax(_: Context<'_>) -> Result<()> { Ok(()) }
+
+/// Displays the treasury and its biggest contributors
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="show",
+    on_error="error_handler",
+)]
+async fn tax_show(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let user_id: u64 = ctx.author().id.into();
+    let connection = &mut ctx.data().conn()?;
+
+    let pool = Tax::get_db_pool(connection, guild_id)?;
+    let mode = Tax::get_db_mode(connection, guild_id)?;
+    let contribution = Tax::get_db_contribution(connection, guild_id, user_id)?;
+
+    let mut fields = vec![];
+    for (i, contributor) in Tax::top_contributors(connection, guild_id, 10)?.into_iter().enumerate() {
+        let name = match UserId::new(contributor.user_id as u64).to_user(&ctx.http()).await {
+            Ok(user) => user.name,
+            Err(_) => continue,
+        };
+        fields.push((
+            format!("{}. {}", i + 1, name),
+            format!("{} aura", contributor.total),
+            false
+        ));
+    }
+
+    let footer = CreateEmbedFooter::new(format!("You have paid {} aura in tax", contribution));
+    let embed = CreateEmbed::new()
+        .title("The Friendship Dungeon Treasury")
+        .description(format!("The treasury holds **{}** aura.\nPayout mode: {}", pool, mode.name()))
+        .fields(fields)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Sets how the treasury pays out
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="mode",
+    on_error="error_handler",
+)]
+async fn tax_mode(
+    ctx: Context<'_>,
+    #[description="Payout mode"] 
+    mode: TaxPayoutMode,
+) -> Result<()> {
+    let connection = &mut ctx.data().conn()?;
+    Tax::set_db_mode(connection, guild_id(ctx)?, mode)?;
+
+    let reply = format!("Successfully set payout mode to {}", mode.name());
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Pays out the treasury now
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="payout",
+    on_error="error_handler",
+)]
+async fn tax_payout(ctx: Context<'_>) -> Result<()> {
+    let payout = Tax::payout(ctx.serenity_context(), guild_id(ctx)?).await?;
+
+    let reply = payout.announcement()
+        .unwrap_or("Nothing to pay out.".to_string());
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Grants aura from the treasury to a member
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="grant",
+    on_error="error_handler",
+)]
+async fn tax_grant(
+    ctx: Context<'_>,
+    #[description="Recipient"] to: Member,
+    #[description="Amount of aura to grant"] 
+    #[min=1]
+    amount: i64,
+) -> Result<()> {
+    let connection = &mut ctx.data().conn()?;
+
+    let granted = Tax::grant(
+        connection,
+        guild_id(ctx)?,
+        to.user.id.into(),
+        ctx.author().id.into(),
+        amount
+    )?;
+
+    let reply = if granted {
+        format!("💰 Granted {} aura from the treasury to {}.", amount, to.display_name())
+    } else {
+        "The treasury doesn't have that much aura.".to_string()
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    subcommands("config_show", "config_set", "config_unset")
+)]
+pub async fn config(_: Context<'_>) -> Result<()> { Ok(()) }
+
+async fn autocomplete_config_key<'a>(
+    _ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = &'static str> + 'a {
+    ConfigKey::ALL.iter()
+        .map(ConfigKey::as_str)
+        .filter(move |key| key.contains(partial))
+}
+
+/// [JASPER ONLY] Displays this server's configuration
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="show",
+    on_error="error_handler",
+)]
+async fn config_show(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    let mut reply = String::new();
+    for key in ConfigKey::ALL {
+        let value = GuildConfig::get(ctx.serenity_context(), guild_id, *key).await?
+            .map(|value| key.display(&value))
+            .unwrap_or("*not set*".to_string());
+        writeln!(reply, "`{}`: {}", key.as_str(), value).unwrap();
+    }
+
+    let embed = CreateEmbed::new()
+        .title("DungeonBot Configuration")
+        .description(reply)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Sets a configuration value
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="set",
+    on_error="error_handler",
+)]
+async fn config_set(
+    ctx: Context<'_>,
+    #[description="Config key"]
+    #[autocomplete="autocomplete_config_key"]
+    key: String,
+    #[description="New value"]
+    value: String,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let key: ConfigKey = key.parse()?;
+
+    let value = GuildConfig::set(ctx.serenity_context(), guild_id, key, &value).await?;
+
+    let reply = format!("Successfully set `{}` to {}", key.as_str(), key.display(&value));
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Unsets a configuration value
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="unset",
+    on_error="error_handler",
+)]
+async fn config_unset(
+    ctx: Context<'_>,
+    #[description="Config key"]
+    #[autocomplete="autocomplete_config_key"]
+    key: String,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let key: ConfigKey = key.parse()?;
+
+    GuildConfig::unset(ctx.serenity_context(), guild_id, key).await?;
+
+    let reply = format!("Successfully unset `{}`", key.as_str());
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("shop_list", "shop_buy", "shop_purchases", "shop_add", "shop_remove", "shop_refund")
+)]
+pub async fn shop(_: Context<'_>) -> Result<()> { Ok(()) }
+
+async fn autocomplete_shop_item(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<String> {
+    let Some(guild_id) = ctx.guild_id() else { return vec![] };
+    let Ok(conn) = &mut ctx.data().conn() else { return vec![] };
+
+    ShopItem::list(conn, guild_id.into())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|item| item.name)
+        .filter(|name| name.contains(partial))
+        .collect()
+}
+
+/// Displays everything for sale
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="list",
+    on_error="error_handler",
+)]
+async fn shop_list(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let mut fields = vec![];
+    for item in ShopItem::list(connection, guild_id.into())? {
+        let kind = item.kind()?;
+
+        let title = format!("{} · {} aura", item.name, item.price);
+        let body = match (kind, item.role_id, item.duration) {
+            (ShopItemKind::TempRole, Some(role_id), Some(duration)) => 
+                format!("<@&{}> for {}", role_id, hms(duration)),
+            _ => kind.name().to_string(),
+        };
+
+        fields.push((title, body, false));
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title("The Friendship Dungeon Shop")
+        .footer(CreateEmbedFooter::new("Buy something with /shop buy"))
+        .timestamp(Timestamp::now());
+    if fields.is_empty() {
+        embed = embed.description("Nothing for sale.");
+    }
+    embed = embed.fields(fields);
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// Buys something from the shop
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="buy",
+    on_error="error_handler",
+)]
+async fn shop_buy(
+    ctx: Context<'_>,
+    #[description="Item to buy"]
+    #[autocomplete="autocomplete_shop_item"]
+    item: String,
+    #[description="Member to use it on"]
+    member: Option<Member>,
+    #[description="Channel to use it on"]
+    channel: Option<GuildChannel>,
+    #[description="Color, nickname, topic or message link"]
+    text: Option<String>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let user_id: u64 = ctx.author().id.into();
+    let connection = &mut ctx.data().conn()?;
+
+    let Some(item) = ShopItem::find(connection, guild_id.into(), &item)? else {
+        ctx.reply("There's no such item.").await?;
+        return Ok(())
+    };
+    let kind = item.kind()?;
+
+    let order = ShopOrder::parse(
+        kind,
+        guild_id,
+        member.map(|member| member.user.id),
+        channel.as_ref(),
+        text.as_deref()
+    );
+    let Some(order) = order else {
+        ctx.reply(kind.usage()).await?;
+        return Ok(())
+    };
+
+    DbUser::new(connection, guild_id.into(), user_id)?;
+    let purchase = match Purchase::buy(connection, guild_id.into(), user_id, &item, &order.target(guild_id)) {
+        Ok(purchase) => purchase,
+        Err(DungeonBotError::InsufficientAuraError { balance, .. }) => {
+            let reply = format!("Insufficient aura. You have {}, but {} costs {}.", balance, item.name, item.price);
+            ctx.reply(reply).await?;
+            return Ok(())
+        }
+        Err(err) => return Err(err),
+    };
+
+    // Don't charge for things that didn't happen
+    if let Err(err) = Shop::deliver(
+        ctx.serenity_context(),
+        connection,
+        guild_id,
+        ctx.author(),
+        &item,
+        &purchase,
+        &order
+    ).await {
+        Shop::refund(ctx.serenity_context(), connection, guild_id, purchase.id, None).await?;
+        return Err(err)
+    }
+
+    let reply = format!("Bought {} for {} aura. (Purchase #{})", item.name, item.price, purchase.id);
+    ctx.reply(reply).await?;
+
+    Ok(())
+}
+
+/// Displays someone's recent purchases
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="purchases",
+    on_error="error_handler",
+)]
+async fn shop_purchases(
+    ctx: Context<'_>,
+    #[description="Member (defaults to you)"] 
+    member: Option<Member>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    let member = match member {
+        Some(member) => member,
+        None => ctx.author_member().await
+            .ok_or(DungeonBotError::DiscordUserNotFoundError(ctx.author().id.into()))?
+            .into_owned(),
+    };
+
+    let connection = &mut ctx.data().conn()?;
+
+    let mut fields = vec![];
+    for purchase in Purchase::recent(connection, guild_id.into(), member.user.id.into(), 10)? {
+        let item = ShopItem::get(connection, purchase.item_id)?;
+
+        let mut title = format!("#{} · {} · {} aura", purchase.id, item.name, purchase.price);
+        if purchase.refunded {
+            title.push_str(" (refunded)");
+        }
+
+        let mut body = format!("<t:{}:R>", purchase.created_at);
+        if !purchase.target.is_empty() {
+            write!(body, " {}", purchase.target).unwrap();
+        }
+        if let (Some(expires_at), false) = (purchase.expires_at, purchase.expired) {
+            write!(body, " (runs out <t:{}:R>)", expires_at).unwrap();
+        }
+
+        fields.push((title, body, false));
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("{}'s purchases", member.display_name()))
+        .fields(fields)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Puts something up for sale
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="add",
+    on_error="error_handler",
+)]
+async fn shop_add(
+    ctx: Context<'_>,
+    #[description="Item name"]
+    name: String,
+    #[description="What buying it does"]
+    kind: ShopItemKind,
+    #[description="Price in aura"]
+    #[min=1]
+    price: i32,
+    #[description="Role given (temporary roles only)"]
+    role: Option<Role>,
+    #[description="How many hours the role lasts (temporary roles only)"]
+    #[min=1]
+    hours: Option<i64>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let name = name.trim();
+
+    let (role_id, duration) = match (kind, role, hours) {
+        (ShopItemKind::TempRole, Some(role), Some(hours)) => (Some(role.id.get()), Some(hours * 60 * 60)),
+        (ShopItemKind::TempRole, _, _) => {
+            ctx.say("Temporary roles need a `role` and `hours`.").await?;
+            return Ok(())
+        }
+        _ => (None, None),
+    };
+
+    let connection = &mut ctx.data().conn()?;
+    let item = ShopItem::add(connection, guild_id.into(), name, kind, price, role_id, duration)?;
+
+    let reply = match item {
+        Some(item) => format!("Successfully added {} ({}) for {} aura", item.name, kind.name(), item.price),
+        None => format!("There already is an item called {}", name),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Takes something off sale
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="remove",
+    on_error="error_handler",
+)]
+async fn shop_remove(
+    ctx: Context<'_>,
+    #[description="Item to remove"]
+    #[autocomplete="autocomplete_shop_item"]
+    item: String,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let reply = match ShopItem::remove(connection, guild_id.into(), &item)? {
+        0 => "There's no such item.".to_string(),
+        _ => format!("Successfully removed {}", item),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Refunds a purchase
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="refund",
+    on_error="error_handler",
+)]
+async fn shop_refund(
+    ctx: Context<'_>,
+    #[description="Purchase number"]
+    purchase: i32,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let refunded = Shop::refund(
+        ctx.serenity_context(),
+        connection,
+        guild_id,
+        purchase,
+        Some(ctx.author().id)
+    ).await?;
+
+    let reply = match refunded {
+        Some(purchase) => format!(
+            "Refunded {} aura to <@{}> for purchase #{}",
+            purchase.price,
+            purchase.user_id,
+            purchase.id
+        ),
+        None => "There's no such purchase, or it was already refunded.".to_string(),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    subcommands("reactionrole_add", "reactionrole_remove", "reactionrole_list")
+)]
+pub async fn reactionrole(_: Context<'_>) -> Result<()> { Ok(()) }
+
+/// [JASPER ONLY] Lets members react to a message to get a role
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="add",
+    on_error="error_handler",
+)]
+async fn reactionrole_add(
+    ctx: Context<'_>,
+    #[description="Link to message"] 
+    msg: String,
+    #[description="Emoji to react with"]
+    emoji: String,
+    #[description="Role to give"]
+    role: Role,
+    #[description="Aura it costs to claim (defaults to free)"]
+    #[min=0]
+    cost: Option<i32>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    // Only messages in this server
+    let link = parse_message_url(&msg)
+        .filter(|(link_guild_id, _, _)| *link_guild_id == guild_id);
+    let Some((_, channel_id, message_id)) = link else {
+        ctx.say("That's not a message in this server.").await?;
+        return Ok(())
+    };
+    let Ok(emoji) = ReactionType::try_from(emoji.trim()) else {
+        ctx.say("That's not an emoji.").await?;
+        return Ok(())
+    };
+
+    // Give members something to click on
+    ctx.http().create_reaction(channel_id, message_id, &emoji).await?;
+
+    let binding = ReactionRole {
+        message_id: message_id.get() as i64,
+        emoji: emoji.to_string(),
+        guild_id: guild_id.get() as i64,
+        channel_id: channel_id.get() as i64,
+        role_id: role.id.get() as i64,
+        cost: cost.unwrap_or(0),
+    };
+    let connection = &mut ctx.data().conn()?;
+    binding.save(connection)?;
+
+    let reply = format!("Successfully bound {} to <@&{}> ({} aura)", emoji, role.id, binding.cost);
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Stops giving a role for reacting to a message
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="remove",
+    on_error="error_handler",
+)]
+async fn reactionrole_remove(
+    ctx: Context<'_>,
+    #[description="Link to message"] 
+    msg: String,
+    #[description="Emoji to unbind"]
+    emoji: String,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    let link = parse_message_url(&msg)
+        .filter(|(link_guild_id, _, _)| *link_guild_id == guild_id);
+    let (Some((_, channel_id, message_id)), Ok(emoji)) = (link, ReactionType::try_from(emoji.trim())) else {
+        ctx.say("There's no such reaction role.").await?;
+        return Ok(())
+    };
+
+    let connection = &mut ctx.data().conn()?;
+    if ReactionRole::delete(connection, message_id.get(), &emoji.to_string())? == 0 {
+        ctx.say("There's no such reaction role.").await?;
+        return Ok(())
+    }
+
+    // Take our reaction back off, if it's still there
+    let _ = ctx.http().delete_reaction_me(channel_id, message_id, &emoji).await;
+
+    ctx.say(format!("Successfully unbound {}", emoji)).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Displays this server's reaction roles
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="list",
+    on_error="error_handler",
+)]
+async fn reactionrole_list(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let mut reply = String::new();
+    for binding in ReactionRole::list(connection, guild_id.into())? {
+        let link = MessageId::new(binding.message_id as u64)
+            .link(ChannelId::new(binding.channel_id as u64), Some(guild_id));
+        writeln!(reply, "{} → <@&{}> ({} aura) {}", binding.emoji, binding.role_id, binding.cost, link).unwrap();
+    }
+    if reply.is_empty() {
+        reply.push_str("No reaction roles yet.");
+    }
+
+    let embed = CreateEmbed::new()
+        .title("Reaction Roles")
+        .description(reply)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("season_show", "season_close", "season_schedule")
+)]
+pub async fn season(_: Context<'_>) -> Result<()> { Ok(()) }
+
+/// Displays the current season and who won the last one
+#[poise::command(
+    slash_command,
+    guild_only,
+    rename="show",
+    on_error="error_handler",
+)]
+async fn season_show(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let current = Season::current(connection, guild_id.into())?;
+    let mut reply = format!("It's season {}. See `/leaderboard season:{}` for the standings.", current, current);
+
+    if let Some(last) = Season::get(connection, guild_id.into(), current - 1)? {
+        let podium = last.standings(connection, 3, 0)?;
+        if !podium.is_empty() {
+            write!(reply, "\nSeason {} ended <t:{}:R>:", last.number, last.ended_at).unwrap();
+            for standing in podium {
+                write!(reply, "\n{}. <@{}> ({} aura earned)", standing.rank, standing.user_id, standing.earned).unwrap();
+            }
+        }
+    }
+
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Ends the season, archiving the standings and rewarding the winners
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="close",
+    on_error="error_handler",
+)]
+async fn season_close(
+    ctx: Context<'_>,
+    #[description="Percentage of everyone's aura to keep (defaults to keeping it all)"]
+    #[min=0]
+    #[max=100]
+    keep_percent: Option<i32>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let config = SeasonEnd::config(ctx.serenity_context(), guild_id).await?;
+
+    let connection = &mut ctx.data().conn()?;
+    let reply = SeasonEnd::close(ctx.http(), connection, &config, guild_id, keep_percent).await?;
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Ends the season automatically, announcing the results in this channel
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="schedule",
+    on_error="error_handler",
+)]
+async fn season_schedule(
+    ctx: Context<'_>,
+    #[description="Days from now"]
+    #[min=1]
+    days: i64,
+    #[description="Percentage of everyone's aura to keep (defaults to keeping it all)"]
+    #[min=0]
+    #[max=100]
+    keep_percent: Option<i32>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let payload = SeasonEndPayload { channel: ctx.channel_id(), keep_percent };
+    let ends_at = Timestamp::now().unix_timestamp() + days * 24 * 60 * 60;
+    let job = ScheduledJob::create(
+        connection,
+        guild_id.into(),
+        SeasonEnd::KIND,
+        &payload.to_string(),
+        Schedule::Once,
+        ends_at
+    )?;
+
+    let reply = format!(
+        "Season {} will end <t:{}:R>, and the results will be announced here. Use `/job cancel id:{}` to call it off.",
+        Season::current(connection, guild_id.into())?,
+        ends_at,
+        job.id
+    );
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    subcommands("job_list", "job_cancel", "job_resume")
+)]
+pub async fn job(_: Context<'_>) -> Result<()> { Ok(()) }
+
+/// [JASPER ONLY] Displays this server's scheduled jobs
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="list",
+    on_error="error_handler",
+)]
+async fn job_list(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    let mut reply = String::new();
+    for job in ScheduledJob::list(connection, guild_id.into())? {
+        let schedule = job.schedule()
+            .map(|schedule| schedule.describe())
+            .unwrap_or_else(|_| job.schedule.clone());
+        write!(reply, "`#{}` {} ({}), next <t:{}:R>", job.id, job.kind, schedule, job.next_run).unwrap();
+        if let Some(last_run) = job.last_run {
+            write!(reply, ", last <t:{}:R>", last_run).unwrap();
+        }
+        reply.push('\n');
+    }
+    if reply.is_empty() {
+        reply.push_str("No jobs scheduled.");
+    }
+    let cancelled = ScheduledJob::cancelled(connection, guild_id.into())?;
+    if !cancelled.is_empty() {
+        reply.push_str("\n**Cancelled**\n");
+        for job in cancelled {
+            writeln!(reply, "`#{}` {}", job.id, job.kind).unwrap();
+        }
+    }
+
+    let embed = CreateEmbed::new()
+        .title("Scheduled Jobs")
+        .description(reply)
+        .timestamp(Timestamp::now());
+
+    let builder = CreateReply::default()
+        .embed(embed);
+
+    ctx.send(builder).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Cancels a scheduled job
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="cancel",
+    on_error="error_handler",
+)]
+async fn job_cancel(
+    ctx: Context<'_>,
+    #[description="Job number, from `/job list`"]
+    id: i32,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    if !ScheduledJob::cancel(connection, guild_id.into(), id)? {
+        ctx.say("There's no such job.").await?;
+        return Ok(())
+    }
+
+    ctx.say(format!("Successfully cancelled job #{}", id)).await?;
+
+    Ok(())
+}
+
+/// [JASPER ONLY] Resumes a cancelled job
+#[poise::command(
+    slash_command,
+    owners_only,
+    guild_only,
+    rename="resume",
+    on_error="error_handler",
+)]
+async fn job_resume(
+    ctx: Context<'_>,
+    #[description="Job number, from `/job list`"]
+    id: i32,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let connection = &mut ctx.data().conn()?;
+
+    if !ScheduledJob::resume(connection, guild_id.into(), id, Timestamp::now().unix_timestamp())? {
+        ctx.say("There's no such cancelled job.").await?;
+        return Ok(())
+    }
+
+    ctx.say(format!("Successfully resumed job #{}", id)).await?;
+
+    Ok(())
+}
+
+/// Displays this help message
+#[poise::command(
+    slash_command,
+    guild_only,
+    on_error="error_handler",
+)]
+pub async fn help(
+    ctx: Context<'_>,
+    #[description = "Specific command to show help about"] command: Option<String>,
+) -> Result<()> {
+    let config = poise::builtins::HelpConfiguration {
+        extra_text_at_bottom: "Meow!",
+        show_subcommands: true,
+        include_description: true,
+        ..Default::default()
+    };
+    poise::builtins::help(ctx, command.as_deref(), config).await?;
+    Ok(())
+}
+
+async fn error_handler(framework_error: poise::FrameworkError<'_, Data, DungeonBotError>) {
+    let header = 
+        "Oh noes, an error \
+        <:flabbergasted:1250998996596555817>. \
+        Please let Jasper know about this immediately.\n";
+
+    match framework_error {
+        FrameworkError::NotAnOwner { ctx, .. } => {
+            let reply = "Sorry, only Jasper is allowed to use this command";
+            ctx.say(reply).await
+                .expect("Unable to send error handler reply");
+        }
+        FrameworkError::Command { ref error, ctx, .. } => {
+            let mut reply = header.to_string();
+
+            reply.push_str("```\n");
+            reply.push_str("[Command Error]\n");
+            writeln!(reply, "{}", error).unwrap();
+            reply.push_str("```");
+
+            ctx.say(reply).await
+                .expect("Unable to send error handler reply");
+        }
+        FrameworkError::CommandPanic { payload, ctx, .. } => {
+            let mut reply = header.to_string();
+
+            reply.push_str("```\n");
+            reply.push_str("[Command Panic]\n");
+            writeln!(reply, "{:?}", payload).unwrap();
+            reply.push_str("```");
+
+            ctx.say(reply).await
+                .expect("Unable to send error handler reply");
+        }
+        _ => {
+        }
+    }
+}
+
+/// Registers commands in guilds joined while running
+/// (the ones at startup are handled in `setup`)
+async fn event_handler(
+    ctx: &serenity::all::Context,
+    event: &FullEvent,
+    framework: poise::FrameworkContext<'_, Data, DungeonBotError>,
+) -> Result<()> {
+    if let FullEvent::GuildCreate { guild, is_new: Some(true) } = event {
+        info!(guild_id = %guild.id, "Registering commands in new guild");
+        poise::builtins::register_in_guild(ctx, &framework.options().commands, guild.id).await?;
+    }
+    Ok(())
+}
+
+/// Wrapper for the framework building
+pub fn dungeonbot_framework(pool: DbPool) -> poise::Framework<Data, DungeonBotError>{
+
+    let jasper_id: UserId = env_snowflake("JASPER_ID")
+        .expect("JASPER_ID should be in environment");
+
+    let mut owners = HashSet::new();
+    owners.insert(jasper_id);
 
     let options = poise::FrameworkOptions {
-        commands: vec![leaderboard(), aura(), count(), pin(), unpin(), help()],
+        commands: vec![leaderboard(), lastmessage(), daily(), weekly(), aura(), count(), tax(), config(), shop(), reactionrole(), season(), job(), help()],
         owners,
+        event_handler: |ctx, event, framework, _data| {
+            Box::pin(event_handler(ctx, event, framework))
+        },
         ..Default::default()
     };
 
     poise::Framework::builder()
         .options(options)
-        .setup(move |ctx, _ready, framework| {
+        .setup(move |ctx, ready, framework| {
             Box::pin(async move {
-                poise::builtins::register_in_guild(ctx, &framework.options().commands, guild_id).await?;
-                Ok(Data)
+                for guild in &ready.guilds {
+                    info!(guild_id = %guild.id, "Registering commands");
+                    poise::builtins::register_in_guild(ctx, &framework.options().commands, guild.id).await?;
+                }
+                Ok(Data { pool })
             })
         })
         .build()
 }
-
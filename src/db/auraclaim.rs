@@ -0,0 +1,222 @@
+use diesel::prelude::*;
+
+use super::schema::aura_claims;
+use super::{AuraSource, DbUser};
+
+use crate::error::{DungeonBotError, Result};
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// How often aura can be claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimPeriod {
+    Daily,
+    Weekly,
+}
+
+impl ClaimPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClaimPeriod::Daily => "daily",
+            ClaimPeriod::Weekly => "weekly",
+        }
+    }
+
+    /// Seconds between claims
+    pub fn length(&self) -> i64 {
+        match self {
+            ClaimPeriod::Daily => DAY,
+            ClaimPeriod::Weekly => 7 * DAY,
+        }
+    }
+
+    /// Seconds after a claim opens up that it still keeps the streak going
+    pub fn grace(&self) -> i64 {
+        match self {
+            ClaimPeriod::Daily => DAY,
+            ClaimPeriod::Weekly => 2 * DAY,
+        }
+    }
+
+    /// The streak past which the multiplier stops growing
+    pub fn max_multiplier(&self) -> i32 {
+        match self {
+            ClaimPeriod::Daily => 7,
+            ClaimPeriod::Weekly => 4,
+        }
+    }
+
+    /// Aura a claim is worth (before the multiplier) by default
+    pub fn default_reward(&self) -> i64 {
+        match self {
+            ClaimPeriod::Daily => 10,
+            ClaimPeriod::Weekly => 100,
+        }
+    }
+
+    pub fn source(&self) -> AuraSource {
+        match self {
+            ClaimPeriod::Daily => AuraSource::Daily,
+            ClaimPeriod::Weekly => AuraSource::Weekly,
+        }
+    }
+}
+
+/// What happened when someone tried to claim aura.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    Claimed { amount: i64, streak: i32, multiplier: i32 },
+    /// They already claimed it this period
+    TooSoon { next_claim: i64 },
+    /// It would overflow their balance
+    Overflow,
+}
+
+/// The last time a member claimed aura.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = aura_claims)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuraClaim {
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub period: String,
+    pub last_claim: i64,
+    pub streak: i32,
+}
+
+impl AuraClaim {
+    /// Gets user `uid`'s last `claim_period` claim in guild `gid`.
+    /// Returns None if they've never claimed.
+    pub fn get(conn: &mut SqliteConnection, gid: u64, uid: u64, claim_period: ClaimPeriod) -> Result<Option<Self>> {
+        use super::schema::aura_claims::dsl::*;
+
+        aura_claims
+            .find((gid as i64, uid as i64, claim_period.as_str()))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Claims user `uid`'s `claim_period` aura in guild `gid` at `now`:
+    /// `reward` times their streak (up to the period's max multiplier).
+    pub fn claim(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        uid: u64,
+        claim_period: ClaimPeriod,
+        reward: i64,
+        now: i64
+    ) -> Result<Claim> {
+        use super::schema::aura_claims::dsl::*;
+
+        conn.transaction(|conn| {
+            DbUser::new(conn, gid, uid)?;
+
+            let previous = Self::get(conn, gid, uid, claim_period)?;
+            let next_streak = match previous {
+                Some(previous) if now < previous.last_claim + claim_period.length() => {
+                    return Ok(Claim::TooSoon {
+                        next_claim: previous.last_claim + claim_period.length()
+                    })
+                }
+                Some(previous) if now <= previous.last_claim + claim_period.length() + claim_period.grace() => {
+                    previous.streak.saturating_add(1)
+                }
+                _ => 1,
+            };
+
+            let multiplier = next_streak.min(claim_period.max_multiplier());
+            let Some(amount) = reward.checked_mul(multiplier.into()) else {
+                return Ok(Claim::Overflow)
+            };
+
+            let added = DbUser::add_points(
+                conn,
+                gid,
+                uid,
+                amount,
+                claim_period.source(),
+                None,
+                &format!("{} claim ({}x streak)", claim_period.as_str(), multiplier)
+            );
+            if let Err(DungeonBotError::AuraOverflowError(_)) = added {
+                return Ok(Claim::Overflow)
+            }
+            added?;
+
+            let new_claim = AuraClaim {
+                guild_id: gid as i64,
+                user_id: uid as i64,
+                period: claim_period.as_str().to_string(),
+                last_claim: now,
+                streak: next_streak,
+            };
+            diesel::insert_into(aura_claims)
+                .values(&new_claim)
+                .on_conflict((guild_id, user_id, period))
+                .do_update()
+                .set((last_claim.eq(now), streak.eq(next_streak)))
+                .execute(conn)?;
+
+            Ok(Claim::Claimed { amount, streak: next_streak, multiplier })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GID: u64 = 1;
+    const UID: u64 = 2;
+
+    fn claim(conn: &mut SqliteConnection, now: i64) -> Claim {
+        AuraClaim::claim(conn, GID, UID, ClaimPeriod::Daily, 10, now).unwrap()
+    }
+
+    #[test]
+    fn streaks_multiply() {
+        let conn = &mut test_conn();
+
+        assert_eq!(claim(conn, 0), Claim::Claimed { amount: 10, streak: 1, multiplier: 1 });
+        assert_eq!(claim(conn, DAY - 1), Claim::TooSoon { next_claim: DAY });
+        // Late, but within the grace window
+        assert_eq!(claim(conn, 2 * DAY - 1), Claim::Claimed { amount: 20, streak: 2, multiplier: 2 });
+        assert_eq!(claim(conn, 3 * DAY), Claim::Claimed { amount: 30, streak: 3, multiplier: 3 });
+        assert_eq!(DbUser::get_points(conn, GID, UID).unwrap(), Some(60));
+
+        // Missed a whole day
+        assert_eq!(claim(conn, 5 * DAY + 1), Claim::Claimed { amount: 10, streak: 1, multiplier: 1 });
+    }
+
+    #[test]
+    fn multiplier_is_capped() {
+        let conn = &mut test_conn();
+        for day in 0..10 {
+            claim(conn, day * DAY);
+        }
+
+        assert_eq!(AuraClaim::get(conn, GID, UID, ClaimPeriod::Daily).unwrap().unwrap().streak, 10);
+        assert_eq!(claim(conn, 10 * DAY), Claim::Claimed { amount: 70, streak: 11, multiplier: 7 });
+        // Weekly claims are kept apart
+        assert!(AuraClaim::get(conn, GID, UID, ClaimPeriod::Weekly).unwrap().is_none());
+    }
+
+    #[test]
+    fn claims_do_not_overflow() {
+        let conn = &mut test_conn();
+        DbUser::new(conn, GID, UID).unwrap();
+        DbUser::add_points(conn, GID, UID, i64::MAX - 5, AuraSource::Add, None, "test").unwrap();
+
+        assert_eq!(claim(conn, 0), Claim::Overflow);
+        assert_eq!(AuraClaim::claim(conn, GID, 4, ClaimPeriod::Daily, i64::MAX, 0).unwrap(), Claim::Claimed {
+            amount: i64::MAX, streak: 1, multiplier: 1
+        });
+        // The multiplier alone overflows
+        assert_eq!(AuraClaim::claim(conn, GID, 4, ClaimPeriod::Daily, i64::MAX, DAY).unwrap(), Claim::Overflow);
+        // Nothing was claimed, so it's still there to claim
+        assert!(AuraClaim::get(conn, GID, UID, ClaimPeriod::Daily).unwrap().is_none());
+    }
+}
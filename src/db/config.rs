@@ -0,0 +1,504 @@
+//! Per-guild configuration
+//!
+//! Values are stored as text in the `guild_config` table, and cached
+//! in the serenity TypeMap so subsystems can read them on every message.
+//! Keys that used to be environment variables fall back to them
+//! in the legacy `GUILD_ID` guild until they're set.
+
+use std::collections::HashMap;
+use std::env;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use serenity::all::{GuildId, ReactionType};
+use serenity::prelude::*;
+use serenity::utils::{parse_channel_mention, parse_role_mention};
+use tokio::sync::RwLock;
+
+use super::{schema, Db};
+use super::models::ConfigVar;
+
+use crate::env_snowflake;
+use crate::error::{DungeonBotError, Result};
+
+/// Most seconds an eligible last message can be held back for,
+/// in case it gets deleted
+const MAX_DELETE_GRACE: i64 = 60;
+
+/// What a config value is expected to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigKind {
+    Channel,
+    Role,
+    Emoji,
+    Integer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigKey {
+    CountingChannel,
+    CountingRole,
+    CountingCycle,
+    CountingReward,
+    CountingPenalty,
+    CountingConsecutivePenalty,
+    CountingExpressionBonus,
+    CountingEditPenalty,
+    LastMessageChannel,
+    LastMessageRole,
+    LastMessageMinLength,
+    LastMessageCooldown,
+    LastMessagePairWindow,
+    LastMessageDeleteGrace,
+    WordLadderChannel,
+    TaxChannel,
+    StarboardChannel,
+    StarboardEmoji,
+    StarboardThreshold,
+    SeasonWinners,
+    SeasonReward,
+    SeasonRole,
+    DailyReward,
+    WeeklyReward,
+}
+
+impl ConfigKey {
+    pub const ALL: &'static [ConfigKey] = &[
+        ConfigKey::CountingChannel,
+        ConfigKey::CountingRole,
+        ConfigKey::CountingCycle,
+        ConfigKey::CountingReward,
+        ConfigKey::CountingPenalty,
+        ConfigKey::CountingConsecutivePenalty,
+        ConfigKey::CountingExpressionBonus,
+        ConfigKey::CountingEditPenalty,
+        ConfigKey::LastMessageChannel,
+        ConfigKey::LastMessageRole,
+        ConfigKey::LastMessageMinLength,
+        ConfigKey::LastMessageCooldown,
+        ConfigKey::LastMessagePairWindow,
+        ConfigKey::LastMessageDeleteGrace,
+        ConfigKey::WordLadderChannel,
+        ConfigKey::TaxChannel,
+        ConfigKey::StarboardChannel,
+        ConfigKey::StarboardEmoji,
+        ConfigKey::StarboardThreshold,
+        ConfigKey::SeasonWinners,
+        ConfigKey::SeasonReward,
+        ConfigKey::SeasonRole,
+        ConfigKey::DailyReward,
+        ConfigKey::WeeklyReward,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigKey::CountingChannel => "counting.channel",
+            ConfigKey::CountingRole => "counting.role",
+            ConfigKey::CountingCycle => "counting.cycle",
+            ConfigKey::CountingReward => "counting.reward",
+            ConfigKey::CountingPenalty => "counting.penalty",
+            ConfigKey::CountingConsecutivePenalty => "counting.consecutive_penalty",
+            ConfigKey::CountingExpressionBonus => "counting.expression_bonus",
+            ConfigKey::CountingEditPenalty => "counting.edit_penalty",
+            ConfigKey::LastMessageChannel => "lastmessage.channel",
+            ConfigKey::LastMessageRole => "lastmessage.role",
+            ConfigKey::LastMessageMinLength => "lastmessage.min_length",
+            ConfigKey::LastMessageCooldown => "lastmessage.cooldown",
+            ConfigKey::LastMessagePairWindow => "lastmessage.pair_window",
+            ConfigKey::LastMessageDeleteGrace => "lastmessage.delete_grace",
+            ConfigKey::WordLadderChannel => "wordladder.channel",
+            ConfigKey::TaxChannel => "tax.channel",
+            ConfigKey::StarboardChannel => "starboard.channel",
+            ConfigKey::StarboardEmoji => "starboard.emoji",
+            ConfigKey::StarboardThreshold => "starboard.threshold",
+            ConfigKey::SeasonWinners => "season.winners",
+            ConfigKey::SeasonReward => "season.reward",
+            ConfigKey::SeasonRole => "season.role",
+            ConfigKey::DailyReward => "daily.reward",
+            ConfigKey::WeeklyReward => "weekly.reward",
+        }
+    }
+
+    pub fn kind(&self) -> ConfigKind {
+        match self {
+            ConfigKey::CountingChannel
+                | ConfigKey::LastMessageChannel
+                | ConfigKey::WordLadderChannel
+                | ConfigKey::TaxChannel
+                | ConfigKey::StarboardChannel => ConfigKind::Channel,
+            ConfigKey::CountingRole
+                | ConfigKey::LastMessageRole
+                | ConfigKey::SeasonRole => ConfigKind::Role,
+            ConfigKey::StarboardEmoji => ConfigKind::Emoji,
+            ConfigKey::CountingCycle
+                | ConfigKey::CountingReward
+                | ConfigKey::CountingPenalty
+                | ConfigKey::CountingConsecutivePenalty
+                | ConfigKey::CountingExpressionBonus
+                | ConfigKey::CountingEditPenalty
+                | ConfigKey::LastMessageMinLength
+                | ConfigKey::LastMessageCooldown
+                | ConfigKey::LastMessagePairWindow
+                | ConfigKey::LastMessageDeleteGrace
+                | ConfigKey::StarboardThreshold
+                | ConfigKey::SeasonWinners
+                | ConfigKey::SeasonReward
+                | ConfigKey::DailyReward
+                | ConfigKey::WeeklyReward => ConfigKind::Integer,
+        }
+    }
+
+    /// The values an integer key can be set to.
+    pub fn range(&self) -> RangeInclusive<i64> {
+        match self {
+            ConfigKey::LastMessageMinLength
+                | ConfigKey::LastMessageCooldown
+                | ConfigKey::LastMessagePairWindow => 0..=i64::MAX,
+            ConfigKey::LastMessageDeleteGrace => 0..=MAX_DELETE_GRACE,
+            _ => i64::MIN..=i64::MAX,
+        }
+    }
+
+    /// The environment variable this key used to be read from.
+    pub fn env_key(&self) -> Option<&'static str> {
+        match self {
+            ConfigKey::CountingChannel => Some("COUNTING_CHANNEL_ID"),
+            ConfigKey::CountingRole => Some("COUNTING_ROLE_ID"),
+            ConfigKey::LastMessageChannel => Some("LAST_MESSAGE_CHANNEL_ID"),
+            ConfigKey::LastMessageRole => Some("LAST_MESSAGE_ROLE_ID"),
+            ConfigKey::WordLadderChannel => Some("WORDLADDER_CHANNEL_ID"),
+            ConfigKey::TaxChannel => Some("TAX_CHANNEL_ID"),
+            _ => None,
+        }
+    }
+
+    /// Checks that `value` makes sense for this key, and returns
+    /// the normalized value to save.
+    pub fn normalize(&self, value: &str) -> Result<String> {
+        let value = value.trim();
+        let invalid = || DungeonBotError::ConfigValueError {
+            key: self.as_str().to_string(),
+            value: value.to_string(),
+        };
+
+        match self.kind() {
+            ConfigKind::Channel => parse_channel_mention(value)
+                .map(|id| id.get())
+                .or_else(|| value.parse::<u64>().ok())
+                .map(|id| id.to_string())
+                .ok_or_else(invalid),
+            ConfigKind::Role => parse_role_mention(value)
+                .map(|id| id.get())
+                .or_else(|| value.parse::<u64>().ok())
+                .map(|id| id.to_string())
+                .ok_or_else(invalid),
+            ConfigKind::Emoji => ReactionType::try_from(value).ok()
+                .filter(|_| !value.contains(char::is_whitespace))
+                .map(|emoji| emoji.to_string())
+                .ok_or_else(invalid),
+            ConfigKind::Integer => value.parse::<i64>().ok()
+                .filter(|n| self.range().contains(n))
+                .map(|n| n.to_string())
+                .ok_or_else(invalid),
+        }
+    }
+
+    /// Formats a saved value for displaying in Discord.
+    pub fn display(&self, value: &str) -> String {
+        match self.kind() {
+            ConfigKind::Channel => format!("<#{}>", value),
+            ConfigKind::Role => format!("<@&{}>", value),
+            ConfigKind::Emoji
+                | ConfigKind::Integer => value.to_string(),
+        }
+    }
+}
+
+impl FromStr for ConfigKey {
+    type Err = DungeonBotError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ConfigKey::ALL.iter()
+            .find(|key| key.as_str() == s)
+            .copied()
+            .ok_or_else(|| DungeonBotError::ConfigKeyError(s.to_string()))
+    }
+}
+
+/// Config values of every guild seen so far
+type ConfigCache = Arc<RwLock<HashMap<GuildId, HashMap<ConfigKey, String>>>>;
+
+pub struct GuildConfig;
+impl TypeMapKey for GuildConfig {
+    type Value = ConfigCache;
+}
+
+impl GuildConfig {
+    pub fn data() -> ConfigCache {
+        ConfigCache::default()
+    }
+
+    async fn cache(ctx: &Context) -> Result<ConfigCache> {
+        ctx.data.read().await.get::<Self>()
+            .cloned()
+            .ok_or(DungeonBotError::TypeMapMissingKeyError("GuildConfig".to_string()))
+    }
+
+    /// Gets the value of `key` in guild `guild_id`.
+    /// Returns None if it isn't set.
+    pub async fn get(
+        ctx: &Context,
+        guild_id: GuildId,
+        key: ConfigKey
+    ) -> Result<Option<String>> {
+        let cache = Self::cache(ctx).await?;
+
+        if let Some(values) = cache.read().await.get(&guild_id) {
+            return Ok(Self::or_env(values.get(&key).cloned(), guild_id, key))
+        }
+
+        // First time seeing this guild
+        let conn = &mut Db::conn(ctx).await?;
+        let values = Self::load_db(conn, guild_id)?;
+        let value = values.get(&key).cloned();
+        cache.write().await.insert(guild_id, values);
+
+        Ok(Self::or_env(value, guild_id, key))
+    }
+
+    /// Gets the value of `key` in guild `guild_id` as a snowflake
+    /// (e.g a channel id). Returns None if it isn't set.
+    pub async fn get_snowflake<T: From<u64>>(
+        ctx: &Context,
+        guild_id: GuildId,
+        key: ConfigKey
+    ) -> Result<Option<T>> {
+        Self::get(ctx, guild_id, key).await?
+            .map(|value| value.parse::<u64>()
+                .map(T::from)
+                .map_err(|e| DungeonBotError::SnowflakeParseError {
+                    snowflake: value,
+                    source: e,
+                }))
+            .transpose()
+    }
+
+    /// Gets the value of `key` in guild `guild_id` parsed as a `T`
+    /// (e.g an integer or emoji). Returns None if it isn't set.
+    pub async fn get_as<T: FromStr>(
+        ctx: &Context,
+        guild_id: GuildId,
+        key: ConfigKey
+    ) -> Result<Option<T>> {
+        Self::get(ctx, guild_id, key).await?
+            .map(|value| value.parse::<T>()
+                .map_err(|_| DungeonBotError::ConfigValueError {
+                    key: key.as_str().to_string(),
+                    value,
+                }))
+            .transpose()
+    }
+
+    /// Like `get_snowflake`, but errors if `key` isn't set.
+    pub async fn snowflake<T: From<u64>>(
+        ctx: &Context,
+        guild_id: GuildId,
+        key: ConfigKey
+    ) -> Result<T> {
+        Self::get_snowflake(ctx, guild_id, key).await?
+            .ok_or_else(|| DungeonBotError::ConfigMissingError(key.as_str().to_string()))
+    }
+
+    /// Sets `key` to `value` in guild `guild_id`, effective immediately.
+    /// Returns the normalized value that was saved.
+    pub async fn set(
+        ctx: &Context,
+        guild_id: GuildId,
+        key: ConfigKey,
+        value: &str
+    ) -> Result<String> {
+        let value = key.normalize(value)?;
+
+        let conn = &mut Db::conn(ctx).await?;
+        Self::set_db(conn, guild_id, key, &value)?;
+
+        let values = Self::load_db(conn, guild_id)?;
+        Self::cache(ctx).await?.write().await.insert(guild_id, values);
+
+        Ok(value)
+    }
+
+    /// Removes `key` in guild `guild_id`, effective immediately.
+    pub async fn unset(ctx: &Context, guild_id: GuildId, key: ConfigKey) -> Result<()> {
+        let conn = &mut Db::conn(ctx).await?;
+        Self::unset_db(conn, guild_id, key)?;
+
+        let values = Self::load_db(conn, guild_id)?;
+        Self::cache(ctx).await?.write().await.insert(guild_id, values);
+
+        Ok(())
+    }
+
+    fn or_env(value: Option<String>, guild_id: GuildId, key: ConfigKey) -> Option<String> {
+        value.or_else(|| {
+            let legacy: GuildId = env_snowflake("GUILD_ID").ok()?;
+            if legacy != guild_id { return None }
+
+            key.env_key().and_then(|env_key| env::var(env_key).ok())
+        })
+    }
+
+    /// Loads all saved values of guild `gid`.
+    pub fn load_db(conn: &mut SqliteConnection, gid: GuildId) -> Result<HashMap<ConfigKey, String>> {
+        use schema::guild_config::dsl::*;
+
+        let vars: Vec<ConfigVar> = guild_config
+            .filter(guild_id.eq(gid.get() as i64))
+            .select(ConfigVar::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)?;
+
+        // Silently skip keys that no longer exist
+        Ok(vars.into_iter()
+            .filter_map(|var| var.key.parse::<ConfigKey>().ok()
+                .map(|config_key| (config_key, var.value)))
+            .collect())
+    }
+
+    pub fn set_db(
+        conn: &mut SqliteConnection,
+        gid: GuildId,
+        config_key: ConfigKey,
+        new_value: &str
+    ) -> Result<usize> {
+        use schema::guild_config::dsl::*;
+
+        let var = ConfigVar {
+            guild_id: gid.get() as i64,
+            key: config_key.as_str().to_string(),
+            value: new_value.to_string(),
+        };
+
+        diesel::insert_into(guild_config)
+            .values(&var)
+            .on_conflict((guild_id, key))
+            .do_update()
+            .set(value.eq(new_value))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    pub fn unset_db(
+        conn: &mut SqliteConnection,
+        gid: GuildId,
+        config_key: ConfigKey
+    ) -> Result<usize> {
+        use schema::guild_config::dsl::*;
+
+        diesel::delete(guild_config.find((gid.get() as i64, config_key.as_str())))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    #[test]
+    fn keys_round_trip() {
+        for key in ConfigKey::ALL {
+            assert_eq!(key.as_str().parse::<ConfigKey>().unwrap(), *key);
+        }
+        assert!("counting.nope".parse::<ConfigKey>().is_err());
+    }
+
+    #[test]
+    fn mentions_and_ids_are_normalized() {
+        assert_eq!(ConfigKey::CountingChannel.normalize("<#123>").unwrap(), "123");
+        assert_eq!(ConfigKey::CountingChannel.normalize(" 123 ").unwrap(), "123");
+        assert!(ConfigKey::CountingChannel.normalize("<@&123>").is_err());
+        assert!(ConfigKey::CountingChannel.normalize("general").is_err());
+
+        assert_eq!(ConfigKey::CountingRole.normalize("<@&45>").unwrap(), "45");
+        assert_eq!(ConfigKey::CountingRole.normalize("45").unwrap(), "45");
+        assert!(ConfigKey::CountingRole.normalize("<#45>").is_err());
+
+        assert_eq!(ConfigKey::CountingChannel.display("123"), "<#123>");
+        assert_eq!(ConfigKey::CountingRole.display("45"), "<@&45>");
+    }
+
+    #[test]
+    fn emoji_and_integers_are_normalized() {
+        assert_eq!(ConfigKey::StarboardEmoji.normalize("⭐").unwrap(), "⭐");
+        assert_eq!(ConfigKey::StarboardEmoji.normalize("<:star:123>").unwrap(), "<:star:123>");
+        assert!(ConfigKey::StarboardEmoji.normalize("two stars").is_err());
+        assert!(ConfigKey::StarboardEmoji.normalize("<:star>").is_err());
+
+        assert_eq!(ConfigKey::StarboardThreshold.normalize(" +3 ").unwrap(), "3");
+        assert!(ConfigKey::StarboardThreshold.normalize("3.5").is_err());
+        assert!(ConfigKey::StarboardThreshold.normalize("three").is_err());
+    }
+
+    #[test]
+    fn integers_are_range_checked() {
+        assert_eq!(ConfigKey::CountingPenalty.normalize("-5").unwrap(), "-5");
+        assert!(ConfigKey::LastMessageCooldown.normalize("-1").is_err());
+        assert!(ConfigKey::LastMessageMinLength.normalize("-1").is_err());
+        assert!(ConfigKey::LastMessagePairWindow.normalize("-1").is_err());
+        assert!(ConfigKey::LastMessageDeleteGrace.normalize("-1").is_err());
+        assert_eq!(ConfigKey::LastMessageDeleteGrace.normalize("60").unwrap(), "60");
+        assert!(ConfigKey::LastMessageDeleteGrace.normalize("61").is_err());
+    }
+
+    #[test]
+    fn saved_values_round_trip() {
+        let conn = &mut test_conn();
+        GuildConfig::set_db(conn, GUILD, ConfigKey::StarboardThreshold, "3").unwrap();
+        GuildConfig::set_db(conn, GUILD, ConfigKey::StarboardThreshold, "5").unwrap();
+        GuildConfig::set_db(conn, GuildId::new(2), ConfigKey::StarboardThreshold, "7").unwrap();
+
+        let values = GuildConfig::load_db(conn, GUILD).unwrap();
+        assert_eq!(values, HashMap::from([(ConfigKey::StarboardThreshold, "5".to_string())]));
+
+        GuildConfig::unset_db(conn, GUILD, ConfigKey::StarboardThreshold).unwrap();
+        assert!(GuildConfig::load_db(conn, GUILD).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_saved_keys_are_skipped() {
+        let conn = &mut test_conn();
+        diesel::insert_into(schema::guild_config::table)
+            .values(&ConfigVar {
+                guild_id: GUILD.get() as i64,
+                key: "counting.removed".to_string(),
+                value: "1".to_string(),
+            })
+            .execute(conn)
+            .unwrap();
+
+        assert!(GuildConfig::load_db(conn, GUILD).unwrap().is_empty());
+    }
+
+    #[test]
+    fn legacy_guild_falls_back_to_env() {
+        env::set_var("GUILD_ID", "77");
+        env::set_var("TAX_CHANNEL_ID", "123");
+
+        let legacy = GuildId::new(77);
+        assert_eq!(GuildConfig::or_env(None, legacy, ConfigKey::TaxChannel), Some("123".to_string()));
+        // Saved values win
+        assert_eq!(
+            GuildConfig::or_env(Some("456".to_string()), legacy, ConfigKey::TaxChannel),
+            Some("456".to_string())
+        );
+        // Only in the legacy guild, and only for keys that were env vars
+        assert_eq!(GuildConfig::or_env(None, GuildId::new(78), ConfigKey::TaxChannel), None);
+        assert_eq!(GuildConfig::or_env(None, legacy, ConfigKey::StarboardChannel), None);
+    }
+}
@@ -0,0 +1,67 @@
+use diesel::prelude::*;
+
+use super::schema::counting_milestones;
+
+use crate::error::{DungeonBotError, Result};
+
+/// A number worth something extra to whoever counts it.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = counting_milestones)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CountingMilestone {
+    pub guild_id: i64,
+    pub number: i64,
+    /// Aura given instead of the usual reward for a count
+    pub reward: i32,
+    pub role_id: Option<i64>,
+}
+
+impl CountingMilestone {
+    /// Saves this milestone, replacing any previous one
+    /// for the same number in the same guild.
+    pub fn save(&self, conn: &mut SqliteConnection) -> Result<usize> {
+        use super::schema::counting_milestones::dsl::*;
+
+        diesel::insert_into(counting_milestones)
+            .values(self)
+            .on_conflict((guild_id, number))
+            .do_update()
+            .set((reward.eq(self.reward), role_id.eq(self.role_id)))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Removes the milestone at `milestone` in guild `gid`.
+    /// Returns the number of milestones removed.
+    pub fn delete(conn: &mut SqliteConnection, gid: u64, milestone: u64) -> Result<usize> {
+        use super::schema::counting_milestones::dsl::*;
+
+        diesel::delete(counting_milestones.find((gid as i64, milestone as i64)))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gets the milestone at `milestone` in guild `gid`, if there is one.
+    pub fn get(conn: &mut SqliteConnection, gid: u64, milestone: u64) -> Result<Option<Self>> {
+        use super::schema::counting_milestones::dsl::*;
+
+        counting_milestones
+            .find((gid as i64, milestone as i64))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Every milestone in guild `gid`, in order.
+    pub fn list(conn: &mut SqliteConnection, gid: u64) -> Result<Vec<Self>> {
+        use super::schema::counting_milestones::dsl::*;
+
+        counting_milestones
+            .filter(guild_id.eq(gid as i64))
+            .order_by(number)
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+}
@@ -0,0 +1,356 @@
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+
+use super::schema::{counting_cycles, counting_runs, counting_stats};
+
+use crate::error::{DungeonBotError, Result};
+
+/// How a member has done at counting in a guild.
+#[derive(Debug, Clone, Default, Queryable, Selectable, Insertable)]
+#[diesel(table_name = counting_stats)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CountingStats {
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub correct: i32,
+    pub mistakes: i32,
+    /// Counting cycles they finished
+    pub cycles: i32,
+    pub current_run: i32,
+    pub longest_run: i32,
+    pub last_count_at: Option<i64>,
+    /// Mistakes they can make in hardcore mode without resetting the count
+    pub saves: i32,
+}
+
+/// A full counting cycle, from 1 to the end.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = counting_cycles)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CountingCycle {
+    pub id: i32,
+    pub guild_id: i64,
+    pub started_at: Option<i64>,
+    pub completed_at: i64,
+    pub completed_by: i64,
+}
+
+/// A hardcore count that ended in a mistake.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = counting_runs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct CountingRun {
+    pub id: i32,
+    pub guild_id: i64,
+    pub started_at: Option<i64>,
+    pub ended_at: i64,
+    /// The last correct number
+    pub reached: i64,
+    pub ruined_by: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = counting_runs)]
+struct NewCountingRun {
+    guild_id: i64,
+    started_at: Option<i64>,
+    ended_at: i64,
+    reached: i64,
+    ruined_by: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = counting_cycles)]
+struct NewCountingCycle {
+    guild_id: i64,
+    started_at: Option<i64>,
+    completed_at: i64,
+    completed_by: i64,
+}
+
+impl CountingStats {
+    /// Gets user `uid`'s stats in guild `gid`.
+    /// Returns None if they've never counted.
+    pub fn get(conn: &mut SqliteConnection, gid: u64, uid: u64) -> Result<Option<Self>> {
+        use super::schema::counting_stats::dsl::*;
+
+        counting_stats
+            .find((gid as i64, uid as i64))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Records a correct count by user `uid` in guild `gid` at `now`,
+    /// which completed a cycle if `completed_cycle`.
+    pub fn record_correct(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        uid: u64,
+        now: i64,
+        completed_cycle: bool
+    ) -> Result<usize> {
+        use super::schema::counting_stats::dsl::*;
+
+        let new_stats = CountingStats {
+            guild_id: gid as i64,
+            user_id: uid as i64,
+            correct: 1,
+            cycles: completed_cycle as i32,
+            current_run: 1,
+            longest_run: 1,
+            last_count_at: Some(now),
+            ..Default::default()
+        };
+
+        // Assignments in an update all see the old row,
+        // so the longest run has to add the 1 itself
+        diesel::insert_into(counting_stats)
+            .values(&new_stats)
+            .on_conflict((guild_id, user_id))
+            .do_update()
+            .set((
+                correct.eq(correct + 1),
+                cycles.eq(cycles + excluded(cycles)),
+                current_run.eq(current_run + 1),
+                longest_run.eq(diesel::dsl::sql::<diesel::sql_types::Integer>(
+                    "MAX(longest_run, current_run + 1)"
+                )),
+                last_count_at.eq(now),
+            ))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Records a wrong count by user `uid` in guild `gid` at `now`,
+    /// ending their run.
+    pub fn record_mistake(conn: &mut SqliteConnection, gid: u64, uid: u64, now: i64) -> Result<usize> {
+        use super::schema::counting_stats::dsl::*;
+
+        let new_stats = CountingStats {
+            guild_id: gid as i64,
+            user_id: uid as i64,
+            mistakes: 1,
+            last_count_at: Some(now),
+            ..Default::default()
+        };
+
+        diesel::insert_into(counting_stats)
+            .values(&new_stats)
+            .on_conflict((guild_id, user_id))
+            .do_update()
+            .set((
+                mistakes.eq(mistakes + 1),
+                current_run.eq(0),
+                last_count_at.eq(now),
+            ))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gives user `uid` in guild `gid` `amount` more saves
+    /// (or takes them away, down to none).
+    pub fn add_saves(conn: &mut SqliteConnection, gid: u64, uid: u64, amount: i32) -> Result<usize> {
+        use super::schema::counting_stats::dsl::*;
+
+        let new_stats = CountingStats {
+            guild_id: gid as i64,
+            user_id: uid as i64,
+            saves: amount.max(0),
+            ..Default::default()
+        };
+
+        diesel::insert_into(counting_stats)
+            .values(&new_stats)
+            .on_conflict((guild_id, user_id))
+            .do_update()
+            .set(saves.eq(diesel::dsl::sql::<diesel::sql_types::Integer>("MAX(saves + ")
+                .bind::<diesel::sql_types::Integer, _>(amount)
+                .sql(", 0)")))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Uses up one of user `uid`'s saves in guild `gid`.
+    /// Returns false if they don't have any.
+    pub fn use_save(conn: &mut SqliteConnection, gid: u64, uid: u64) -> Result<bool> {
+        use super::schema::counting_stats::dsl::*;
+
+        let updated = diesel::update(counting_stats.find((gid as i64, uid as i64)))
+            .filter(saves.gt(0))
+            .set(saves.eq(saves - 1))
+            .execute(conn)?;
+
+        Ok(updated > 0)
+    }
+
+    /// Retrieves the [off,off + lim)-th users by correct counts in guild `gid`
+    pub fn top(conn: &mut SqliteConnection, gid: u64, lim: i64, off: i64) -> Result<Vec<Self>> {
+        use super::schema::counting_stats::dsl::*;
+
+        counting_stats
+            .filter(guild_id.eq(gid as i64))
+            .order_by((correct.desc(), mistakes, user_id))
+            .limit(lim)
+            .offset(off)
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Returns the number of users who have counted in guild `gid`
+    pub fn count(conn: &mut SqliteConnection, gid: u64) -> Result<i64> {
+        use super::schema::counting_stats::dsl::*;
+
+        counting_stats
+            .filter(guild_id.eq(gid as i64))
+            .count()
+            .get_result(conn)
+            .map_err(DungeonBotError::from)
+    }
+}
+
+impl CountingCycle {
+    /// Records a cycle in guild `gid` that started at `started_at`
+    /// (if known) and was completed by user `uid` at `now`.
+    pub fn complete(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        started_at: Option<i64>,
+        now: i64,
+        uid: u64
+    ) -> Result<usize> {
+        let new_cycle = NewCountingCycle {
+            guild_id: gid as i64,
+            started_at,
+            completed_at: now,
+            completed_by: uid as i64,
+        };
+
+        diesel::insert_into(counting_cycles::table)
+            .values(&new_cycle)
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Returns the number of cycles completed in guild `gid`
+    pub fn count(conn: &mut SqliteConnection, gid: u64) -> Result<i64> {
+        use super::schema::counting_cycles::dsl::*;
+
+        counting_cycles
+            .filter(guild_id.eq(gid as i64))
+            .count()
+            .get_result(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gets the fastest cycle (of those with a known start) in guild `gid`.
+    pub fn fastest(conn: &mut SqliteConnection, gid: u64) -> Result<Option<Self>> {
+        use super::schema::counting_cycles::dsl::*;
+
+        counting_cycles
+            .filter(guild_id.eq(gid as i64))
+            .filter(started_at.is_not_null())
+            .order_by(((completed_at - started_at.assume_not_null()), id))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// How long the cycle took, if its start is known.
+    pub fn duration(&self) -> Option<i64> {
+        self.started_at.map(|started_at| self.completed_at - started_at)
+    }
+}
+
+impl CountingRun {
+    /// Records a run in guild `gid` that started at `started_at`
+    /// (if known) and was ruined by user `uid` at `now`, after
+    /// reaching `reached`.
+    pub fn record(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        started_at: Option<i64>,
+        now: i64,
+        reached: i64,
+        uid: u64
+    ) -> Result<usize> {
+        let new_run = NewCountingRun {
+            guild_id: gid as i64,
+            started_at,
+            ended_at: now,
+            reached,
+            ruined_by: uid as i64,
+        };
+
+        diesel::insert_into(counting_runs::table)
+            .values(&new_run)
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gets the run that got the furthest in guild `gid`.
+    pub fn best(conn: &mut SqliteConnection, gid: u64) -> Result<Option<Self>> {
+        use super::schema::counting_runs::dsl::*;
+
+        counting_runs
+            .filter(guild_id.eq(gid as i64))
+            .order_by((reached.desc(), id))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GID: u64 = 1;
+    const UID: u64 = 2;
+
+    #[test]
+    fn runs_end_on_mistakes() {
+        let conn = &mut test_conn();
+        for t in 0..3 {
+            CountingStats::record_correct(conn, GID, UID, t, false).unwrap();
+        }
+        CountingStats::record_mistake(conn, GID, UID, 3).unwrap();
+        CountingStats::record_correct(conn, GID, UID, 4, true).unwrap();
+
+        let stats = CountingStats::get(conn, GID, UID).unwrap().unwrap();
+        assert_eq!((stats.correct, stats.mistakes, stats.cycles), (4, 1, 1));
+        assert_eq!((stats.current_run, stats.longest_run), (1, 3));
+        assert_eq!(stats.last_count_at, Some(4));
+    }
+
+    #[test]
+    fn saves_run_out() {
+        let conn = &mut test_conn();
+        CountingStats::add_saves(conn, GID, UID, 2).unwrap();
+        CountingStats::add_saves(conn, GID, UID, -1).unwrap();
+
+        assert!(CountingStats::use_save(conn, GID, UID).unwrap());
+        assert!(!CountingStats::use_save(conn, GID, UID).unwrap());
+
+        // Can't go into debt
+        CountingStats::add_saves(conn, GID, UID, -1).unwrap();
+        assert_eq!(CountingStats::get(conn, GID, UID).unwrap().unwrap().saves, 0);
+    }
+
+    #[test]
+    fn fastest_cycle_needs_a_start() {
+        let conn = &mut test_conn();
+        CountingCycle::complete(conn, GID, None, 10, UID).unwrap();
+        CountingCycle::complete(conn, GID, Some(100), 500, UID).unwrap();
+        CountingCycle::complete(conn, GID, Some(1000), 1200, UID).unwrap();
+
+        assert_eq!(CountingCycle::count(conn, GID).unwrap(), 3);
+        let fastest = CountingCycle::fastest(conn, GID).unwrap().unwrap();
+        assert_eq!(fastest.duration(), Some(200));
+    }
+}
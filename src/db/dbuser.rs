@@ -7,144 +7,302 @@ use super::schema::users;
 #[diesel(table_name = users)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct DbUser {
+    pub guild_id: i64,
     pub id: i64,
-    pub points: i32
+    pub points: i64
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = users)]
 pub struct NewDbUser {
+    pub guild_id: i64,
     pub id: i64,
 }
 
+use super::{AuraSource, DbTransaction};
+
 use crate::error::{DungeonBotError, Result};
 
 impl DbUser {
-    /// Returns the total number of users
-    pub fn count(conn: &mut SqliteConnection) -> Result<i64> {
+    /// Returns the total number of users in guild `gid`
+    pub fn count(conn: &mut SqliteConnection, gid: u64) -> Result<i64> {
         use schema::users::dsl::*;
 
         users
+            .filter(guild_id.eq(gid as i64))
             .count()
             .get_result(conn)
             .map_err(DungeonBotError::from)
     }
 
-    /// Creates a new DbUser with id `user_id`.
+    /// Creates a new DbUser with id `user_id` in guild `gid`.
     /// Returns the created or existing DbUser.
-    pub fn new(conn: &mut SqliteConnection, user_id: u64) -> Result<Self> {
+    pub fn new(conn: &mut SqliteConnection, gid: u64, user_id: u64) -> Result<Self> {
         use schema::users::dsl::*;
+        let gid = gid as i64;
         let user_id = user_id as i64;
 
-        let new_user = NewDbUser { id: user_id };
+        let new_user = NewDbUser { guild_id: gid, id: user_id };
 
         diesel::insert_into(users)
             .values(&new_user)
-            .on_conflict(id)
+            .on_conflict((guild_id, id))
             .do_nothing()
             .execute(conn)
             .map_err(DungeonBotError::from)?;
 
         users
-            .find(user_id)
+            .find((gid, user_id))
             .select(Self::as_select())
             .first(conn)
             .map_err(DungeonBotError::from)
     }
 
-    /// Gets the DbUser `user_id`. 
+    /// Gets the DbUser `user_id` in guild `gid`.
     /// Returns None if the user is not found.
-    pub fn get(conn: &mut SqliteConnection, user_id: u64) -> Result<Option<Self>> {
+    pub fn get(conn: &mut SqliteConnection, gid: u64, user_id: u64) -> Result<Option<Self>> {
         use schema::users::dsl::*;
 
         users
-            .find(user_id as i64)
+            .find((gid as i64, user_id as i64))
             .select(Self::as_select())
             .first(conn)
             .optional()
             .map_err(DungeonBotError::from)
     }
 
-    /// Gets the DbUser `user_id`'s points.
+    /// Gets the DbUser `user_id`'s points in guild `gid`.
     /// Returns None if the user is not found.
-    pub fn get_points(conn: &mut SqliteConnection, user_id: u64) -> Result<Option<i32>> {
+    pub fn get_points(conn: &mut SqliteConnection, gid: u64, user_id: u64) -> Result<Option<i64>> {
         use schema::users::dsl::*;
 
         users
-            .find(user_id as i64)
+            .find((gid as i64, user_id as i64))
             .select(points)
             .first(conn)
             .optional()
             .map_err(DungeonBotError::from)
     }
 
-    /// Adds `pts` points to user `user_id`. 
-    /// Returns the updated number of points.
-    pub fn add_points(conn: &mut SqliteConnection, user_id: u64, pts: i32) -> Result<usize> {
+    /// Changes user `user_id`'s points in guild `gid` by `pts`, as long
+    /// as it doesn't overflow (or, if `solvent`, leave them in debt).
+    /// Returns their new balance.
+    ///
+    /// The bounds are checked in the update itself, so concurrent
+    /// changes can't get around them.
+    fn update_balance(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        user_id: u64,
+        pts: i64,
+        solvent: bool,
+    ) -> Result<i64> {
         use schema::users::dsl::*;
-        let user_id = user_id as i64;
 
-        diesel::update(users)
-            .filter(id.eq(user_id))
+        // The balances `pts` can be added to without overflowing
+        let (mut lowest, highest) = match pts {
+            0.. => (i64::MIN, i64::MAX - pts),
+            _ => (i64::MIN - pts, i64::MAX),
+        };
+        if solvent {
+            lowest = lowest.max(pts.saturating_neg());
+        }
+
+        let balance = diesel::update(users.find((gid as i64, user_id as i64)))
+            .filter(points.between(lowest, highest))
             .set(points.eq(points + pts))
-            .execute(conn)
-            .map_err(DungeonBotError::from)
+            .returning(points)
+            .get_result(conn)
+            .optional()?;
+        if let Some(balance) = balance { return Ok(balance) }
+
+        // Work out why it didn't go through
+        let balance = Self::get_points(conn, gid, user_id)?
+            .ok_or(DungeonBotError::DbUserNotFoundError(user_id))?;
+        match balance.checked_add(pts) {
+            Some(_) => Err(DungeonBotError::InsufficientAuraError {
+                user: user_id,
+                balance,
+                needed: pts.saturating_neg(),
+            }),
+            None => Err(DungeonBotError::AuraOverflowError(user_id)),
+        }
+    }
+
+    /// Adds `pts` points to user `user_id` in guild `gid`, recording
+    /// the change in the aura ledger.
+    /// Returns their new balance, or an error (changing nothing)
+    /// if they don't exist or it would overflow.
+    pub fn add_points(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        user_id: u64,
+        pts: i64,
+        source: AuraSource,
+        counterparty: Option<u64>,
+        reason: &str,
+    ) -> Result<i64> {
+        conn.transaction(|conn| {
+            let balance = Self::update_balance(conn, gid, user_id, pts, false)?;
+            DbTransaction::record(conn, gid, user_id, pts, source, counterparty, reason)?;
+
+            Ok(balance)
+        })
+    }
+
+    /// Takes `pts` points from user `user_id` in guild `gid`, recording
+    /// the change in the aura ledger, but only if they have that many.
+    /// Returns their new balance, or an error (changing nothing)
+    /// if they don't.
+    pub fn spend(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        user_id: u64,
+        pts: i64,
+        source: AuraSource,
+        counterparty: Option<u64>,
+        reason: &str,
+    ) -> Result<i64> {
+        let pts = pts.checked_neg()
+            .ok_or(DungeonBotError::AuraOverflowError(user_id))?;
+
+        conn.transaction(|conn| {
+            let balance = Self::update_balance(conn, gid, user_id, pts, true)?;
+            DbTransaction::record(conn, gid, user_id, pts, source, counterparty, reason)?;
+
+            Ok(balance)
+        })
     }
 
-    /// Transfers `pts` points from user `from_id` to user `to_id`.
+    /// Transfers `pts` points from user `from_id` to user `to_id` in guild `gid`.
+    /// Returns an error (changing nothing) if either doesn't exist, `from_id`
+    /// doesn't have that many, or it would overflow `to_id`'s balance.
     pub fn xfer_points(
         conn: &mut SqliteConnection,
-        to_id: u64, 
+        gid: u64,
+        to_id: u64,
         from_id: u64,
-        pts: i32
+        pts: i64,
+        reason: &str,
     ) -> Result<()> {
-        use schema::users::dsl::*;
+        let neg_pts = pts.checked_neg()
+            .ok_or(DungeonBotError::AuraOverflowError(from_id))?;
 
         conn.transaction(|conn| {
-            let to = users
-                .find(to_id as i64)
-                .select(Self::as_select())
-                .first(conn)
-                .optional()?;
-            if to.is_none() {
-                return Err(DungeonBotError::DbUserNotFoundError(to_id))
-            }
-
-            let from = users
-                .find(from_id as i64)
-                .select(Self::as_select())
-                .first(conn)
-                .optional()?;
-            if from.is_none() {
-                return Err(DungeonBotError::DbUserNotFoundError(from_id))
-            }
-
-
-            diesel::update(users)
-                .filter(id.eq(to_id as i64))
-                .set(points.eq(points + pts))
-                .execute(conn)?;
-
-            diesel::update(users)
-                .filter(id.eq(from_id as i64))
-                .set(points.eq(points - pts))
-                .execute(conn)?;
+            Self::update_balance(conn, gid, from_id, neg_pts, true)?;
+            Self::update_balance(conn, gid, to_id, pts, false)?;
+
+            DbTransaction::record(conn, gid, to_id, pts, AuraSource::Give, Some(from_id), reason)?;
+            DbTransaction::record(conn, gid, from_id, neg_pts, AuraSource::Give, Some(to_id), reason)?;
 
             Ok(())
         })
     }
 
-    /// Retrieves the [off,off + lim)-th users by aura
-    pub fn top(conn: &mut SqliteConnection, lim: i64, off: i64) -> Vec<Self> {
+    /// Retrieves the [off,off + lim)-th users by aura in guild `gid`
+    pub fn top(conn: &mut SqliteConnection, gid: u64, lim: i64, off: i64) -> Vec<Self> {
         use schema::users::dsl::*;
 
-        users 
+        users
+            .filter(guild_id.eq(gid as i64))
             .limit(lim)
             .offset(off)
             .order_by(points.desc())
-            .select((id, points))
+            .select(Self::as_select())
             .load(conn)
             .expect("Error loading users")
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GID: u64 = 1;
+
+    fn setup(conn: &mut SqliteConnection, uid: u64, pts: i64) {
+        DbUser::new(conn, GID, uid).unwrap();
+        DbUser::add_points(conn, GID, uid, pts, AuraSource::Add, None, "test").unwrap();
+    }
+
+    fn ledger(conn: &mut SqliteConnection, uid: u64) -> i64 {
+        DbTransaction::count_for(conn, GID, uid, None).unwrap()
+    }
+
+    #[test]
+    fn balances_are_64_bit() {
+        let conn = &mut test_conn();
+        setup(conn, 1, i32::MAX.into());
+
+        assert_eq!(
+            DbUser::add_points(conn, GID, 1, 1, AuraSource::Add, None, "test").unwrap(),
+            i64::from(i32::MAX) + 1
+        );
+    }
+
+    #[test]
+    fn overflow_changes_nothing() {
+        let conn = &mut test_conn();
+        setup(conn, 1, i64::MAX - 5);
+        setup(conn, 2, -5);
+
+        assert!(matches!(
+            DbUser::add_points(conn, GID, 1, 6, AuraSource::Add, None, "test"),
+            Err(DungeonBotError::AuraOverflowError(1))
+        ));
+        assert!(matches!(
+            DbUser::add_points(conn, GID, 2, i64::MIN, AuraSource::Add, None, "test"),
+            Err(DungeonBotError::AuraOverflowError(2))
+        ));
+        assert_eq!(DbUser::get_points(conn, GID, 1).unwrap(), Some(i64::MAX - 5));
+        assert_eq!(ledger(conn, 1), 1);
+    }
+
+    #[test]
+    fn missing_users_are_errors() {
+        let conn = &mut test_conn();
+
+        assert!(matches!(
+            DbUser::add_points(conn, GID, 1, 5, AuraSource::Add, None, "test"),
+            Err(DungeonBotError::DbUserNotFoundError(1))
+        ));
+        assert_eq!(ledger(conn, 1), 0);
+    }
+
+    #[test]
+    fn spending_needs_funds() {
+        let conn = &mut test_conn();
+        setup(conn, 1, 10);
+
+        assert_eq!(DbUser::spend(conn, GID, 1, 4, AuraSource::Shop, None, "test").unwrap(), 6);
+        assert!(matches!(
+            DbUser::spend(conn, GID, 1, 7, AuraSource::Shop, None, "test"),
+            Err(DungeonBotError::InsufficientAuraError { user: 1, balance: 6, needed: 7 })
+        ));
+        assert_eq!(DbUser::get_points(conn, GID, 1).unwrap(), Some(6));
+    }
+
+    #[test]
+    fn failed_transfers_change_nothing() {
+        let conn = &mut test_conn();
+        setup(conn, 1, 100);
+        setup(conn, 2, i64::MAX - 50);
+
+        // The sender was already debited by the time the recipient overflowed
+        assert!(matches!(
+            DbUser::xfer_points(conn, GID, 2, 1, 60, "test"),
+            Err(DungeonBotError::AuraOverflowError(2))
+        ));
+        assert!(matches!(
+            DbUser::xfer_points(conn, GID, 3, 1, 60, "test"),
+            Err(DungeonBotError::DbUserNotFoundError(3))
+        ));
+        assert_eq!(DbUser::get_points(conn, GID, 1).unwrap(), Some(100));
+        assert_eq!(ledger(conn, 1), 1);
+
+        DbUser::xfer_points(conn, GID, 2, 1, 50, "test").unwrap();
+        assert_eq!(DbUser::get_points(conn, GID, 2).unwrap(), Some(i64::MAX));
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+use std::str::FromStr;
+
+use diesel::prelude::*;
+
+use super::schema::jobs;
+
+use crate::error::{DungeonBotError, Result};
+use crate::hms;
+
+const DAY: i64 = 24 * 60 * 60;
+const WEEK: i64 = 7 * DAY;
+
+/// Unix time 0 was a Thursday; this is the Monday after
+const FIRST_MONDAY: i64 = 4 * DAY;
+
+/// Most missed runs of a job that are made up for after downtime
+const MAX_CATCH_UP: i64 = 3;
+
+/// When a job runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Just the one time
+    Once,
+    /// Every so many seconds
+    Every(i64),
+    /// Every day, so many seconds after midnight (UTC)
+    Daily(i64),
+    /// Every week, so many seconds after Monday midnight (UTC)
+    Weekly(i64),
+}
+
+impl Schedule {
+    /// Seconds between runs, if there's more than one.
+    pub fn period(&self) -> Option<i64> {
+        match self {
+            Schedule::Once => None,
+            Schedule::Every(period) => Some(*period),
+            Schedule::Daily(_) => Some(DAY),
+            Schedule::Weekly(_) => Some(WEEK),
+        }
+    }
+
+    /// The first run strictly after `after`.
+    pub fn next(&self, after: i64) -> Option<i64> {
+        let (period, phase) = match self {
+            Schedule::Once => return None,
+            Schedule::Every(period) => return Some(after + period),
+            Schedule::Daily(at) => (DAY, *at),
+            Schedule::Weekly(at) => (WEEK, FIRST_MONDAY + at),
+        };
+
+        Some(after - (after - phase).rem_euclid(period) + period)
+    }
+
+    /// When to run next, at `now`, after the run that was due at `due`.
+    ///
+    /// Missed runs are caught up on one at a time, but only the
+    /// last `MAX_CATCH_UP` of them.
+    pub fn after(&self, due: i64, now: i64) -> Option<i64> {
+        let period = self.period()?;
+        let next = self.next(due)?;
+        if next > now { return Some(next) }
+
+        let missed = (now - next) / period + 1;
+        Some(next + (missed - MAX_CATCH_UP).max(0) * period)
+    }
+
+    /// Describes the schedule for displaying in Discord.
+    pub fn describe(&self) -> String {
+        const WEEKDAYS: [&str; 7] = [
+            "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday",
+        ];
+
+        match self {
+            Schedule::Once => "once".to_string(),
+            Schedule::Every(period) => format!("every {}", hms(*period)),
+            Schedule::Daily(at) => format!("daily at {} UTC", hms(*at)),
+            Schedule::Weekly(at) => format!(
+                "weekly on {} at {} UTC",
+                WEEKDAYS[(at / DAY) as usize % 7],
+                hms(at % DAY)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Once => f.write_str("once"),
+            Schedule::Every(period) => write!(f, "every {}", period),
+            Schedule::Daily(at) => write!(f, "daily {}", at),
+            Schedule::Weekly(at) => write!(f, "weekly {}", at),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = DungeonBotError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || DungeonBotError::Other(format!("Invalid job schedule `{}`", s));
+
+        let (kind, seconds) = s.split_once(' ').unwrap_or((s, ""));
+        let seconds = || seconds.parse::<i64>().map_err(|_| invalid());
+
+        match kind {
+            "once" => Ok(Schedule::Once),
+            "every" => Some(seconds()?).filter(|&period| period > 0)
+                .map(Schedule::Every)
+                .ok_or_else(invalid),
+            "daily" => Some(seconds()?).filter(|at| (0..DAY).contains(at))
+                .map(Schedule::Daily)
+                .ok_or_else(invalid),
+            "weekly" => Some(seconds()?).filter(|at| (0..WEEK).contains(at))
+                .map(Schedule::Weekly)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A job waiting to be run by the scheduler.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ScheduledJob {
+    pub id: i32,
+    pub guild_id: i64,
+    pub kind: String,
+    pub payload: String,
+    pub schedule: String,
+    pub next_run: i64,
+    pub last_run: Option<i64>,
+    pub cancelled: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = jobs)]
+struct NewScheduledJob<'a> {
+    guild_id: i64,
+    kind: &'a str,
+    payload: &'a str,
+    schedule: String,
+    next_run: i64,
+}
+
+impl ScheduledJob {
+    /// Schedules a `job_kind` job in guild `gid`, first running at `first_run`.
+    pub fn create(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        job_kind: &str,
+        job_payload: &str,
+        job_schedule: Schedule,
+        first_run: i64
+    ) -> Result<Self> {
+        diesel::insert_into(jobs::table)
+            .values(&NewScheduledJob {
+                guild_id: gid as i64,
+                kind: job_kind,
+                payload: job_payload,
+                schedule: job_schedule.to_string(),
+                next_run: first_run,
+            })
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Schedules a `job_kind` job in guild `gid` unless there ever was one
+    /// (so cancelling it sticks, until it's resumed). Returns true if it was scheduled.
+    pub fn ensure(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        job_kind: &str,
+        job_schedule: Schedule,
+        first_run: i64
+    ) -> Result<bool> {
+        use super::schema::jobs::dsl::*;
+
+        conn.transaction(|conn| {
+            let existing: i64 = jobs
+                .filter(guild_id.eq(gid as i64))
+                .filter(kind.eq(job_kind))
+                .count()
+                .get_result(conn)?;
+            if existing > 0 { return Ok(false) }
+
+            Self::create(conn, gid, job_kind, "", job_schedule, first_run)?;
+            Ok(true)
+        })
+    }
+
+    /// Retrieves the jobs (of every guild) due to run by `now`.
+    pub fn due(conn: &mut SqliteConnection, now: i64) -> Result<Vec<Self>> {
+        use super::schema::jobs::dsl::*;
+
+        jobs
+            .filter(cancelled.eq(false))
+            .filter(next_run.le(now))
+            .order_by((next_run, id))
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Retrieves the jobs still scheduled in guild `gid`, soonest first.
+    pub fn list(conn: &mut SqliteConnection, gid: u64) -> Result<Vec<Self>> {
+        use super::schema::jobs::dsl::*;
+
+        jobs
+            .filter(guild_id.eq(gid as i64))
+            .filter(cancelled.eq(false))
+            .order_by((next_run, id))
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Cancels job `job_id` in guild `gid`.
+    /// Returns false if there's no such job left to cancel.
+    pub fn cancel(conn: &mut SqliteConnection, gid: u64, job_id: i32) -> Result<bool> {
+        use super::schema::jobs::dsl::*;
+
+        let updated = diesel::update(jobs.find(job_id))
+            .filter(guild_id.eq(gid as i64))
+            .filter(cancelled.eq(false))
+            .set(cancelled.eq(true))
+            .execute(conn)?;
+
+        Ok(updated > 0)
+    }
+
+    /// Retrieves the cancelled jobs of guild `gid`.
+    pub fn cancelled(conn: &mut SqliteConnection, gid: u64) -> Result<Vec<Self>> {
+        use super::schema::jobs::dsl::*;
+
+        jobs
+            .filter(guild_id.eq(gid as i64))
+            .filter(cancelled.eq(true))
+            .order_by(id)
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Resumes cancelled job `job_id` in guild `gid`, picking its schedule
+    /// back up after `now` rather than making up for the runs it missed.
+    /// Returns false if there's no such job left to resume.
+    pub fn resume(conn: &mut SqliteConnection, gid: u64, job_id: i32, now: i64) -> Result<bool> {
+        use super::schema::jobs::dsl::*;
+
+        conn.transaction(|conn| {
+            let Some(job) = jobs.find(job_id)
+                .filter(guild_id.eq(gid as i64))
+                .filter(cancelled.eq(true))
+                .select(Self::as_select())
+                .first(conn)
+                .optional()? else { return Ok(false) };
+
+            let resume_at = job.schedule()?.next(now).unwrap_or(now).max(job.next_run);
+            diesel::update(jobs.find(job_id))
+                .set((cancelled.eq(false), next_run.eq(resume_at)))
+                .execute(conn)?;
+
+            Ok(true)
+        })
+    }
+
+    /// Records that this job ran at `ran_at`, and schedules its next run
+    /// (or removes it, if it was the last).
+    pub fn reschedule(&self, conn: &mut SqliteConnection, ran_at: i64, next: Option<i64>) -> Result<usize> {
+        use super::schema::jobs::dsl::*;
+
+        match next {
+            Some(next) => diesel::update(jobs.find(self.id))
+                .set((next_run.eq(next), last_run.eq(ran_at)))
+                .execute(conn),
+            None => diesel::delete(jobs.find(self.id))
+                .execute(conn),
+        }.map_err(DungeonBotError::from)
+    }
+
+    /// When this job runs.
+    pub fn schedule(&self) -> Result<Schedule> {
+        self.schedule.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GID: u64 = 1;
+
+    #[test]
+    fn schedules_round_trip() {
+        for schedule in [Schedule::Once, Schedule::Every(90), Schedule::Daily(3600), Schedule::Weekly(DAY + 60)] {
+            assert_eq!(schedule.to_string().parse::<Schedule>().unwrap(), schedule);
+        }
+        assert!("every 0".parse::<Schedule>().is_err());
+        assert!("daily 86400".parse::<Schedule>().is_err());
+        assert!("hourly".parse::<Schedule>().is_err());
+        assert_eq!(Schedule::Weekly(DAY + 60).describe(), "weekly on Tuesday at 00:01:00 UTC");
+    }
+
+    #[test]
+    fn runs_line_up() {
+        // Noon UTC
+        let daily = Schedule::Daily(12 * 60 * 60);
+        assert_eq!(daily.next(10 * DAY), Some(10 * DAY + 12 * 60 * 60));
+        assert_eq!(daily.next(10 * DAY + 12 * 60 * 60), Some(11 * DAY + 12 * 60 * 60));
+
+        // 1970-01-12 was a Monday
+        let weekly = Schedule::Weekly(0);
+        assert_eq!(weekly.next(5 * DAY), Some(11 * DAY));
+
+        assert_eq!(Schedule::Every(60).next(1000), Some(1060));
+        assert_eq!(Schedule::Once.next(1000), None);
+    }
+
+    #[test]
+    fn catch_up_is_capped() {
+        let every = Schedule::Every(100);
+
+        // On time
+        assert_eq!(every.after(1000, 1000), Some(1100));
+        // Missed the run at 1100; make it up right away
+        assert_eq!(every.after(1000, 1150), Some(1100));
+        // Missed ten runs; only make up the last three
+        assert_eq!(every.after(1000, 2050), Some(1800));
+        assert_eq!(Schedule::Once.after(1000, 2050), None);
+    }
+
+    #[test]
+    fn job_lifecycle() {
+        let conn = &mut test_conn();
+        assert!(ScheduledJob::ensure(conn, GID, "test", Schedule::Every(60), 100).unwrap());
+        assert!(!ScheduledJob::ensure(conn, GID, "test", Schedule::Every(60), 100).unwrap());
+        let once = ScheduledJob::create(conn, GID, "other", "hi", Schedule::Once, 50).unwrap();
+
+        let due = ScheduledJob::due(conn, 100).unwrap();
+        assert_eq!(due.iter().map(|job| job.kind.as_str()).collect::<Vec<_>>(), vec!["other", "test"]);
+
+        // One-off jobs are gone once they've run
+        once.reschedule(conn, 100, None).unwrap();
+        due[1].reschedule(conn, 100, Some(160)).unwrap();
+        assert!(ScheduledJob::due(conn, 100).unwrap().is_empty());
+        assert_eq!(ScheduledJob::list(conn, GID).unwrap()[0].last_run, Some(100));
+
+        assert!(ScheduledJob::cancel(conn, GID, due[1].id).unwrap());
+        assert!(!ScheduledJob::cancel(conn, GID, due[1].id).unwrap());
+        assert!(ScheduledJob::list(conn, GID).unwrap().is_empty());
+        // Cancelling sticks
+        assert!(!ScheduledJob::ensure(conn, GID, "test", Schedule::Every(60), 100).unwrap());
+        assert_eq!(ScheduledJob::cancelled(conn, GID).unwrap().len(), 1);
+
+        // ...until it's resumed, without making up for missed runs
+        assert!(ScheduledJob::resume(conn, GID, due[1].id, 1000).unwrap());
+        assert!(!ScheduledJob::resume(conn, GID, due[1].id, 1000).unwrap());
+        assert!(ScheduledJob::cancelled(conn, GID).unwrap().is_empty());
+        assert_eq!(ScheduledJob::list(conn, GID).unwrap()[0].next_run, 1060);
+    }
+}
@@ -0,0 +1,138 @@
+use diesel::prelude::*;
+
+use super::schema::last_message_streaks;
+
+use crate::error::{DungeonBotError, Result};
+
+/// A finished last message streak.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = last_message_streaks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct LastMessageStreak {
+    pub id: i32,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub started_at: i64,
+    pub ended_at: i64,
+    /// Whoever took the last message from them, if anyone did
+    pub broken_by: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = last_message_streaks)]
+struct NewLastMessageStreak {
+    guild_id: i64,
+    user_id: i64,
+    started_at: i64,
+    ended_at: i64,
+    broken_by: Option<i64>,
+}
+
+impl LastMessageStreak {
+    /// Records a streak by user `uid` in guild `gid` from `started_at`
+    /// to `ended_at`, broken by user `broken_by` (if anyone).
+    pub fn record(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        uid: u64,
+        started_at: i64,
+        ended_at: i64,
+        broken_by: Option<u64>
+    ) -> Result<usize> {
+        let new_streak = NewLastMessageStreak {
+            guild_id: gid as i64,
+            user_id: uid as i64,
+            started_at,
+            ended_at,
+            broken_by: broken_by.map(|uid| uid as i64),
+        };
+
+        diesel::insert_into(last_message_streaks::table)
+            .values(&new_streak)
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Retrieves the `lim` longest streaks in guild `gid`.
+    pub fn top(conn: &mut SqliteConnection, gid: u64, lim: i64) -> Result<Vec<Self>> {
+        use super::schema::last_message_streaks::dsl::*;
+
+        last_message_streaks
+            .filter(guild_id.eq(gid as i64))
+            .order_by(((ended_at - started_at).desc(), id))
+            .limit(lim)
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// When user `uid` last lost the last message in guild `gid`.
+    /// Returns None if they've never had it.
+    pub fn last_held(conn: &mut SqliteConnection, gid: u64, uid: u64) -> Result<Option<i64>> {
+        use super::schema::last_message_streaks::dsl::*;
+
+        last_message_streaks
+            .filter(guild_id.eq(gid as i64))
+            .filter(user_id.eq(uid as i64))
+            .select(diesel::dsl::max(ended_at))
+            .first(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Returns the number of times users `a` and `b` took the last
+    /// message from each other in guild `gid` since `since`.
+    pub fn trades(conn: &mut SqliteConnection, gid: u64, a: u64, b: u64, since: i64) -> Result<i64> {
+        use super::schema::last_message_streaks::dsl::*;
+
+        let (a, b) = (a as i64, b as i64);
+        last_message_streaks
+            .filter(guild_id.eq(gid as i64))
+            .filter(ended_at.ge(since))
+            .filter(user_id.eq(a).and(broken_by.eq(b))
+                .or(user_id.eq(b).and(broken_by.eq(a))))
+            .count()
+            .get_result(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// How long the streak lasted.
+    pub fn duration(&self) -> i64 {
+        self.ended_at - self.started_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GID: u64 = 1;
+
+    #[test]
+    fn longest_streaks_first() {
+        let conn = &mut test_conn();
+        LastMessageStreak::record(conn, GID, 2, 0, 600, Some(3)).unwrap();
+        LastMessageStreak::record(conn, GID, 3, 600, 4200, None).unwrap();
+        LastMessageStreak::record(conn, GID, 2, 5000, 6000, Some(3)).unwrap();
+        LastMessageStreak::record(conn, 9, 2, 0, 99999, None).unwrap();
+
+        let top = LastMessageStreak::top(conn, GID, 2).unwrap();
+        assert_eq!(top.iter().map(LastMessageStreak::duration).collect::<Vec<_>>(), vec![3600, 1000]);
+        assert_eq!(top[0].broken_by, None);
+        assert_eq!(top[1].broken_by, Some(3));
+    }
+
+    #[test]
+    fn trades_go_both_ways() {
+        let conn = &mut test_conn();
+        LastMessageStreak::record(conn, GID, 2, 0, 10, Some(3)).unwrap();
+        LastMessageStreak::record(conn, GID, 3, 10, 20, Some(2)).unwrap();
+        LastMessageStreak::record(conn, GID, 2, 20, 30, Some(4)).unwrap();
+        LastMessageStreak::record(conn, GID, 4, 30, 40, None).unwrap();
+
+        assert_eq!(LastMessageStreak::trades(conn, GID, 2, 3, 0).unwrap(), 2);
+        assert_eq!(LastMessageStreak::trades(conn, GID, 3, 2, 15).unwrap(), 1);
+        assert_eq!(LastMessageStreak::last_held(conn, GID, 2).unwrap(), Some(30));
+        assert_eq!(LastMessageStreak::last_held(conn, GID, 5).unwrap(), None);
+    }
+}
@@ -1,6 +1,9 @@
 use diesel::backend::Backend;
+use diesel::prelude::*;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
+use super::schema::{seasons, state, tax_contributions, transactions, users};
+
 use crate::error::{DungeonBotError, Result};
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
@@ -8,7 +11,37 @@ const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 pub fn run_migrations<DB: Backend>(conn: &mut impl MigrationHarness<DB>) -> Result<()> {
 
     conn.run_pending_migrations(MIGRATIONS)
-        .map_err(|e| DungeonBotError::MigrationError(e))?;
+        .map_err(DungeonBotError::MigrationError)?;
 
     Ok(())
 }
+
+/// Moves rows saved before multi-guild support (which all
+/// belong to guild 0) into guild `gid`.
+pub fn adopt_legacy_guild(conn: &mut SqliteConnection, gid: u64) -> Result<()> {
+    let gid = gid as i64;
+
+    conn.transaction(|conn| {
+        diesel::update(users::table.filter(users::guild_id.eq(0)))
+            .set(users::guild_id.eq(gid))
+            .execute(conn)?;
+
+        diesel::update(state::table.filter(state::guild_id.eq(0)))
+            .set(state::guild_id.eq(gid))
+            .execute(conn)?;
+
+        diesel::update(tax_contributions::table.filter(tax_contributions::guild_id.eq(0)))
+            .set(tax_contributions::guild_id.eq(gid))
+            .execute(conn)?;
+
+        diesel::update(transactions::table.filter(transactions::guild_id.eq(0)))
+            .set(transactions::guild_id.eq(gid))
+            .execute(conn)?;
+
+        diesel::update(seasons::table.filter(seasons::guild_id.eq(0)))
+            .set(seasons::guild_id.eq(gid))
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
@@ -2,24 +2,45 @@ pub mod models;
 pub mod schema;
 
 mod migrations;
+mod pool;
+mod auraclaim;
+mod config;
+mod countingmilestone;
+mod countingstats;
 mod dbuser;
+mod job;
+mod lastmessagestreak;
+mod reactionrole;
+mod season;
+mod shop;
+mod starboard;
+mod state;
+mod transaction;
 
-pub use migrations::run_migrations;
+pub use migrations::{adopt_legacy_guild, run_migrations};
+pub use pool::*;
+pub use auraclaim::*;
 pub use dbuser::*;
+pub use config::*;
+pub use countingmilestone::*;
+pub use countingstats::*;
+pub use job::*;
+pub use lastmessagestreak::*;
+pub use reactionrole::*;
+pub use season::*;
+pub use shop::*;
+pub use starboard::*;
+pub use transaction::*;
 
-use dotenvy::dotenv;
+#[cfg(test)]
+use diesel::{prelude::*, sqlite::SqliteConnection};
 
-use diesel::sqlite::SqliteConnection;
-use diesel::prelude::*;
-
-use crate::env_str;
-use crate::error::{DungeonBotError, Result};
-
-/// Creates a connection to the current Dungeon database.
-pub fn db_conn() -> Result<SqliteConnection> {
-    dotenv().ok();
-
-    let database_url = env_str("DATABASE_URL")?;
-    SqliteConnection::establish(&database_url)
-        .map_err(DungeonBotError::from)
+/// Creates a fresh in-memory database with every migration applied.
+#[cfg(test)]
+pub fn test_conn() -> SqliteConnection {
+    let mut conn = SqliteConnection::establish(":memory:")
+        .expect("Unable to open in-memory database");
+    run_migrations(&mut conn)
+        .expect("Unable to run migrations");
+    conn
 }
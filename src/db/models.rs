@@ -6,13 +6,15 @@ use crate::schema::users;
 #[diesel(table_name = users)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct User {
+    pub guild_id: i64,
     pub id: i64,
-    pub points: i32
+    pub points: i64
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = users)]
 pub struct NewUser {
+    pub guild_id: i64,
     pub id: i64,
 }
 
@@ -22,7 +24,30 @@ use crate::schema::state;
 #[diesel(table_name = state)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct StateVar {
+    pub guild_id: i64,
     pub key: String,
     pub value: String,
 }
 
+
+use crate::schema::tax_contributions;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = tax_contributions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TaxContribution {
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub total: i64,
+}
+
+use crate::schema::guild_config;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = guild_config)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ConfigVar {
+    pub guild_id: i64,
+    pub key: String,
+    pub value: String,
+}
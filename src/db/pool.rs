@@ -0,0 +1,62 @@
+//! Pooled connections to the Dungeon database
+//!
+//! The pool is created once in `main`, and shared through both the
+//! serenity TypeMap (for subsystems) and the poise `Data` (for commands).
+
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection};
+use diesel::sqlite::SqliteConnection;
+use serenity::prelude::*;
+
+use crate::env_str;
+use crate::error::{DungeonBotError, Result};
+
+pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+pub type DbConn = PooledConnection<ConnectionManager<SqliteConnection>>;
+
+/// How long a connection waits on another one's write lock
+/// before giving up with `database is locked`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Creates a pool of connections to the current Dungeon database.
+pub fn db_pool() -> Result<DbPool> {
+    let database_url = env_str("DATABASE_URL")?;
+
+    Pool::builder()
+        .connection_customizer(Box::new(SqlitePragmas))
+        .build(ConnectionManager::new(database_url))
+        .map_err(DungeonBotError::from)
+}
+
+/// Sets up every new connection for concurrent use: WAL lets readers
+/// and the writer work at the same time, and the busy timeout makes
+/// writers wait on each other instead of erroring.
+#[derive(Debug)]
+struct SqlitePragmas;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SqlitePragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> core::result::Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&format!(
+            "PRAGMA journal_mode = WAL; PRAGMA busy_timeout = {};",
+            BUSY_TIMEOUT.as_millis()
+        )).map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+pub struct Db;
+impl TypeMapKey for Db {
+    type Value = DbPool;
+}
+
+impl Db {
+    /// Gets a connection from the pool in the serenity TypeMap.
+    pub async fn conn(ctx: &Context) -> Result<DbConn> {
+        let pool = ctx.data.read().await.get::<Self>()
+            .cloned()
+            .ok_or(DungeonBotError::TypeMapMissingKeyError("Db".to_string()))?;
+
+        pool.get().map_err(DungeonBotError::from)
+    }
+}
@@ -0,0 +1,123 @@
+use diesel::prelude::*;
+
+use super::schema::reaction_roles;
+
+use crate::error::{DungeonBotError, Result};
+
+/// An emoji on a message that members react with to get a role.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = reaction_roles)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ReactionRole {
+    pub message_id: i64,
+    pub emoji: String,
+    pub guild_id: i64,
+    pub channel_id: i64,
+    pub role_id: i64,
+    /// Aura it costs to claim the role
+    pub cost: i32,
+}
+
+impl ReactionRole {
+    /// Saves this binding, replacing any previous one
+    /// for the same emoji on the same message.
+    pub fn save(&self, conn: &mut SqliteConnection) -> Result<usize> {
+        use super::schema::reaction_roles::dsl::*;
+
+        diesel::insert_into(reaction_roles)
+            .values(self)
+            .on_conflict((message_id, emoji))
+            .do_update()
+            .set((role_id.eq(self.role_id), cost.eq(self.cost)))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Removes the binding for `role_emoji` on message `mid`.
+    /// Returns the number of bindings removed.
+    pub fn delete(conn: &mut SqliteConnection, mid: u64, role_emoji: &str) -> Result<usize> {
+        use super::schema::reaction_roles::dsl::*;
+
+        diesel::delete(reaction_roles.find((mid as i64, role_emoji)))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Every binding on message `mid`.
+    pub fn for_message(conn: &mut SqliteConnection, mid: u64) -> Result<Vec<Self>> {
+        use super::schema::reaction_roles::dsl::*;
+
+        reaction_roles
+            .filter(message_id.eq(mid as i64))
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Every binding in guild `gid`.
+    pub fn list(conn: &mut SqliteConnection, gid: u64) -> Result<Vec<Self>> {
+        use super::schema::reaction_roles::dsl::*;
+
+        reaction_roles
+            .filter(guild_id.eq(gid as i64))
+            .order_by((channel_id, message_id))
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Records that user `uid` got role `rid` in guild `gid` by reacting.
+    pub fn grant(conn: &mut SqliteConnection, gid: u64, uid: u64, rid: u64) -> Result<usize> {
+        use super::schema::reaction_role_grants::dsl::*;
+
+        diesel::insert_into(reaction_role_grants)
+            .values((guild_id.eq(gid as i64), user_id.eq(uid as i64), role_id.eq(rid as i64)))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Checks if user `uid` got role `rid` in guild `gid` by reacting.
+    pub fn granted(conn: &mut SqliteConnection, gid: u64, uid: u64, rid: u64) -> Result<bool> {
+        use super::schema::reaction_role_grants::dsl::*;
+
+        let grants: i64 = reaction_role_grants
+            .find((gid as i64, uid as i64, rid as i64))
+            .count()
+            .get_result(conn)?;
+
+        Ok(grants > 0)
+    }
+
+    /// Forgets that user `uid` got role `rid` in guild `gid` by reacting.
+    /// Returns false if they didn't get it that way.
+    pub fn revoke(conn: &mut SqliteConnection, gid: u64, uid: u64, rid: u64) -> Result<bool> {
+        use super::schema::reaction_role_grants::dsl::*;
+
+        let deleted = diesel::delete(reaction_role_grants.find((gid as i64, uid as i64, rid as i64)))
+            .execute(conn)?;
+
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    #[test]
+    fn only_granted_roles_are_revoked() {
+        let conn = &mut test_conn();
+        ReactionRole::grant(conn, 1, 2, 3).unwrap();
+        ReactionRole::grant(conn, 1, 2, 3).unwrap();
+        assert!(ReactionRole::granted(conn, 1, 2, 3).unwrap());
+        assert!(!ReactionRole::granted(conn, 1, 2, 4).unwrap());
+
+        assert!(!ReactionRole::revoke(conn, 1, 2, 4).unwrap());
+        assert!(!ReactionRole::revoke(conn, 9, 2, 3).unwrap());
+        assert!(ReactionRole::revoke(conn, 1, 2, 3).unwrap());
+        assert!(!ReactionRole::revoke(conn, 1, 2, 3).unwrap());
+        assert!(!ReactionRole::granted(conn, 1, 2, 3).unwrap());
+    }
+}
@@ -1,20 +1,228 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    state (key) {
+    aura_claims (guild_id, user_id, period) {
+        guild_id -> BigInt,
+        user_id -> BigInt,
+        period -> Text,
+        last_claim -> BigInt,
+        streak -> Integer,
+    }
+}
+
+diesel::table! {
+    counting_cycles (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        started_at -> Nullable<BigInt>,
+        completed_at -> BigInt,
+        completed_by -> BigInt,
+    }
+}
+
+diesel::table! {
+    counting_milestones (guild_id, number) {
+        guild_id -> BigInt,
+        number -> BigInt,
+        reward -> Integer,
+        role_id -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    counting_runs (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        started_at -> Nullable<BigInt>,
+        ended_at -> BigInt,
+        reached -> BigInt,
+        ruined_by -> BigInt,
+    }
+}
+
+diesel::table! {
+    counting_stats (guild_id, user_id) {
+        guild_id -> BigInt,
+        user_id -> BigInt,
+        correct -> Integer,
+        mistakes -> Integer,
+        cycles -> Integer,
+        current_run -> Integer,
+        longest_run -> Integer,
+        last_count_at -> Nullable<BigInt>,
+        saves -> Integer,
+    }
+}
+
+diesel::table! {
+    guild_config (guild_id, key) {
+        guild_id -> BigInt,
         key -> Text,
         value -> Text,
     }
 }
 
 diesel::table! {
-    users (id) {
+    jobs (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        kind -> Text,
+        payload -> Text,
+        schedule -> Text,
+        next_run -> BigInt,
+        last_run -> Nullable<BigInt>,
+        cancelled -> Bool,
+    }
+}
+
+diesel::table! {
+    last_message_streaks (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        user_id -> BigInt,
+        started_at -> BigInt,
+        ended_at -> BigInt,
+        broken_by -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    purchases (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        item_id -> Integer,
+        user_id -> BigInt,
+        price -> Integer,
+        target -> Text,
+        created_at -> BigInt,
+        role_id -> Nullable<BigInt>,
+        expires_at -> Nullable<BigInt>,
+        expired -> Bool,
+        refunded -> Bool,
+    }
+}
+
+diesel::table! {
+    reaction_role_grants (guild_id, user_id, role_id) {
+        guild_id -> BigInt,
+        user_id -> BigInt,
+        role_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    reaction_roles (message_id, emoji) {
+        message_id -> BigInt,
+        emoji -> Text,
+        guild_id -> BigInt,
+        channel_id -> BigInt,
+        role_id -> BigInt,
+        cost -> Integer,
+    }
+}
+
+diesel::table! {
+    season_standings (season_id, user_id) {
+        season_id -> Integer,
+        user_id -> BigInt,
+        rank -> Integer,
+        earned -> BigInt,
+        points -> BigInt,
+    }
+}
+
+diesel::table! {
+    seasons (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        number -> Integer,
+        ended_at -> BigInt,
+        last_transaction_id -> Integer,
+    }
+}
+
+diesel::table! {
+    shop_items (id) {
+        id -> Integer,
+        guild_id -> BigInt,
+        name -> Text,
+        kind -> Text,
+        price -> Integer,
+        role_id -> Nullable<BigInt>,
+        duration -> Nullable<BigInt>,
+        enabled -> Bool,
+    }
+}
+
+diesel::table! {
+    starboard (message_id) {
+        message_id -> BigInt,
+        guild_id -> BigInt,
+        channel_id -> BigInt,
+        author_id -> BigInt,
+        stars -> Integer,
+        repost_id -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    state (guild_id, key) {
+        guild_id -> BigInt,
+        key -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    tax_contributions (guild_id, user_id) {
+        guild_id -> BigInt,
+        user_id -> BigInt,
+        total -> BigInt,
+    }
+}
+
+diesel::table! {
+    transactions (id) {
+        id -> Integer,
+        user_id -> BigInt,
+        counterparty -> Nullable<BigInt>,
+        source -> Text,
+        amount -> BigInt,
+        reason -> Text,
+        created_at -> BigInt,
+        guild_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    users (guild_id, id) {
+        guild_id -> BigInt,
         id -> BigInt,
-        points -> Integer,
+        points -> BigInt,
     }
 }
 
+diesel::joinable!(purchases -> shop_items (item_id));
+diesel::joinable!(season_standings -> seasons (season_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    aura_claims,
+    counting_cycles,
+    counting_milestones,
+    counting_runs,
+    counting_stats,
+    guild_config,
+    jobs,
+    last_message_streaks,
+    purchases,
+    reaction_role_grants,
+    reaction_roles,
+    season_standings,
+    seasons,
+    shop_items,
+    starboard,
     state,
+    tax_contributions,
+    transactions,
     users,
 );
@@ -0,0 +1,381 @@
+use diesel::dsl;
+use diesel::prelude::*;
+
+use super::schema::{season_standings, seasons, transactions, users};
+use super::{AuraSource, DbUser};
+
+use crate::error::{DungeonBotError, Result};
+use crate::unix_now;
+
+/// Sources that don't count towards a season's earnings:
+/// gifts just move aura around, season rewards and resets
+/// happen between seasons, and the rest is spending (or
+/// refunds of it), which shouldn't cost anyone their rank.
+/// Tax is only counted when it's paid out, not collected.
+const NOT_EARNED: [AuraSource; 6] = [
+    AuraSource::Give,
+    AuraSource::Season,
+    AuraSource::Shop,
+    AuraSource::ReactionRole,
+    AuraSource::Pin,
+    AuraSource::Unpin,
+];
+
+/// A season that has been closed.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = seasons)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Season {
+    pub id: i32,
+    pub guild_id: i64,
+    pub number: i32,
+    pub ended_at: i64,
+    pub last_transaction_id: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = seasons)]
+struct NewSeason {
+    guild_id: i64,
+    number: i32,
+    ended_at: i64,
+    last_transaction_id: i32,
+}
+
+/// Where a member finished in a closed season.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = season_standings)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct SeasonStanding {
+    pub season_id: i32,
+    pub user_id: i64,
+    pub rank: i32,
+    /// Aura earned during the season
+    pub earned: i64,
+    /// Balance when the season closed, before any reset
+    pub points: i64,
+}
+
+impl Season {
+    /// The number of the season currently running in guild `gid`.
+    pub fn current(conn: &mut SqliteConnection, gid: u64) -> Result<i32> {
+        use super::schema::seasons::dsl::*;
+
+        let last: Option<i32> = seasons
+            .filter(guild_id.eq(gid as i64))
+            .select(dsl::max(number))
+            .first(conn)?;
+
+        Ok(last.unwrap_or(0) + 1)
+    }
+
+    /// Gets closed season `season_number` in guild `gid`.
+    /// Returns None if it hasn't been closed (or doesn't exist).
+    pub fn get(conn: &mut SqliteConnection, gid: u64, season_number: i32) -> Result<Option<Self>> {
+        use super::schema::seasons::dsl::*;
+
+        seasons
+            .filter(guild_id.eq(gid as i64))
+            .filter(number.eq(season_number))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// The last ledger entry of the previous season in guild `gid`;
+    /// everything after it belongs to the current one.
+    fn current_start(conn: &mut SqliteConnection, gid: u64) -> Result<i32> {
+        use super::schema::seasons::dsl::*;
+
+        let start: Option<i32> = seasons
+            .filter(guild_id.eq(gid as i64))
+            .select(dsl::max(last_transaction_id))
+            .first(conn)?;
+
+        Ok(start.unwrap_or(0))
+    }
+
+    /// Retrieves the [off,off + lim)-th users by aura earned so far
+    /// this season in guild `gid`.
+    pub fn earnings(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        lim: i64,
+        off: i64,
+    ) -> Result<Vec<(i64, i64)>> {
+        let start = Self::current_start(conn, gid)?;
+        Self::earned_between(conn, gid, start, None, lim, off)
+    }
+
+    /// Returns the number of users who have earned (or lost) aura
+    /// this season in guild `gid`.
+    pub fn count_earners(conn: &mut SqliteConnection, gid: u64) -> Result<i64> {
+        use super::schema::transactions::dsl::*;
+
+        let start = Self::current_start(conn, gid)?;
+
+        transactions
+            .filter(guild_id.eq(gid as i64))
+            .filter(id.gt(start))
+            .filter(source.ne_all(NOT_EARNED.map(|s| s.as_str())))
+            .filter(source.ne(AuraSource::Tax.as_str()).or(amount.gt(0)))
+            .select(dsl::count_distinct(user_id))
+            .first(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Aura earned per user in guild `gid` by ledger entries after
+    /// `after` (and up to `until`), best first.
+    fn earned_between(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        after: i32,
+        until: Option<i32>,
+        lim: i64,
+        off: i64,
+    ) -> Result<Vec<(i64, i64)>> {
+        use super::schema::transactions::dsl::*;
+
+        // Diesel would sum a BIGINT column as NUMERIC, but SQLite
+        // sums integers exactly (or fails if it overflows)
+        let total = dsl::sql::<diesel::sql_types::Nullable<diesel::sql_types::BigInt>>("SUM(amount)");
+
+        let mut query = transactions
+            .filter(guild_id.eq(gid as i64))
+            .filter(id.gt(after))
+            .filter(source.ne_all(NOT_EARNED.map(|s| s.as_str())))
+            .filter(source.ne(AuraSource::Tax.as_str()).or(amount.gt(0)))
+            .group_by(user_id)
+            .select((user_id, total.clone()))
+            .order_by((total.desc(), user_id))
+            .limit(lim)
+            .offset(off)
+            .into_boxed();
+        if let Some(until) = until {
+            query = query.filter(id.le(until));
+        }
+
+        let earned: Vec<(i64, Option<i64>)> = query.load(conn)?;
+
+        Ok(earned.into_iter()
+            .map(|(uid, earned)| (uid, earned.unwrap_or(0)))
+            .collect())
+    }
+
+    /// Closes the current season in guild `gid`, archiving everyone's
+    /// standings and paying `reward` aura to each of the top `winners`.
+    /// If `keep_percent` is given, balances are then cut down to that
+    /// percentage of what they were.
+    ///
+    /// Returns the closed season and its winners.
+    pub fn close(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        winners: i64,
+        reward: i64,
+        keep_percent: Option<i32>,
+    ) -> Result<(Self, Vec<SeasonStanding>)> {
+        conn.transaction(|conn| {
+            let number = Self::current(conn, gid)?;
+            let start = Self::current_start(conn, gid)?;
+
+            let last_transaction_id: Option<i32> = transactions::table
+                .select(dsl::max(transactions::id))
+                .first(conn)?;
+            let last_transaction_id = last_transaction_id.unwrap_or(0);
+
+            let season: Self = diesel::insert_into(seasons::table)
+                .values(&NewSeason {
+                    guild_id: gid as i64,
+                    number,
+                    ended_at: unix_now(),
+                    last_transaction_id,
+                })
+                .returning(Self::as_returning())
+                .get_result(conn)?;
+
+            let earned = Self::earned_between(conn, gid, start, Some(last_transaction_id), -1, 0)?;
+            let standings: Vec<SeasonStanding> = earned.into_iter()
+                .enumerate()
+                .map(|(i, (uid, earned))| -> Result<SeasonStanding> {
+                    let points = DbUser::get_points(conn, gid, uid as u64)?.unwrap_or(0);
+                    Ok(SeasonStanding {
+                        season_id: season.id,
+                        user_id: uid,
+                        rank: i as i32 + 1,
+                        earned,
+                        points,
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            diesel::insert_into(season_standings::table)
+                .values(&standings)
+                .execute(conn)?;
+
+            // Only actual earners get a reward
+            let winners: Vec<SeasonStanding> = standings.into_iter()
+                .filter(|standing| standing.earned > 0)
+                .take(winners.max(0) as usize)
+                .collect();
+
+            if reward != 0 {
+                for winner in &winners {
+                    DbUser::add_points(
+                        conn,
+                        gid,
+                        winner.user_id as u64,
+                        reward,
+                        AuraSource::Season,
+                        None,
+                        &format!("Finished #{} in season {}", winner.rank, number)
+                    )?;
+                }
+            }
+
+            if let Some(keep_percent) = keep_percent {
+                Self::soft_reset(conn, gid, number, keep_percent)?;
+            }
+
+            Ok((season, winners))
+        })
+    }
+
+    /// Cuts every balance in guild `gid` down to `keep_percent` percent.
+    fn soft_reset(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        number: i32,
+        keep_percent: i32,
+    ) -> Result<()> {
+        let balances: Vec<(i64, i64)> = users::table
+            .filter(users::guild_id.eq(gid as i64))
+            .filter(users::points.ne(0))
+            .select((users::id, users::points))
+            .load(conn)?;
+
+        let reason = format!("Season {} reset ({}% kept)", number, keep_percent);
+        for (uid, points) in balances {
+            let kept = i64::try_from(i128::from(points) * i128::from(keep_percent) / 100)
+                .map_err(|_| DungeonBotError::AuraOverflowError(uid as u64))?;
+            let delta = kept - points;
+            if delta == 0 { continue }
+
+            DbUser::add_points(conn, gid, uid as u64, delta, AuraSource::Season, None, &reason)?;
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the [off,off + lim)-th finishers of this season.
+    pub fn standings(&self, conn: &mut SqliteConnection, lim: i64, off: i64) -> Result<Vec<SeasonStanding>> {
+        use super::schema::season_standings::dsl::*;
+
+        season_standings
+            .filter(season_id.eq(self.id))
+            .order_by(rank)
+            .limit(lim)
+            .offset(off)
+            .select(SeasonStanding::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Returns the number of members who placed in this season.
+    pub fn count_standings(&self, conn: &mut SqliteConnection) -> Result<i64> {
+        use super::schema::season_standings::dsl::*;
+
+        season_standings
+            .filter(season_id.eq(self.id))
+            .count()
+            .get_result(conn)
+            .map_err(DungeonBotError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    const GID: u64 = 1;
+
+    fn earn(conn: &mut SqliteConnection, uid: u64, pts: i64) {
+        DbUser::new(conn, GID, uid).unwrap();
+        DbUser::add_points(conn, GID, uid, pts, AuraSource::Counting, None, "test").unwrap();
+    }
+
+    #[test]
+    fn earnings_only_count_this_season() {
+        let conn = &mut test_conn();
+        earn(conn, 1, 100);
+        earn(conn, 2, 50);
+
+        Season::close(conn, GID, 0, 0, None).unwrap();
+        earn(conn, 2, 20);
+
+        assert_eq!(Season::current(conn, GID).unwrap(), 2);
+        assert_eq!(Season::earnings(conn, GID, 10, 0).unwrap(), vec![(2, 20)]);
+        assert_eq!(Season::count_earners(conn, GID).unwrap(), 1);
+    }
+
+    #[test]
+    fn gifts_are_not_earnings() {
+        let conn = &mut test_conn();
+        earn(conn, 1, 100);
+        DbUser::new(conn, GID, 2).unwrap();
+        DbUser::xfer_points(conn, GID, 2, 1, 60, "gift").unwrap();
+
+        assert_eq!(Season::earnings(conn, GID, 10, 0).unwrap(), vec![(1, 100)]);
+    }
+
+    #[test]
+    fn spending_is_not_negative_earnings() {
+        let conn = &mut test_conn();
+        earn(conn, 1, 100);
+        for (source, pts) in [(AuraSource::Shop, -50), (AuraSource::Pin, -10), (AuraSource::Tax, -5)] {
+            DbUser::add_points(conn, GID, 1, pts, source, None, "spent").unwrap();
+        }
+        DbUser::new(conn, GID, 2).unwrap();
+        DbUser::add_points(conn, GID, 2, 30, AuraSource::Tax, None, "payout").unwrap();
+
+        assert_eq!(Season::earnings(conn, GID, 10, 0).unwrap(), vec![(1, 100), (2, 30)]);
+        assert_eq!(Season::count_earners(conn, GID).unwrap(), 2);
+    }
+
+    #[test]
+    fn closing_archives_and_rewards() {
+        let conn = &mut test_conn();
+        earn(conn, 1, 30);
+        earn(conn, 2, 100);
+        earn(conn, 3, 60);
+
+        let (season, winners) = Season::close(conn, GID, 2, 25, None).unwrap();
+
+        assert_eq!(season.number, 1);
+        assert_eq!(winners.iter().map(|w| w.user_id).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(DbUser::get_points(conn, GID, 2).unwrap(), Some(125));
+        assert_eq!(DbUser::get_points(conn, GID, 1).unwrap(), Some(30));
+
+        let standings = season.standings(conn, 10, 0).unwrap();
+        assert_eq!(standings.len(), 3);
+        assert_eq!((standings[2].user_id, standings[2].rank, standings[2].earned), (1, 3, 30));
+        // Rewards belong to neither season
+        assert!(Season::earnings(conn, GID, 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn soft_reset_keeps_a_share() {
+        let conn = &mut test_conn();
+        earn(conn, 1, 100);
+        earn(conn, 2, -30);
+
+        Season::close(conn, GID, 0, 0, Some(25)).unwrap();
+
+        assert_eq!(DbUser::get_points(conn, GID, 1).unwrap(), Some(25));
+        assert_eq!(DbUser::get_points(conn, GID, 2).unwrap(), Some(-7));
+        let season = Season::get(conn, GID, 1).unwrap().unwrap();
+        assert_eq!(season.standings(conn, 10, 0).unwrap()[0].points, 100);
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt;
+use std::str::FromStr;
+
+use diesel::prelude::*;
+
+use super::schema::{purchases, shop_items};
+use super::{AuraSource, DbUser};
+
+use crate::error::{DungeonBotError, Result};
+use crate::unix_now;
+
+/// What buying a shop item does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum ShopItemKind {
+    /// A personal role in the color of your choice
+    #[name = "Role color"]
+    RoleColor,
+    /// Changes another member's nickname
+    #[name = "Nickname"]
+    Nickname,
+    /// Changes a channel's topic
+    #[name = "Channel topic"]
+    Topic,
+    #[name = "Pin"]
+    Pin,
+    #[name = "Unpin"]
+    Unpin,
+    /// A role that is taken away again after a while
+    #[name = "Temporary role"]
+    TempRole,
+    /// Keeps your hardcore counting mistakes from resetting the count
+    #[name = "Counting save"]
+    CountingSave,
+    /// A counting save anyone in the server can use up
+    #[name = "Server counting save"]
+    ServerSave,
+}
+
+impl ShopItemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShopItemKind::RoleColor => "rolecolor",
+            ShopItemKind::Nickname => "nickname",
+            ShopItemKind::Topic => "topic",
+            ShopItemKind::Pin => "pin",
+            ShopItemKind::Unpin => "unpin",
+            ShopItemKind::TempRole => "temprole",
+            ShopItemKind::CountingSave => "countingsave",
+            ShopItemKind::ServerSave => "serversave",
+        }
+    }
+
+    /// Which `/shop buy` options this kind of item needs.
+    pub fn usage(&self) -> &'static str {
+        match self {
+            ShopItemKind::RoleColor => "Give a hex color (like `#ff8800`) as `text`.",
+            ShopItemKind::Nickname => "Give a `member` and their new nickname as `text`.",
+            ShopItemKind::Topic => "Give a `channel` and its new topic as `text`.",
+            ShopItemKind::Pin
+                | ShopItemKind::Unpin => "Give a link to a message in this server as `text`.",
+            ShopItemKind::TempRole
+                | ShopItemKind::CountingSave
+                | ShopItemKind::ServerSave => "No options needed.",
+        }
+    }
+}
+
+impl fmt::Display for ShopItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ShopItemKind {
+    type Err = DungeonBotError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rolecolor" => Ok(ShopItemKind::RoleColor),
+            "nickname" => Ok(ShopItemKind::Nickname),
+            "topic" => Ok(ShopItemKind::Topic),
+            "pin" => Ok(ShopItemKind::Pin),
+            "unpin" => Ok(ShopItemKind::Unpin),
+            "temprole" => Ok(ShopItemKind::TempRole),
+            "countingsave" => Ok(ShopItemKind::CountingSave),
+            "serversave" => Ok(ShopItemKind::ServerSave),
+            _ => Err(DungeonBotError::Other(format!("Unknown shop item kind `{}`", s))),
+        }
+    }
+}
+
+/// Items every guild's shop starts out with, as (name, kind, price).
+/// Pinning and unpinning used to be their own commands.
+const DEFAULT_ITEMS: [(&str, ShopItemKind, i32); 2] = [
+    ("pin", ShopItemKind::Pin, 500),
+    ("unpin", ShopItemKind::Unpin, 1000),
+];
+
+/// Something for sale in a guild's shop.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = shop_items)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ShopItem {
+    pub id: i32,
+    pub guild_id: i64,
+    pub name: String,
+    pub kind: String,
+    pub price: i32,
+    /// The role given by temporary role items
+    pub role_id: Option<i64>,
+    /// How long temporary role items last, in seconds
+    pub duration: Option<i64>,
+    pub enabled: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = shop_items)]
+pub struct NewShopItem<'a> {
+    pub guild_id: i64,
+    pub name: &'a str,
+    pub kind: &'a str,
+    pub price: i32,
+    pub role_id: Option<i64>,
+    pub duration: Option<i64>,
+}
+
+impl ShopItem {
+    /// Puts a new item up for sale in guild `gid`.
+    /// Returns None if there already is one called `item_name`.
+    pub fn add(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        item_name: &str,
+        item_kind: ShopItemKind,
+        item_price: i32,
+        item_role_id: Option<u64>,
+        item_duration: Option<i64>,
+    ) -> Result<Option<Self>> {
+        use super::schema::shop_items::dsl::*;
+
+        let new_item = NewShopItem {
+            guild_id: gid as i64,
+            name: item_name,
+            kind: item_kind.as_str(),
+            price: item_price,
+            role_id: item_role_id.map(|r| r as i64),
+            duration: item_duration,
+        };
+
+        conn.transaction(|conn| {
+            if Self::find(conn, gid, item_name)?.is_some() {
+                return Ok(None)
+            }
+
+            diesel::insert_into(shop_items)
+                .values(&new_item)
+                .returning(Self::as_returning())
+                .get_result(conn)
+                .map(Some)
+                .map_err(DungeonBotError::from)
+        })
+    }
+
+    /// Puts the default items up for sale in guild `gid`, skipping any
+    /// it has ever had (so taking them off sale sticks).
+    /// Returns the number of items added.
+    pub fn add_defaults(conn: &mut SqliteConnection, gid: u64) -> Result<usize> {
+        use super::schema::shop_items::dsl::*;
+
+        conn.transaction(|conn| {
+            let mut added = 0;
+            for (item_name, item_kind, item_price) in DEFAULT_ITEMS {
+                let existing: i64 = shop_items
+                    .filter(guild_id.eq(gid as i64))
+                    .filter(name.eq(item_name))
+                    .count()
+                    .get_result(conn)?;
+                if existing > 0 { continue }
+
+                Self::add(conn, gid, item_name, item_kind, item_price, None, None)?;
+                added += 1;
+            }
+
+            Ok(added)
+        })
+    }
+
+    /// Takes item `item_name` off sale in guild `gid`.
+    /// Returns the number of items removed.
+    pub fn remove(conn: &mut SqliteConnection, gid: u64, item_name: &str) -> Result<usize> {
+        use super::schema::shop_items::dsl::*;
+
+        diesel::update(shop_items)
+            .filter(guild_id.eq(gid as i64))
+            .filter(name.eq(item_name))
+            .filter(enabled.eq(true))
+            .set(enabled.eq(false))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gets the item called `item_name` for sale in guild `gid`.
+    pub fn find(conn: &mut SqliteConnection, gid: u64, item_name: &str) -> Result<Option<Self>> {
+        use super::schema::shop_items::dsl::*;
+
+        shop_items
+            .filter(guild_id.eq(gid as i64))
+            .filter(name.eq(item_name))
+            .filter(enabled.eq(true))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gets item `item_id`, even if it's no longer for sale.
+    pub fn get(conn: &mut SqliteConnection, item_id: i32) -> Result<Self> {
+        use super::schema::shop_items::dsl::*;
+
+        shop_items
+            .find(item_id)
+            .select(Self::as_select())
+            .first(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Every item for sale in guild `gid`, cheapest first.
+    pub fn list(conn: &mut SqliteConnection, gid: u64) -> Result<Vec<Self>> {
+        use super::schema::shop_items::dsl::*;
+
+        shop_items
+            .filter(guild_id.eq(gid as i64))
+            .filter(enabled.eq(true))
+            .order_by((price.asc(), name.asc()))
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    pub fn kind(&self) -> Result<ShopItemKind> {
+        self.kind.parse()
+    }
+}
+
+/// A record of someone buying a shop item.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = purchases)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Purchase {
+    pub id: i32,
+    pub guild_id: i64,
+    pub item_id: i32,
+    pub user_id: i64,
+    pub price: i32,
+    /// What the item was used on, for displaying
+    pub target: String,
+    pub created_at: i64,
+    /// The role given, for role color and temporary role items
+    pub role_id: Option<i64>,
+    /// When a temporary role is taken away again
+    pub expires_at: Option<i64>,
+    pub expired: bool,
+    pub refunded: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = purchases)]
+pub struct NewPurchase<'a> {
+    pub guild_id: i64,
+    pub item_id: i32,
+    pub user_id: i64,
+    pub price: i32,
+    pub target: &'a str,
+    pub created_at: i64,
+}
+
+impl Purchase {
+    /// Has user `uid` in guild `gid` pay for `item`, and records the purchase.
+    /// Returns an error (and changes nothing) if they can't afford it.
+    pub fn buy(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        uid: u64,
+        item: &ShopItem,
+        purchase_target: &str,
+    ) -> Result<Self> {
+        use super::schema::purchases::dsl::*;
+
+        conn.transaction(|conn| {
+            DbUser::spend(
+                conn,
+                gid,
+                uid,
+                item.price.into(),
+                AuraSource::Shop,
+                None,
+                &format!("Bought {}", item.name)
+            )?;
+
+            let new_purchase = NewPurchase {
+                guild_id: gid as i64,
+                item_id: item.id,
+                user_id: uid as i64,
+                price: item.price,
+                target: purchase_target,
+                created_at: unix_now(),
+            };
+
+            diesel::insert_into(purchases)
+                .values(&new_purchase)
+                .returning(Self::as_returning())
+                .get_result(conn)
+                .map_err(DungeonBotError::from)
+        })
+    }
+
+    /// Gives purchase `purchase_id`'s aura back to whoever bought it.
+    /// Returns None if there is no such purchase in guild `gid`,
+    /// or it was already refunded.
+    pub fn refund(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        purchase_id: i32,
+        refunded_by: Option<u64>,
+    ) -> Result<Option<Self>> {
+        use super::schema::purchases::dsl::*;
+
+        conn.transaction(|conn| {
+            let Some(purchase) = purchases
+                .filter(guild_id.eq(gid as i64))
+                .filter(id.eq(purchase_id))
+                .filter(refunded.eq(false))
+                .select(Self::as_select())
+                .first(conn)
+                .optional()? else { return Ok(None) };
+
+            diesel::update(purchases.find(purchase_id))
+                .set(refunded.eq(true))
+                .execute(conn)?;
+
+            DbUser::add_points(
+                conn,
+                gid,
+                purchase.user_id as u64,
+                purchase.price.into(),
+                AuraSource::Shop,
+                refunded_by,
+                &format!("Refund for purchase #{}", purchase.id)
+            )?;
+
+            Ok(Some(purchase))
+        })
+    }
+
+    /// Saves the role purchase `purchase_id` gave, and when
+    /// it should be taken away, if ever.
+    pub fn set_role(
+        conn: &mut SqliteConnection,
+        purchase_id: i32,
+        purchase_role_id: u64,
+        purchase_expires_at: Option<i64>,
+    ) -> Result<usize> {
+        use super::schema::purchases::dsl::*;
+
+        diesel::update(purchases.find(purchase_id))
+            .set((
+                role_id.eq(purchase_role_id as i64),
+                expires_at.eq(purchase_expires_at),
+            ))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Marks purchase `purchase_id`'s temporary role as taken away.
+    pub fn set_expired(conn: &mut SqliteConnection, purchase_id: i32) -> Result<usize> {
+        use super::schema::purchases::dsl::*;
+
+        diesel::update(purchases.find(purchase_id))
+            .set(expired.eq(true))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Every temporary role in guild `gid` due to be taken away at `now`.
+    pub fn due(conn: &mut SqliteConnection, gid: u64, now: i64) -> Result<Vec<Self>> {
+        use super::schema::purchases::dsl::*;
+
+        purchases
+            .filter(guild_id.eq(gid as i64))
+            .filter(expires_at.le(now))
+            .filter(expired.eq(false))
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// The personal color role user `uid` in guild `gid` last bought, if any.
+    pub fn color_role(conn: &mut SqliteConnection, gid: u64, uid: u64) -> Result<Option<u64>> {
+        purchases::table
+            .inner_join(shop_items::table)
+            .filter(purchases::guild_id.eq(gid as i64))
+            .filter(purchases::user_id.eq(uid as i64))
+            .filter(purchases::role_id.is_not_null())
+            .filter(shop_items::kind.eq(ShopItemKind::RoleColor.as_str()))
+            .order_by(purchases::id.desc())
+            .select(purchases::role_id)
+            .first::<Option<i64>>(conn)
+            .optional()
+            .map(|role| role.flatten().map(|r| r as u64))
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Retrieves the `lim` most recent purchases of user `uid` in guild `gid`.
+    pub fn recent(conn: &mut SqliteConnection, gid: u64, uid: u64, lim: i64) -> Result<Vec<Self>> {
+        use super::schema::purchases::dsl::*;
+
+        purchases
+            .filter(guild_id.eq(gid as i64))
+            .filter(user_id.eq(uid as i64))
+            .order_by(id.desc())
+            .limit(lim)
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{test_conn, DbTransaction};
+
+    const GUILD: u64 = 1;
+    const USER: u64 = 2;
+
+    fn setup(points: i64) -> (SqliteConnection, ShopItem) {
+        let mut conn = test_conn();
+        DbUser::new(&mut conn, GUILD, USER).unwrap();
+        DbUser::add_points(&mut conn, GUILD, USER, points, AuraSource::Add, None, "").unwrap();
+
+        let item = ShopItem::add(&mut conn, GUILD, "pin", ShopItemKind::Pin, 500, None, None)
+            .unwrap()
+            .unwrap();
+        (conn, item)
+    }
+
+    fn points(conn: &mut SqliteConnection) -> i64 {
+        DbUser::get_points(conn, GUILD, USER).unwrap().unwrap()
+    }
+
+    #[test]
+    fn buying_debits_and_records() {
+        let (mut conn, item) = setup(600);
+
+        let purchase = Purchase::buy(&mut conn, GUILD, USER, &item, "somewhere").unwrap();
+
+        assert_eq!(points(&mut conn), 100);
+        assert_eq!(purchase.price, 500);
+        assert_eq!(purchase.item_id, item.id);
+        assert_eq!(
+            DbTransaction::count_for(&mut conn, GUILD, USER, Some(AuraSource::Shop)).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn cannot_overdraw() {
+        let (mut conn, item) = setup(499);
+
+        assert!(matches!(
+            Purchase::buy(&mut conn, GUILD, USER, &item, ""),
+            Err(DungeonBotError::InsufficientAuraError { balance: 499, needed: 500, .. })
+        ));
+        assert_eq!(points(&mut conn), 499);
+        assert!(Purchase::recent(&mut conn, GUILD, USER, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refunds_only_once() {
+        let (mut conn, item) = setup(500);
+        let purchase = Purchase::buy(&mut conn, GUILD, USER, &item, "").unwrap();
+
+        assert!(Purchase::refund(&mut conn, GUILD, purchase.id, None).unwrap().is_some());
+        assert!(Purchase::refund(&mut conn, GUILD, purchase.id, None).unwrap().is_none());
+        assert_eq!(points(&mut conn), 500);
+    }
+
+    #[test]
+    fn names_are_unique_while_for_sale() {
+        let (mut conn, _) = setup(0);
+
+        assert!(ShopItem::add(&mut conn, GUILD, "pin", ShopItemKind::Pin, 1, None, None).unwrap().is_none());
+
+        assert_eq!(ShopItem::remove(&mut conn, GUILD, "pin").unwrap(), 1);
+        assert!(ShopItem::find(&mut conn, GUILD, "pin").unwrap().is_none());
+        assert!(ShopItem::add(&mut conn, GUILD, "pin", ShopItemKind::Pin, 1, None, None).unwrap().is_some());
+    }
+
+    #[test]
+    fn defaults_are_only_added_once() {
+        let mut conn = test_conn();
+
+        assert_eq!(ShopItem::add_defaults(&mut conn, GUILD).unwrap(), 2);
+        assert_eq!(ShopItem::add_defaults(&mut conn, GUILD).unwrap(), 0);
+        assert_eq!(ShopItem::add_defaults(&mut conn, 9).unwrap(), 2);
+
+        // Taking one off sale sticks
+        ShopItem::remove(&mut conn, GUILD, "pin").unwrap();
+        assert_eq!(ShopItem::add_defaults(&mut conn, GUILD).unwrap(), 0);
+        let names: Vec<String> = ShopItem::list(&mut conn, GUILD).unwrap()
+            .into_iter()
+            .map(|item| item.name)
+            .collect();
+        assert_eq!(names, vec!["unpin"]);
+    }
+
+    #[test]
+    fn temporary_roles_come_due() {
+        let (mut conn, item) = setup(500);
+        let purchase = Purchase::buy(&mut conn, GUILD, USER, &item, "").unwrap();
+        Purchase::set_role(&mut conn, purchase.id, 3, Some(1000)).unwrap();
+
+        assert!(Purchase::due(&mut conn, GUILD, 999).unwrap().is_empty());
+        assert_eq!(Purchase::due(&mut conn, GUILD, 1000).unwrap().len(), 1);
+        assert!(Purchase::due(&mut conn, 9, 1000).unwrap().is_empty());
+
+        Purchase::set_expired(&mut conn, purchase.id).unwrap();
+        assert!(Purchase::due(&mut conn, GUILD, 1000).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+use diesel::prelude::*;
+
+use super::schema::starboard;
+
+use crate::error::{DungeonBotError, Result};
+
+/// A message that has been starred at least once.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable)]
+#[diesel(table_name = starboard)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct StarredMessage {
+    pub message_id: i64,
+    pub guild_id: i64,
+    pub channel_id: i64,
+    pub author_id: i64,
+    /// Stars the author has been paid for
+    pub stars: i32,
+    /// The message's repost in the starboard channel, if it made it
+    pub repost_id: Option<i64>,
+}
+
+impl StarredMessage {
+    /// Gets starred message `mid`.
+    /// Returns None if it was never starred.
+    pub fn get(conn: &mut SqliteConnection, mid: u64) -> Result<Option<Self>> {
+        use super::schema::starboard::dsl::*;
+
+        starboard
+            .find(mid as i64)
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Saves the stars and repost of this message.
+    pub fn save(&self, conn: &mut SqliteConnection) -> Result<usize> {
+        use super::schema::starboard::dsl::*;
+
+        diesel::insert_into(starboard)
+            .values(self)
+            .on_conflict(message_id)
+            .do_update()
+            .set((stars.eq(self.stars), repost_id.eq(self.repost_id)))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+}
@@ -0,0 +1,97 @@
+use diesel::prelude::*;
+
+use super::models::StateVar;
+use super::schema;
+
+use crate::error::{DungeonBotError, Result};
+
+impl StateVar {
+    /// Gets guild `gid`'s saved value for `state_key`, inserting
+    /// `default` first if there is none yet.
+    pub fn get_or_insert(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        state_key: &str,
+        default: &str
+    ) -> Result<String> {
+        use schema::state::dsl::*;
+
+        let new_var = StateVar {
+            guild_id: gid as i64,
+            key: state_key.to_string(),
+            value: default.to_string(),
+        };
+
+        diesel::insert_into(state)
+            .values(&new_var)
+            .on_conflict((guild_id, key))
+            .do_nothing()
+            .execute(conn)
+            .map_err(DungeonBotError::from)?;
+
+        state
+            .find((gid as i64, state_key))
+            .select(value)
+            .first(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Gets guild `gid`'s saved value for `state_key`.
+    /// Returns None if nothing is saved.
+    pub fn get(conn: &mut SqliteConnection, gid: u64, state_key: &str) -> Result<Option<String>> {
+        use schema::state::dsl::*;
+
+        state
+            .find((gid as i64, state_key))
+            .select(value)
+            .first(conn)
+            .optional()
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Saves `new_value` under `state_key` for guild `gid`,
+    /// overwriting any previous value.
+    pub fn set(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        state_key: &str,
+        new_value: &str
+    ) -> Result<usize> {
+        use schema::state::dsl::*;
+
+        let new_var = StateVar {
+            guild_id: gid as i64,
+            key: state_key.to_string(),
+            value: new_value.to_string(),
+        };
+
+        diesel::insert_into(state)
+            .values(&new_var)
+            .on_conflict((guild_id, key))
+            .do_update()
+            .set(value.eq(new_value))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Removes guild `gid`'s saved value for `state_key`, if any.
+    pub fn delete(conn: &mut SqliteConnection, gid: u64, state_key: &str) -> Result<usize> {
+        use schema::state::dsl::*;
+
+        diesel::delete(state.find((gid as i64, state_key)))
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Every guild that has a saved value for `state_key`.
+    pub fn guilds_with(conn: &mut SqliteConnection, state_key: &str) -> Result<Vec<u64>> {
+        use schema::state::dsl::*;
+
+        state
+            .filter(key.eq(state_key))
+            .select(guild_id)
+            .load::<i64>(conn)
+            .map(|ids| ids.into_iter().map(|id| id as u64).collect())
+            .map_err(DungeonBotError::from)
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::str::FromStr;
+
+use diesel::prelude::*;
+
+use super::schema::transactions;
+
+use crate::error::{DungeonBotError, Result};
+use crate::unix_now;
+
+/// Where a change in aura came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum AuraSource {
+    #[name = "Counting"]
+    Counting,
+    #[name = "Last Message"]
+    LastMessage,
+    #[name = "Tax"]
+    Tax,
+    #[name = "Word Ladder"]
+    WordLadder,
+    #[name = "Pin"]
+    Pin,
+    #[name = "Unpin"]
+    Unpin,
+    #[name = "Shop"]
+    Shop,
+    #[name = "Starboard"]
+    Starboard,
+    #[name = "Reaction Role"]
+    ReactionRole,
+    #[name = "Season"]
+    Season,
+    #[name = "/aura give"]
+    Give,
+    #[name = "/aura add"]
+    Add,
+    #[name = "/daily"]
+    Daily,
+    #[name = "/weekly"]
+    Weekly,
+}
+
+impl AuraSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuraSource::Counting => "counting",
+            AuraSource::LastMessage => "lastmessage",
+            AuraSource::Tax => "tax",
+            AuraSource::WordLadder => "wordladder",
+            AuraSource::Pin => "pin",
+            AuraSource::Unpin => "unpin",
+            AuraSource::Shop => "shop",
+            AuraSource::Starboard => "starboard",
+            AuraSource::ReactionRole => "reactionrole",
+            AuraSource::Season => "season",
+            AuraSource::Give => "give",
+            AuraSource::Add => "add",
+            AuraSource::Daily => "daily",
+            AuraSource::Weekly => "weekly",
+        }
+    }
+}
+
+impl fmt::Display for AuraSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuraSource {
+    type Err = DungeonBotError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "counting" => Ok(AuraSource::Counting),
+            "lastmessage" => Ok(AuraSource::LastMessage),
+            "tax" => Ok(AuraSource::Tax),
+            "wordladder" => Ok(AuraSource::WordLadder),
+            "pin" => Ok(AuraSource::Pin),
+            "unpin" => Ok(AuraSource::Unpin),
+            "shop" => Ok(AuraSource::Shop),
+            "starboard" => Ok(AuraSource::Starboard),
+            "reactionrole" => Ok(AuraSource::ReactionRole),
+            "season" => Ok(AuraSource::Season),
+            "give" => Ok(AuraSource::Give),
+            "add" => Ok(AuraSource::Add),
+            "daily" => Ok(AuraSource::Daily),
+            "weekly" => Ok(AuraSource::Weekly),
+            _ => Err(DungeonBotError::Other(format!("Unknown aura source `{}`", s))),
+        }
+    }
+}
+
+/// A single entry in the (append-only) aura ledger.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = transactions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct DbTransaction {
+    pub id: i32,
+    pub user_id: i64,
+    pub counterparty: Option<i64>,
+    pub source: String,
+    pub amount: i64,
+    pub reason: String,
+    pub created_at: i64,
+    pub guild_id: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = transactions)]
+pub struct NewDbTransaction<'a> {
+    pub user_id: i64,
+    pub counterparty: Option<i64>,
+    pub source: &'a str,
+    pub amount: i64,
+    pub reason: &'a str,
+    pub created_at: i64,
+    pub guild_id: i64,
+}
+
+impl DbTransaction {
+    /// Records a change of `amount` aura for user `user_id` in guild `gid`.
+    ///
+    /// This only writes to the ledger; callers are expected to
+    /// update the balance in the same database transaction.
+    pub fn record(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        user_id: u64,
+        amount: i64,
+        source: AuraSource,
+        counterparty: Option<u64>,
+        reason: &str,
+    ) -> Result<usize> {
+        use super::schema::transactions::dsl;
+
+        let new_transaction = NewDbTransaction {
+            user_id: user_id as i64,
+            counterparty: counterparty.map(|c| c as i64),
+            source: source.as_str(),
+            amount,
+            reason,
+            created_at: unix_now(),
+            guild_id: gid as i64,
+        };
+
+        diesel::insert_into(dsl::transactions)
+            .values(&new_transaction)
+            .execute(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Retrieves the [off,off + lim)-th most recent changes to user
+    /// `uid`'s aura in guild `gid`, optionally only those from `from_source`.
+    pub fn history(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        uid: u64,
+        from_source: Option<AuraSource>,
+        lim: i64,
+        off: i64,
+    ) -> Result<Vec<Self>> {
+        use super::schema::transactions::dsl::*;
+
+        let mut query = transactions
+            .filter(guild_id.eq(gid as i64))
+            .filter(user_id.eq(uid as i64))
+            .into_boxed();
+        if let Some(from_source) = from_source {
+            query = query.filter(source.eq(from_source.as_str()));
+        }
+
+        query
+            .order_by((created_at.desc(), id.desc()))
+            .limit(lim)
+            .offset(off)
+            .select(Self::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Returns the number of changes to user `uid`'s aura in
+    /// guild `gid`, optionally only those from `from_source`.
+    pub fn count_for(
+        conn: &mut SqliteConnection,
+        gid: u64,
+        uid: u64,
+        from_source: Option<AuraSource>,
+    ) -> Result<i64> {
+        use super::schema::transactions::dsl::*;
+
+        let mut query = transactions
+            .filter(guild_id.eq(gid as i64))
+            .filter(user_id.eq(uid as i64))
+            .into_boxed();
+        if let Some(from_source) = from_source {
+            query = query.filter(source.eq(from_source.as_str()));
+        }
+
+        query
+            .count()
+            .get_result(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    pub fn source(&self) -> Result<AuraSource> {
+        self.source.parse()
+    }
+}
@@ -14,8 +14,11 @@ pub enum DungeonBotError {
     #[error("Error connecting to database")]
     DbConnError (#[from] diesel::ConnectionError),
 
+    #[error("Error getting a connection from the database pool")]
+    DbPoolError (#[from] diesel::r2d2::PoolError),
+
     #[error("Discord (serenity) error")]
-    DiscordError (#[from] serenity::Error),
+    DiscordError (#[source] Box<serenity::Error>),
 
     #[error("{0} not found in TypeMap")]
     TypeMapKeyError (String),
@@ -33,6 +36,18 @@ pub enum DungeonBotError {
         source: ParseIntError,
     },
 
+    #[error("Unknown config key `{0}`")]
+    ConfigKeyError(String),
+
+    #[error("`{value}` is not a valid value for `{key}`")]
+    ConfigValueError {
+        key: String,
+        value: String,
+    },
+
+    #[error("Config key `{0}` is not set")]
+    ConfigMissingError(String),
+
     #[error("{0}")]
     CountingError(#[from] CountingError),
 
@@ -42,9 +57,22 @@ pub enum DungeonBotError {
     #[error("User {0} not found (database)")]
     DbUserNotFoundError(u64),
 
+    #[error("User {user} has {balance} aura, but needs {needed}")]
+    InsufficientAuraError {
+        user: u64,
+        balance: i64,
+        needed: i64,
+    },
+
+    #[error("This would overflow user {0}'s aura")]
+    AuraOverflowError(u64),
+
     #[error("Global data does not have key {0}")]
     TypeMapMissingKeyError(String),
 
+    #[error("This can only be used in a server")]
+    NotInGuildError,
+
     #[error("User {0} not found (discord)")]
     DiscordUserNotFoundError(u64),
 
@@ -58,4 +86,11 @@ pub enum DungeonBotError {
     Unknown,
 }
 
+/// Boxed, since it dwarfs everything else in here
+impl From<serenity::Error> for DungeonBotError {
+    fn from(err: serenity::Error) -> Self {
+        DungeonBotError::DiscordError(Box::new(err))
+    }
+}
+
 pub type Result<T> = core::result::Result<T, DungeonBotError>;
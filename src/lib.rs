@@ -6,6 +6,7 @@ pub mod subsystems;
 pub mod error;
 
 use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 use error::{DungeonBotError, Result};
 
 use tracing::debug;
@@ -46,6 +47,15 @@ pub fn env_str(key: &str) -> Result<String> {
 pub fn hms(seconds: i64) -> String {
     let s = seconds % 60;
     let m = (seconds / 60) % 60; 
-    let h = (seconds / 3600) % 60; 
+    let h = seconds / 3600;
     format!("{:02}:{:02}:{:02}", h, m, s)
 }
+
+
+/// Current unix time, in seconds
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
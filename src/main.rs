@@ -1,15 +1,14 @@
 use tracing::info;
-use tracing_subscriber;
 
 use dotenvy::dotenv;
 
-use dungeonbot::db::{db_conn, run_migrations};
+use dungeonbot::db::{adopt_legacy_guild, db_pool, run_migrations, Db, GuildConfig};
 use dungeonbot::subsystems::{Subsystem, Tax};
 use dungeonbot::{env_snowflake, env_str};
 use serenity::prelude::*;
 use serenity::all::GuildId;
 
-use dungeonbot::subsystems::{LastMessage, Counting};
+use dungeonbot::subsystems::{LastMessage, Counting, ReactionRoles, Scheduler, Shop, Starboard, WordLadder};
 use dungeonbot::commands::dungeonbot_framework;
 use dungeonbot::error::{DungeonBotError, Result};
 
@@ -20,32 +19,51 @@ async fn main() -> Result<()> {
 
     tracing_subscriber::fmt::init();
 
+    let pool = db_pool()?;
+
     info!("Running pending migrations");
     {
-        let conn = &mut db_conn()?;
+        let conn = &mut pool.get()?;
         run_migrations(conn)?;
+
+        // From back when DungeonBot only ran in one server
+        if let Ok(guild_id) = env_snowflake::<GuildId>("GUILD_ID") {
+            info!(%guild_id, "Adopting single-guild data");
+            adopt_legacy_guild(conn, guild_id.get())?;
+        }
     }
     info!("Done");
 
-    let guild_id: GuildId = env_snowflake("GUILD_ID")?;
     let bot_token = env_str("BOT_TOKEN")?;
-    let intents = GatewayIntents::GUILD_MESSAGES 
+    let intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES 
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS
         | GatewayIntents::DIRECT_MESSAGES 
         | GatewayIntents::MESSAGE_CONTENT;
 
     info!("Building poise framework");
-    let framework = dungeonbot_framework(guild_id);
+    let framework = dungeonbot_framework(pool.clone());
     info!("Done");
 
     info!("Building serenity client");
     let mut client = Client::builder(&bot_token, intents)
         .framework(framework)
+        .type_map_insert::<Db>(pool)
+        .type_map_insert::<GuildConfig>(GuildConfig::data())
         .type_map_insert::<Counting>(Counting::data())
         .event_handler(Counting)
         .type_map_insert::<LastMessage>(LastMessage::data())
         .event_handler(LastMessage)
         .type_map_insert::<Tax>(Tax::data())
         .event_handler(Tax)
+        .type_map_insert::<WordLadder>(WordLadder::data())
+        .event_handler(WordLadder)
+        .event_handler(Shop)
+        .type_map_insert::<Starboard>(Starboard::data())
+        .event_handler(Starboard)
+        .type_map_insert::<ReactionRoles>(ReactionRoles::data())
+        .event_handler(ReactionRoles)
+        .event_handler(Scheduler)
         .await
         .map_err(DungeonBotError::from)?;
 
@@ -0,0 +1,296 @@
+//! A small arithmetic evaluator for counting
+//!
+//! Understands integers (decimal, `0x` hex, `0b` binary and `0o` octal),
+//! `+ - * / % ^`, parentheses and `sqrt(..)`. Everything is done in
+//! checked `i64` arithmetic, and only whole results count: `7/2` and
+//! `sqrt(2)` are errors, not 3 and 1.
+
+use thiserror::Error;
+
+/// Longest expression that will be looked at
+const MAX_LEN: usize = 100;
+
+/// Deepest nesting of parentheses (and powers) allowed
+const MAX_DEPTH: usize = 16;
+
+/// Largest exponent allowed
+const MAX_EXPONENT: i64 = 64;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ArithmeticError {
+    #[error("Expression is too long")]
+    TooLong,
+    #[error("Expression is nested too deeply")]
+    TooDeep,
+    #[error("Unexpected `{0}`")]
+    Unexpected(String),
+    #[error("Unexpected end of expression")]
+    UnexpectedEnd,
+    #[error("Result is out of range")]
+    Overflow,
+    #[error("Result isn't a whole number")]
+    NotWhole,
+}
+
+type Result<T> = core::result::Result<T, ArithmeticError>;
+
+/// The value of an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Evaluation {
+    pub value: i64,
+    /// Number of operators and functions used; 0 for a plain number
+    pub operations: usize,
+}
+
+impl Evaluation {
+    /// Whether there's more to the expression than just a number
+    pub fn is_nontrivial(&self) -> bool {
+        self.operations > 0
+    }
+}
+
+/// Evaluates `expr`.
+pub fn evaluate(expr: &str) -> Result<Evaluation> {
+    if expr.len() > MAX_LEN { return Err(ArithmeticError::TooLong) }
+
+    let mut parser = Parser { input: expr.as_bytes(), pos: 0, depth: 0, operations: 0 };
+    let value = parser.expr()?;
+    if let Some(c) = parser.peek() {
+        return Err(ArithmeticError::Unexpected((c as char).to_string()))
+    }
+
+    Ok(Evaluation { value, operations: parser.operations })
+}
+
+/// Recursive descent over
+///
+/// ```text
+/// expr   = term (("+" | "-") term)*
+/// term   = unary (("*" | "/" | "%") unary)*
+/// unary  = "-" unary | power
+/// power  = atom ("^" unary)?
+/// atom   = number | "(" expr ")" | "sqrt" "(" expr ")"
+/// ```
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+    operations: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(found) => Err(ArithmeticError::Unexpected((found as char).to_string())),
+            None => Err(ArithmeticError::UnexpectedEnd),
+        }
+    }
+
+    /// Runs `f` one level deeper, refusing to go past `MAX_DEPTH`.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH { return Err(ArithmeticError::TooDeep) }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expr(&mut self) -> Result<i64> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat(b'+') {
+                self.operations += 1;
+                value = value.checked_add(self.term()?).ok_or(ArithmeticError::Overflow)?;
+            } else if self.eat(b'-') {
+                self.operations += 1;
+                value = value.checked_sub(self.term()?).ok_or(ArithmeticError::Overflow)?;
+            } else {
+                return Ok(value)
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<i64> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat(b'*') {
+                self.operations += 1;
+                value = value.checked_mul(self.unary()?).ok_or(ArithmeticError::Overflow)?;
+            } else if self.eat(b'/') {
+                self.operations += 1;
+                let divisor = self.unary()?;
+                if divisor == 0 || value.checked_rem(divisor).ok_or(ArithmeticError::Overflow)? != 0 {
+                    return Err(ArithmeticError::NotWhole)
+                }
+                value = value.checked_div(divisor).ok_or(ArithmeticError::Overflow)?;
+            } else if self.eat(b'%') {
+                self.operations += 1;
+                let divisor = self.unary()?;
+                if divisor == 0 { return Err(ArithmeticError::NotWhole) }
+                value = value.checked_rem_euclid(divisor).ok_or(ArithmeticError::Overflow)?;
+            } else {
+                return Ok(value)
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64> {
+        if self.eat(b'-') {
+            self.operations += 1;
+            let value = self.nested(Self::unary)?;
+            return value.checked_neg().ok_or(ArithmeticError::Overflow)
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<i64> {
+        let base = self.atom()?;
+        if !self.eat(b'^') { return Ok(base) }
+
+        self.operations += 1;
+        // Right associative: 2^3^2 is 2^9
+        let exponent = self.nested(Self::unary)?;
+        if exponent < 0 { return Err(ArithmeticError::NotWhole) }
+        if exponent > MAX_EXPONENT { return Err(ArithmeticError::Overflow) }
+        base.checked_pow(exponent as u32).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn atom(&mut self) -> Result<i64> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.nested(Self::expr)?;
+                self.expect(b')')?;
+                Ok(value)
+            }
+            Some(b'0'..=b'9') => self.number(),
+            Some(b's') if self.input[self.pos..].starts_with(b"sqrt(") => {
+                self.pos += "sqrt(".len();
+                self.operations += 1;
+                let value = self.nested(Self::expr)?;
+                self.expect(b')')?;
+                isqrt(value)
+            }
+            Some(c) => Err(ArithmeticError::Unexpected((c as char).to_string())),
+            None => Err(ArithmeticError::UnexpectedEnd),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64> {
+        let radix = match self.input.get(self.pos..self.pos + 2) {
+            Some(b"0x") => 16,
+            Some(b"0b") => 2,
+            Some(b"0o") => 8,
+            _ => 10,
+        };
+        if radix != 10 { self.pos += 2 }
+
+        let start = self.pos;
+        while self.peek().is_some_and(|c| (c as char).is_digit(radix)) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return match self.peek() {
+                Some(c) => Err(ArithmeticError::Unexpected((c as char).to_string())),
+                None => Err(ArithmeticError::UnexpectedEnd),
+            }
+        }
+
+        // Only ascii digits were consumed
+        let digits = std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        i64::from_str_radix(digits, radix).map_err(|_| ArithmeticError::Overflow)
+    }
+}
+
+/// The square root of `value`, if it's a perfect square.
+fn isqrt(value: i64) -> Result<i64> {
+    if value < 0 { return Err(ArithmeticError::NotWhole) }
+
+    let mut root = (value as f64).sqrt() as i64;
+    // Floating point can be off by one either way for big numbers
+    while root.checked_mul(root).is_none_or(|square| square > value) {
+        root -= 1;
+    }
+    while (root + 1).checked_mul(root + 1).is_some_and(|square| square <= value) {
+        root += 1;
+    }
+
+    if root * root == value { Ok(root) } else { Err(ArithmeticError::NotWhole) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(expr: &str) -> Result<i64> {
+        evaluate(expr).map(|evaluation| evaluation.value)
+    }
+
+    #[test]
+    fn plain_numbers_are_trivial() {
+        assert_eq!(evaluate("42"), Ok(Evaluation { value: 42, operations: 0 }));
+        assert_eq!(value("0x2a"), Ok(42));
+        assert_eq!(value("0b101010"), Ok(42));
+        assert_eq!(value("0o52"), Ok(42));
+        assert!(!evaluate("0x2a").unwrap().is_nontrivial());
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(value("2+3*4"), Ok(14));
+        assert_eq!(value("(2+3)*4"), Ok(20));
+        assert_eq!(value("10-4-3"), Ok(3));
+        assert_eq!(value("2^3^2"), Ok(512));
+        assert_eq!(value("-2^2"), Ok(-4));
+        assert_eq!(value("17%5"), Ok(2));
+        assert_eq!(value("sqrt(16)+1"), Ok(5));
+        assert_eq!(evaluate("6*7").unwrap().operations, 1);
+    }
+
+    #[test]
+    fn only_whole_results() {
+        assert_eq!(value("7/2"), Err(ArithmeticError::NotWhole));
+        assert_eq!(value("1/0"), Err(ArithmeticError::NotWhole));
+        assert_eq!(value("sqrt(2)"), Err(ArithmeticError::NotWhole));
+        assert_eq!(value("2^-1"), Err(ArithmeticError::NotWhole));
+        assert_eq!(value("8/2"), Ok(4));
+    }
+
+    #[test]
+    fn stays_in_bounds() {
+        assert_eq!(value("2^63"), Err(ArithmeticError::Overflow));
+        assert_eq!(value("1^99"), Err(ArithmeticError::Overflow));
+        assert_eq!(value("99999999999999999999"), Err(ArithmeticError::Overflow));
+        assert_eq!(value(&format!("{}1{}", "(".repeat(20), ")".repeat(20))), Err(ArithmeticError::TooDeep));
+        assert_eq!(value(&"1+".repeat(60)), Err(ArithmeticError::TooLong));
+        assert_eq!(value(&"-".repeat(40)), Err(ArithmeticError::TooDeep));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(value("hello").is_err());
+        assert!(value("1+").is_err());
+        assert!(value("(1").is_err());
+        assert!(value("1)").is_err());
+        assert!(value("0x").is_err());
+        assert!(value("sqrt4").is_err());
+        assert!(value("").is_err());
+    }
+}
@@ -1,12 +1,17 @@
-use dotenvy::dotenv;
- 
+use std::collections::HashMap;
+
 use serenity::{async_trait, prelude::*};
-use serenity::all::{ChannelId, Message, RoleId};
+use serenity::all::{ChannelId, GuildId, Message, MessageId, MessageUpdateEvent, RoleId, UserId};
+use tracing::error;
 
-use crate::db::{db_conn, DbUser};
-use crate::env_snowflake;
+use crate::db::{
+    AuraSource, ConfigKey, CountingCycle, CountingMilestone, CountingRun, CountingStats, Db, DbUser,
+    GuildConfig,
+};
 use crate::error::{DungeonBotError, Result};
 
+use super::arithmetic;
+use super::discord::Discord;
 use super::subsystem::{Subsystem, SyncRwLock};
 
 use thiserror::Error;
@@ -19,24 +24,100 @@ pub enum CountingError {
     CTLockWriteError,
 }
 
-#[derive(Debug, Clone)]
+/// The current count of every guild, loaded from
+/// the database the first time it's needed.
+#[derive(Debug, Clone, Default)]
 pub struct CountingData {
-    pub num: u64,
+    pub nums: HashMap<GuildId, u64>,
 }
-impl Default for CountingData {
-    fn default() -> Self {
-        let conn = &mut db_conn()
-            .expect("Unable to connect to db");
-        let num = Counting::get_db_ct(conn)
-            .expect("No saved count in database");
 
-        Self {
-            num
+pub type CountingLock = SyncRwLock<CountingData>;
+
+/// What happens when someone gets the count wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, poise::ChoiceParameter)]
+pub enum CountingMode {
+    /// It costs them aura, and the count carries on
+    #[default]
+    #[name = "Casual"]
+    Casual,
+    /// The count starts over, unless a save is used up
+    #[name = "Hardcore"]
+    Hardcore,
+}
+
+impl CountingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CountingMode::Casual => "casual",
+            CountingMode::Hardcore => "hardcore",
         }
     }
 }
 
-type CountingLock = SyncRwLock<CountingData>;
+/// Numbers counted before starting over at 1, unless configured otherwise
+pub const DEFAULT_CYCLE: u64 = 1000;
+
+/// Aura for a correct count, unless configured otherwise
+pub const DEFAULT_REWARD: i64 = 3;
+
+/// Aura taken for a wrong count, unless configured otherwise
+pub const DEFAULT_PENALTY: i64 = 10;
+
+/// Aura taken for counting twice in a row, unless configured otherwise
+pub const DEFAULT_CONSECUTIVE_PENALTY: i64 = 10;
+
+/// Extra aura for counting with an expression, unless configured otherwise
+pub const DEFAULT_EXPRESSION_BONUS: i64 = 2;
+
+/// The milestone of a guild that hasn't set any
+pub const DEFAULT_MILESTONE: u64 = 1000;
+
+/// Aura for reaching [`DEFAULT_MILESTONE`]
+pub const DEFAULT_MILESTONE_REWARD: i32 = 500;
+
+/// The counting settings of a guild, read from its config.
+#[derive(Debug, Clone)]
+pub struct CountingConfig {
+    /// Given to whoever finishes a cycle
+    pub role: Option<RoleId>,
+    /// Numbers counted before starting over at 1, or None to count forever
+    pub cycle: Option<u64>,
+    /// Aura for a correct count (milestones have their own)
+    pub reward: i64,
+    /// Aura taken for a wrong count
+    pub penalty: i64,
+    /// Whether someone may count twice in a row
+    pub allow_consecutive: bool,
+    /// Aura taken for counting twice in a row when that's not allowed
+    pub consecutive_penalty: i64,
+    pub mode: CountingMode,
+    /// Whether counts can be arithmetic expressions, like `6*7`
+    pub expressions: bool,
+    /// Extra aura for a correct count that's more than just a number
+    pub expression_bonus: i64,
+    /// Aura taken for editing or deleting the last count
+    pub edit_penalty: i64,
+    /// Counts worth their own reward (and maybe a role)
+    pub milestones: Vec<CountingMilestone>,
+}
+
+impl Default for CountingConfig {
+    fn default() -> Self {
+        CountingConfig {
+            role: None,
+            cycle: Some(DEFAULT_CYCLE),
+            reward: DEFAULT_REWARD,
+            penalty: DEFAULT_PENALTY,
+            allow_consecutive: false,
+            consecutive_penalty: DEFAULT_CONSECUTIVE_PENALTY,
+            mode: CountingMode::default(),
+            expressions: false,
+            expression_bonus: DEFAULT_EXPRESSION_BONUS,
+            edit_penalty: 0,
+            milestones: Vec::new(),
+        }
+    }
+}
 
 pub struct Counting;
 impl TypeMapKey for Counting {
@@ -49,112 +130,437 @@ impl Subsystem for Counting {
     async fn message_handler(ctx: &mut Context, msg: &Message) -> Result<()> {
         if msg.author.bot { return Ok(()) }
 
-        dotenv().ok();
+        let Some(guild_id) = msg.guild_id else { return Ok(()) };
 
-        let ctchannel: ChannelId = env_snowflake("COUNTING_CHANNEL_ID")?;
-        let ctrole: RoleId = env_snowflake("COUNTING_ROLE_ID")?;
+        // Counting isn't set up in this guild
+        let Some(ctchannel) = Self::channel(ctx, guild_id).await? else { return Ok(()) };
 
         // Don't care if it's not in the right channel!
         if msg.channel_id != ctchannel { return Ok(()) }
 
-        // Attempt to parse first word of message
-        let Some(Ok(newct)) = msg.content
-            .split(" ")
-            .next()
-            .map(str::parse::<u64>) else { return Ok(()) };
+        let ctlock = Self::lock(ctx).await?;
+        let connection = &mut Db::conn(ctx).await?;
+        let config = Self::config(ctx, connection, guild_id).await?;
 
-        // Check if value is correct
-        let oldct = Self::get_lock_ct(ctx).await?;
-        let is_next_value = newct == (oldct).rem_euclid(1000) + 1;
+        Self::count(ctx.http.as_ref(), connection, &ctlock, &config, guild_id, msg).await
+    }
+}
+
+use diesel::SqliteConnection;
+use crate::db::models::StateVar;
 
-        let connection = &mut db_conn()?;
+impl Counting {
+    /// Reads the counting settings of guild `guild_id`.
+    async fn config(ctx: &Context, conn: &mut SqliteConnection, guild_id: GuildId) -> Result<CountingConfig> {
+        Ok(CountingConfig {
+            role: GuildConfig::get_snowflake(ctx, guild_id, ConfigKey::CountingRole).await?,
+            // 0 (or less) counts forever
+            cycle: GuildConfig::get_as::<i64>(ctx, guild_id, ConfigKey::CountingCycle).await?
+                .map_or(Some(DEFAULT_CYCLE), |cycle| u64::try_from(cycle).ok().filter(|cycle| *cycle > 0)),
+            reward: GuildConfig::get_as(ctx, guild_id, ConfigKey::CountingReward).await?
+                .unwrap_or(DEFAULT_REWARD),
+            penalty: GuildConfig::get_as(ctx, guild_id, ConfigKey::CountingPenalty).await?
+                .unwrap_or(DEFAULT_PENALTY),
+            allow_consecutive: Self::get_db_allow_consecutive(conn, guild_id)?,
+            consecutive_penalty: GuildConfig::get_as(ctx, guild_id, ConfigKey::CountingConsecutivePenalty).await?
+                .unwrap_or(DEFAULT_CONSECUTIVE_PENALTY),
+            mode: Self::get_db_mode(conn, guild_id)?,
+            expressions: Self::get_db_expressions(conn, guild_id)?,
+            expression_bonus: GuildConfig::get_as(ctx, guild_id, ConfigKey::CountingExpressionBonus).await?
+                .unwrap_or(DEFAULT_EXPRESSION_BONUS),
+            edit_penalty: GuildConfig::get_as(ctx, guild_id, ConfigKey::CountingEditPenalty).await?
+                .unwrap_or(0),
+            milestones: Self::milestones(conn, guild_id)?,
+        })
+    }
+
+    /// The milestones of guild `guild_id`, or the default one if it hasn't set any.
+    pub fn milestones(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<Vec<CountingMilestone>> {
+        let milestones = CountingMilestone::list(conn, guild_id.get())?;
+        if !milestones.is_empty() {
+            return Ok(milestones);
+        }
+        Ok(vec![CountingMilestone {
+            guild_id: guild_id.get() as i64,
+            number: DEFAULT_MILESTONE as i64,
+            reward: DEFAULT_MILESTONE_REWARD,
+            role_id: None,
+        }])
+    }
+
+    /// The counting channel of guild `guild_id`, if counting is set up there.
+    async fn channel(ctx: &Context, guild_id: GuildId) -> Result<Option<ChannelId>> {
+        GuildConfig::get_snowflake::<ChannelId>(ctx, guild_id, ConfigKey::CountingChannel).await
+    }
 
-        if is_next_value {
+    /// Checks `msg` against the current count of guild `guild_id`,
+    /// handing out aura and reactions accordingly.
+    pub async fn count<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        ctlock: &CountingLock,
+        config: &CountingConfig,
+        guild_id: GuildId,
+        msg: &Message
+    ) -> Result<()> {
+        let Some((newct, is_expression)) = Self::parse(&msg.content, config.expressions) else {
+            return Ok(())
+        };
+        DbUser::new(conn, guild_id.get(), msg.author.id.get())?;
+
+        // Check if value is correct
+        let oldct = Self::read_ct(conn, ctlock, guild_id)?;
+        let is_next_value = newct == Self::next(oldct, config.cycle);
+        let now = msg.timestamp.unix_timestamp();
+
+        // Nobody gets to count all on their own
+        let is_consecutive = !config.allow_consecutive
+            && Self::get_db_last_counter(conn, guild_id)? == Some(msg.author.id);
+
+        if is_next_value && is_consecutive {
+            DbUser::add_points(
+                conn,
+                guild_id.into(),
+                msg.author.id.into(),
+                -config.consecutive_penalty,
+                AuraSource::Counting,
+                None,
+                "Counted twice in a row"
+            )?;
+            discord.react(msg.channel_id, msg.id, '⛔').await?;
+        } else if is_next_value {
             // Set count behind lock
-            Self::set_lock_ct(ctx, newct).await?;
+            ctlock.write()?.nums.insert(guild_id, newct);
 
             // Set saved count in db
-            Self::set_db_ct(connection, newct)?;
+            Self::set_db_ct(conn, guild_id, newct)?;
+            Self::set_db_last_counter(conn, guild_id, msg.author.id)?;
+            Self::set_db_last_message(conn, guild_id, Some(msg.id))?;
+
+            let finishes_cycle = config.cycle == Some(newct);
+            CountingStats::record_correct(conn, guild_id.get(), msg.author.id.get(), now, finishes_cycle)?;
+            if newct == 1 {
+                StateVar::set(conn, guild_id.get(), "COUNT_CYCLE_START", &now.to_string())?;
+            }
 
-            if newct == 1000 {
-                DbUser::add_points(connection, msg.author.id.into(), 500)?;
+            // Milestones are worth their own reward instead
+            let milestone = config.milestones.iter().find(|milestone| milestone.number == newct as i64);
+            let (reward, reason) = match milestone {
+                Some(milestone) => (i64::from(milestone.reward), format!("Counted to {}", newct)),
+                None => (config.reward, "Correct count".to_string()),
+            };
+            if reward != 0 {
+                DbUser::add_points(conn, guild_id.into(), msg.author.id.into(), reward, AuraSource::Counting, None, &reason)?;
+            }
+            if let Some(role_id) = milestone.and_then(|milestone| milestone.role_id) {
+                discord.add_role(guild_id, msg.author.id, RoleId::new(role_id as u64)).await?;
+            }
 
-                /* Add 1000 role */
-                let memb = msg.member(&ctx.http()).await
-                    .map_err(DungeonBotError::from)?;
-                memb.add_role(&ctx.http(), ctrole).await
-                    .map_err(DungeonBotError::from)?;
-            } else {
-                DbUser::add_points(connection, msg.author.id.into(), 3)?;
+            if finishes_cycle {
+                let started_at = Self::get_db_cycle_start(conn, guild_id)?;
+                CountingCycle::complete(conn, guild_id.get(), started_at, now, msg.author.id.get())?;
+
+                if let Some(role_id) = config.role {
+                    discord.add_role(guild_id, msg.author.id, role_id).await?;
+                }
+            }
+
+            if is_expression && config.expression_bonus != 0 {
+                DbUser::add_points(
+                    conn,
+                    guild_id.into(),
+                    msg.author.id.into(),
+                    config.expression_bonus,
+                    AuraSource::Counting,
+                    None,
+                    "Counted with an expression"
+                )?;
             }
 
-            msg.react(&ctx.http, '✅').await
-                .map_err(DungeonBotError::from)?;
+            discord.react(msg.channel_id, msg.id, '✅').await?;
         } else { 
-            DbUser::add_points(connection, msg.author.id.into(), -10)?;
-            msg.react(&ctx.http, '❌').await
-                .map_err(DungeonBotError::from)?;
+            CountingStats::record_mistake(conn, guild_id.get(), msg.author.id.get(), now)?;
+            DbUser::add_points(conn, guild_id.into(), msg.author.id.into(), -config.penalty, AuraSource::Counting, None, "Wrong count")?;
+
+            // Nothing to ruin at 0
+            if config.mode == CountingMode::Hardcore && oldct != 0 {
+                Self::ruin(discord, conn, ctlock, guild_id, msg, oldct, now).await?;
+            } else {
+                discord.react(msg.channel_id, msg.id, '❌').await?;
+            }
         }
 
         Ok(())
     }
-}
 
-use diesel::SqliteConnection;
-use diesel::prelude::*;
-use crate::db::models::StateVar;
+    /// Handles message `message_id` in `channel_id` being edited to
+    /// `content`, or deleted if that's None. If it was the last count,
+    /// and no longer says it, the count is reposted for everyone to see.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn tamper<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        ctlock: &CountingLock,
+        config: &CountingConfig,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: Option<&str>
+    ) -> Result<()> {
+        if Self::get_db_last_message(conn, guild_id)? != Some(message_id) { return Ok(()) }
+        let Some(user_id) = Self::get_db_last_counter(conn, guild_id)? else { return Ok(()) };
+
+        let ct = Self::read_ct(conn, ctlock, guild_id)?;
+        let (verb, reason) = match content {
+            // Fixing a typo is fine, as long as the number stays
+            Some(content) if Self::parse(content, config.expressions).map(|(n, _)| n) == Some(ct) => {
+                return Ok(())
+            }
+            Some(_) => ("edited", "Edited their count"),
+            None => ("deleted", "Deleted their count"),
+        };
 
-impl Counting {
+        // Only once per count
+        Self::set_db_last_message(conn, guild_id, None)?;
+
+        if config.edit_penalty != 0 {
+            DbUser::new(conn, guild_id.get(), user_id.get())?;
+            DbUser::add_points(
+                conn,
+                guild_id.into(),
+                user_id.into(),
+                -config.edit_penalty,
+                AuraSource::Counting,
+                None,
+                reason
+            )?;
+        }
+
+        discord.send_message(channel_id, &format!(
+            "<@{}> {} their count: {}, the next number is {}.",
+            user_id, verb, ct, Self::next(ct, config.cycle)
+        )).await
+    }
+
+    /// Like `tamper`, but for an event in any channel.
+    async fn tamper_handler(
+        ctx: &Context,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: Option<&str>
+    ) -> Result<()> {
+        if Self::channel(ctx, guild_id).await? != Some(channel_id) { return Ok(()) }
 
-    pub async fn get_lock_ct(ctx: &Context) -> Result<u64> {
         let ctlock = Self::lock(ctx).await?;
-        let read_lock = ctlock.read()?;
-        Ok(read_lock.num)
+        let conn = &mut Db::conn(ctx).await?;
+        let config = Self::config(ctx, conn, guild_id).await?;
+
+        Self::tamper(ctx.http.as_ref(), conn, &ctlock, &config, guild_id, channel_id, message_id, content).await
+    }
+
+    /// The number that comes after `ct`, starting over at 1 after `cycle`.
+    pub fn next(ct: u64, cycle: Option<u64>) -> u64 {
+        match cycle {
+            Some(cycle) if ct >= cycle => 1,
+            _ => ct + 1,
+        }
+    }
+
+    /// Reads the count off the first word of `content`, evaluating it if
+    /// `expressions` are allowed. Returns the number and whether it took
+    /// an expression to get there, or None if it's not a count.
+    fn parse(content: &str, expressions: bool) -> Option<(u64, bool)> {
+        // Attempt to parse first word of message
+        let word = content.split(" ").next()?;
+
+        if !expressions {
+            return word.parse::<u64>().ok().map(|ct| (ct, false))
+        }
+
+        let evaluation = arithmetic::evaluate(word).ok()?;
+        u64::try_from(evaluation.value).ok()
+            .map(|ct| (ct, evaluation.is_nontrivial()))
+    }
+
+    /// Handles a hardcore mistake in `msg` at count `oldct`: a save is
+    /// used up if the author or the server has one, otherwise the count
+    /// starts over and the run goes in the records.
+    async fn ruin<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        ctlock: &CountingLock,
+        guild_id: GuildId,
+        msg: &Message,
+        oldct: u64,
+        now: i64
+    ) -> Result<()> {
+        let user_id = msg.author.id;
+
+        if CountingStats::use_save(conn, guild_id.get(), user_id.get())? {
+            discord.react(msg.channel_id, msg.id, '🩹').await?;
+            discord.send_message(msg.channel_id, &format!(
+                "<@{}> used one of their saves! The count is still {}.",
+                user_id, oldct
+            )).await?;
+            return Ok(())
+        }
+
+        let pool = Self::get_db_save_pool(conn, guild_id)?;
+        if pool > 0 {
+            Self::add_db_save_pool(conn, guild_id, -1)?;
+            discord.react(msg.channel_id, msg.id, '🩹').await?;
+            discord.send_message(msg.channel_id, &format!(
+                "<@{}> used up a server save ({} left)! The count is still {}.",
+                user_id, pool - 1, oldct
+            )).await?;
+            return Ok(())
+        }
+
+        let started_at = StateVar::get(conn, guild_id.get(), "COUNT_RUN_START")?
+            .and_then(|start| start.parse::<i64>().ok());
+        CountingRun::record(conn, guild_id.get(), started_at, now, oldct as i64, user_id.get())?;
+        StateVar::set(conn, guild_id.get(), "COUNT_RUN_START", &now.to_string())?;
+
+        ctlock.write()?.nums.insert(guild_id, 0);
+        Self::set_db_ct(conn, guild_id, 0)?;
+        Self::set_db_last_message(conn, guild_id, None)?;
+        Self::reset_db_cycle_start(conn, guild_id)?;
+
+        discord.react(msg.channel_id, msg.id, '💥').await?;
+        discord.send_message(msg.channel_id, &format!(
+            "<@{}> ruined it at **{}**! Back to 1.",
+            user_id, oldct
+        )).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_lock_ct(ctx: &Context, guild_id: GuildId) -> Result<u64> {
+        let ctlock = Self::lock(ctx).await?;
+
+        if let Some(num) = ctlock.read()?.nums.get(&guild_id) {
+            return Ok(*num)
+        }
+
+        let conn = &mut Db::conn(ctx).await?;
+        Self::read_ct(conn, &ctlock, guild_id)
     }
 
-    pub async fn set_lock_ct(ctx: &Context, ct: u64) -> Result<()> {
+    pub async fn set_lock_ct(ctx: &Context, guild_id: GuildId, ct: u64) -> Result<()> {
         let ctlock = Self::lock(ctx).await?;
         let mut write_lock = ctlock.write()?;
-        (*write_lock).num = ct;
+        write_lock.nums.insert(guild_id, ct);
         Ok(())
     }
 
-    pub fn get_db_ct(conn: &mut SqliteConnection) -> Result<u64> {
-        use crate::db::schema::state::dsl::*;
+    /// Gets the count of guild `guild_id` behind `ctlock`,
+    /// loading it from the database if it isn't there yet.
+    fn read_ct(conn: &mut SqliteConnection, ctlock: &CountingLock, guild_id: GuildId) -> Result<u64> {
+        if let Some(num) = ctlock.read()?.nums.get(&guild_id) {
+            return Ok(*num)
+        }
 
-        let new_count = StateVar { 
-            key: "COUNT".to_string(),
-            value: "1000".to_string()
-        };
+        let num = Self::get_db_ct(conn, guild_id)?;
+        ctlock.write()?.nums.insert(guild_id, num);
+        Ok(num)
+    }
+
+    pub fn get_db_ct(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<u64> {
+        StateVar::get_or_insert(conn, guild_id.get(), "COUNT", "0")?
+            .parse::<u64>()
+            .map_err(|_| DungeonBotError::Other("Unable to parse saved count value".to_string()))
+    }
 
-        diesel::insert_into(state)
-            .values(&new_count)
-            .on_conflict(key)
-            .do_nothing()
-            .execute(conn)
-            .map_err(DungeonBotError::from)?;
+    pub fn set_db_ct(conn: &mut SqliteConnection, guild_id: GuildId, ct: u64) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "COUNT", &format!("{}", ct))
+    }
 
-        let res: String = state 
-            .find("COUNT")
-            .select(value)
-            .first(conn)
-            .map_err(DungeonBotError::from)?;
+    pub fn get_db_mode(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<CountingMode> {
+        match StateVar::get_or_insert(conn, guild_id.get(), "COUNT_MODE", "casual")?.as_str() {
+            "casual" => Ok(CountingMode::Casual),
+            "hardcore" => Ok(CountingMode::Hardcore),
+            other => Err(DungeonBotError::Other(format!("Unknown counting mode `{}`", other))),
+        }
+    }
 
-        res.parse::<u64>()
-            .map_err(|_| DungeonBotError::Other("Unable to parse saved count value".to_string()))
+    pub fn set_db_mode(conn: &mut SqliteConnection, guild_id: GuildId, mode: CountingMode) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "COUNT_MODE", mode.as_str())
     }
 
-    pub fn set_db_ct(conn: &mut SqliteConnection, ct: u64) -> Result<usize> {
-        use crate::db::schema::state::dsl::*;
+    /// Whether counts in guild `guild_id` can be arithmetic expressions.
+    pub fn get_db_expressions(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<bool> {
+        Ok(StateVar::get_or_insert(conn, guild_id.get(), "COUNT_EXPRESSIONS", "false")? == "true")
+    }
 
-        {
-            diesel::update(state)
-                .filter(key.eq("COUNT"))
-                .set(value.eq(format!("{}", ct)))
-                .execute(conn)
-                .map_err(DungeonBotError::from)
+    pub fn set_db_expressions(conn: &mut SqliteConnection, guild_id: GuildId, allow: bool) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "COUNT_EXPRESSIONS", &allow.to_string())
+    }
+
+    /// Saves anyone in guild `guild_id` can use up.
+    pub fn get_db_save_pool(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<i32> {
+        StateVar::get_or_insert(conn, guild_id.get(), "COUNT_SAVE_POOL", "0")?
+            .parse::<i32>()
+            .map_err(|_| DungeonBotError::Other("Unable to parse saved counting save pool".to_string()))
+    }
+
+    /// Adds `amount` saves to the pool of guild `guild_id`
+    /// (or takes them away, down to none).
+    pub fn add_db_save_pool(conn: &mut SqliteConnection, guild_id: GuildId, amount: i32) -> Result<usize> {
+        let pool = Self::get_db_save_pool(conn, guild_id)?;
+        StateVar::set(conn, guild_id.get(), "COUNT_SAVE_POOL", &format!("{}", (pool + amount).max(0)))
+    }
+
+    /// Who made the last correct count in guild `guild_id`, if anyone.
+    pub fn get_db_last_counter(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<Option<UserId>> {
+        Ok(StateVar::get(conn, guild_id.get(), "COUNT_LAST_USER")?
+            .and_then(|user_id| user_id.parse::<u64>().ok())
+            .map(UserId::new))
+    }
+
+    pub fn set_db_last_counter(conn: &mut SqliteConnection, guild_id: GuildId, user_id: UserId) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "COUNT_LAST_USER", &user_id.to_string())
+    }
+
+    /// The message of the last correct count in guild `guild_id`, if it's still there.
+    pub fn get_db_last_message(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<Option<MessageId>> {
+        Ok(StateVar::get(conn, guild_id.get(), "COUNT_LAST_MESSAGE")?
+            .and_then(|message_id| message_id.parse::<u64>().ok())
+            .map(MessageId::new))
+    }
+
+    pub fn set_db_last_message(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        message_id: Option<MessageId>
+    ) -> Result<usize> {
+        match message_id {
+            Some(message_id) => StateVar::set(conn, guild_id.get(), "COUNT_LAST_MESSAGE", &message_id.to_string()),
+            None => StateVar::delete(conn, guild_id.get(), "COUNT_LAST_MESSAGE"),
         }
     }
+
+    /// Whether members of guild `guild_id` may count twice in a row.
+    pub fn get_db_allow_consecutive(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<bool> {
+        Ok(StateVar::get_or_insert(conn, guild_id.get(), "COUNT_ALLOW_CONSECUTIVE", "false")? == "true")
+    }
+
+    pub fn set_db_allow_consecutive(conn: &mut SqliteConnection, guild_id: GuildId, allow: bool) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "COUNT_ALLOW_CONSECUTIVE", &allow.to_string())
+    }
+
+    /// When the current cycle of guild `guild_id` was started at 1.
+    /// Returns None if that's not known (e.g the count was set by hand).
+    pub fn get_db_cycle_start(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<Option<i64>> {
+        Ok(StateVar::get(conn, guild_id.get(), "COUNT_CYCLE_START")?
+            .and_then(|start| start.parse::<i64>().ok()))
+    }
+
+    /// Forgets when the current cycle of guild `guild_id` was started,
+    /// so a cycle that was skipped ahead doesn't set a record.
+    pub fn reset_db_cycle_start(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<usize> {
+        StateVar::delete(conn, guild_id.get(), "COUNT_CYCLE_START")
+    }
 }
 
 #[async_trait]
@@ -167,4 +573,388 @@ impl EventHandler for Counting {
             Self::error_handler(&mut ctx, &msg, err).await;
         }
     }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _: Option<Message>,
+        _: Option<Message>,
+        event: MessageUpdateEvent
+    ) {
+        let Some(guild_id) = event.guild_id else { return };
+        // Embeds loading in and such
+        let Some(content) = event.content else { return };
+
+        if let Err(err) = Self::tamper_handler(&ctx, guild_id, event.channel_id, event.id, Some(&content)).await {
+            error!(?err, "Unable to check edited count");
+        }
+    }
+
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        guild_id: Option<GuildId>
+    ) {
+        let Some(guild_id) = guild_id else { return };
+
+        if let Err(err) = Self::tamper_handler(&ctx, guild_id, channel_id, deleted_message_id, None).await {
+            error!(?err, "Unable to check deleted count");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::db::test_conn;
+    use crate::subsystems::discord::FakeDiscord;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const CHANNEL: ChannelId = ChannelId::new(2);
+    const ROLE: RoleId = RoleId::new(3);
+    const USER: UserId = UserId::new(4);
+    const OTHER: UserId = UserId::new(5);
+
+    fn message(id: u64, content: &str) -> Message {
+        message_from(id, USER, content)
+    }
+
+    fn message_from(id: u64, author: UserId, content: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(id);
+        msg.channel_id = CHANNEL;
+        msg.guild_id = Some(GUILD);
+        msg.author.id = author;
+        msg.content = content.to_string();
+        msg
+    }
+
+    fn setup(ct: u64) -> (SqliteConnection, CountingLock, CountingConfig) {
+        let mut conn = test_conn();
+        DbUser::new(&mut conn, GUILD.get(), USER.get()).unwrap();
+        Counting::set_db_ct(&mut conn, GUILD, ct).unwrap();
+
+        let milestones = Counting::milestones(&mut conn, GUILD).unwrap();
+        let config = CountingConfig { role: Some(ROLE), milestones, ..Default::default() };
+        (conn, CountingLock::default(), config)
+    }
+
+    fn points(conn: &mut SqliteConnection) -> i64 {
+        DbUser::get_points(conn, GUILD.get(), USER.get()).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn correct_count() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "42 yay")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 42);
+        assert_eq!(ctlock.read().unwrap().nums[&GUILD], 42);
+        assert_eq!(points(&mut conn), 3);
+        assert_eq!(discord.reactions(), vec![(MessageId::new(1), '✅')]);
+    }
+
+    #[tokio::test]
+    async fn wrong_count() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "43")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 41);
+        assert_eq!(points(&mut conn), -10);
+        assert_eq!(discord.reactions(), vec![(MessageId::new(1), '❌')]);
+    }
+
+    #[tokio::test]
+    async fn ignores_non_numbers() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "hello")).await.unwrap();
+
+        assert_eq!(points(&mut conn), 0);
+        assert!(discord.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn wraps_around_at_1000() {
+        let (mut conn, ctlock, config) = setup(999);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "1000")).await.unwrap();
+
+        assert_eq!(points(&mut conn), 500);
+        assert!(discord.has_role(GUILD, USER, ROLE));
+
+        // 1000 is followed by 1, not 1001
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(2, "1001")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(3, OTHER, "1")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 1);
+        assert_eq!(points(&mut conn), 500 - 10);
+        assert_eq!(discord.reactions(), vec![
+            (MessageId::new(1), '✅'),
+            (MessageId::new(2), '❌'),
+            (MessageId::new(3), '✅'),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn rejects_consecutive_counts() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "42")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(2, "43")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 42);
+        assert_eq!(points(&mut conn), 3 - DEFAULT_CONSECUTIVE_PENALTY);
+        assert_eq!(discord.reactions(), vec![
+            (MessageId::new(1), '✅'),
+            (MessageId::new(2), '⛔'),
+        ]);
+
+        // Anyone else can carry on
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(3, OTHER, "43")).await.unwrap();
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 43);
+    }
+
+    #[tokio::test]
+    async fn consecutive_counts_can_be_allowed() {
+        let (mut conn, ctlock, _) = setup(41);
+        let config = CountingConfig { allow_consecutive: true, ..Default::default() };
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "42")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(2, "43")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 43);
+        assert_eq!(points(&mut conn), 6);
+    }
+
+    #[tokio::test]
+    async fn hardcore_mistakes_reset_the_count() {
+        let (mut conn, ctlock, _) = setup(41);
+        let config = CountingConfig { mode: CountingMode::Hardcore, ..Default::default() };
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "40")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 0);
+        assert_eq!(discord.reactions(), vec![(MessageId::new(1), '💥')]);
+        assert_eq!(discord.messages(), vec![(CHANNEL, "<@4> ruined it at **41**! Back to 1.".to_string())]);
+
+        let run = CountingRun::best(&mut conn, GUILD.get()).unwrap().unwrap();
+        assert_eq!((run.reached, run.ruined_by), (41, USER.get() as i64));
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(2, OTHER, "1")).await.unwrap();
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn saves_protect_the_count() {
+        let (mut conn, ctlock, _) = setup(41);
+        let config = CountingConfig { mode: CountingMode::Hardcore, ..Default::default() };
+        let discord = FakeDiscord::default();
+
+        CountingStats::add_saves(&mut conn, GUILD.get(), USER.get(), 1).unwrap();
+        Counting::add_db_save_pool(&mut conn, GUILD, 1).unwrap();
+
+        // Their own save goes first, then the server's
+        for id in 1..=3 {
+            Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(id, "40")).await.unwrap();
+        }
+
+        assert_eq!(discord.reactions(), vec![
+            (MessageId::new(1), '🩹'),
+            (MessageId::new(2), '🩹'),
+            (MessageId::new(3), '💥'),
+        ]);
+        assert_eq!(Counting::get_db_save_pool(&mut conn, GUILD).unwrap(), 0);
+        assert_eq!(points(&mut conn), -30);
+    }
+
+    #[tokio::test]
+    async fn expressions_are_opt_in() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "6*7")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 41);
+        assert!(discord.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn expressions_earn_a_bonus() {
+        let (mut conn, ctlock, _) = setup(41);
+        let config = CountingConfig { expressions: true, ..Default::default() };
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "6*7 nice")).await.unwrap();
+        assert_eq!(points(&mut conn), 3 + DEFAULT_EXPRESSION_BONUS);
+
+        // Plain numbers still work, without the bonus
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(2, OTHER, "43")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(3, "(0x2c")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 43);
+        assert_eq!(points(&mut conn), 3 + DEFAULT_EXPRESSION_BONUS);
+        assert_eq!(discord.reactions(), vec![
+            (MessageId::new(1), '✅'),
+            (MessageId::new(2), '✅'),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn keeps_stats_and_cycle_records() {
+        let (mut conn, ctlock, config) = setup(997);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "998")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(2, OTHER, "999")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(3, "5")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(4, "1000")).await.unwrap();
+
+        let stats = CountingStats::get(&mut conn, GUILD.get(), USER.get()).unwrap().unwrap();
+        assert_eq!((stats.correct, stats.mistakes, stats.cycles), (2, 1, 1));
+        assert_eq!((stats.current_run, stats.longest_run), (1, 1));
+
+        // Nobody saw this cycle start
+        assert_eq!(CountingCycle::count(&mut conn, GUILD.get()).unwrap(), 1);
+        assert!(CountingCycle::fastest(&mut conn, GUILD.get()).unwrap().is_none());
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(5, OTHER, "1")).await.unwrap();
+        assert!(Counting::get_db_cycle_start(&mut conn, GUILD).unwrap().is_some());
+    }
+
+    #[test]
+    fn default_milestone_until_one_is_set() {
+        let (mut conn, _, _) = setup(0);
+        let numbers = |conn: &mut SqliteConnection| Counting::milestones(conn, GUILD).unwrap()
+            .into_iter()
+            .map(|milestone| milestone.number)
+            .collect::<Vec<_>>();
+        assert_eq!(numbers(&mut conn), vec![1000]);
+
+        CountingMilestone { guild_id: GUILD.get() as i64, number: 5, reward: 50, role_id: None }
+            .save(&mut conn)
+            .unwrap();
+        assert_eq!(numbers(&mut conn), vec![5]);
+    }
+
+    #[tokio::test]
+    async fn custom_rules() {
+        let (mut conn, ctlock, _) = setup(9);
+        CountingMilestone { guild_id: GUILD.get() as i64, number: 5, reward: 50, role_id: Some(6) }
+            .save(&mut conn)
+            .unwrap();
+        let milestones = Counting::milestones(&mut conn, GUILD).unwrap();
+        let config = CountingConfig {
+            role: Some(ROLE), cycle: Some(10), reward: 1, penalty: 2, milestones, ..Default::default()
+        };
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "10")).await.unwrap();
+        assert!(discord.has_role(GUILD, USER, ROLE));
+        assert_eq!(CountingCycle::count(&mut conn, GUILD.get()).unwrap(), 1);
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(2, "11")).await.unwrap();
+        for (id, ct) in (3..).zip(1..=5) {
+            let author = if ct % 2 == 0 { USER } else { OTHER };
+            let content = ct.to_string();
+            Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(id, author, &content)).await.unwrap();
+        }
+
+        // +1 for 10, -2 for the mistake, +1 each for 2 and 4
+        assert_eq!(points(&mut conn), 1);
+        assert!(discord.has_role(GUILD, OTHER, RoleId::new(6)));
+    }
+
+    #[tokio::test]
+    async fn unbounded_counting_goes_past_1000() {
+        let (mut conn, ctlock, _) = setup(1000);
+        let config = CountingConfig { cycle: None, ..Default::default() };
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "1001")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 1001);
+        assert_eq!(Counting::next(1001, None), 1002);
+        assert_eq!(Counting::next(1001, Some(1000)), 1);
+    }
+
+    #[tokio::test]
+    async fn edits_that_keep_the_number_are_fine() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "42")).await.unwrap();
+        Counting::tamper(&discord, &mut conn, &ctlock, &config, GUILD, CHANNEL, MessageId::new(1), Some("42 :)")).await.unwrap();
+
+        assert!(discord.messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn edited_counts_are_reposted() {
+        let (mut conn, ctlock, _) = setup(41);
+        let config = CountingConfig { edit_penalty: 5, ..Default::default() };
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "42")).await.unwrap();
+        Counting::tamper(&discord, &mut conn, &ctlock, &config, GUILD, CHANNEL, MessageId::new(1), Some("lol")).await.unwrap();
+        // Only once
+        Counting::tamper(&discord, &mut conn, &ctlock, &config, GUILD, CHANNEL, MessageId::new(1), None).await.unwrap();
+
+        assert_eq!(discord.messages(), vec![
+            (CHANNEL, "<@4> edited their count: 42, the next number is 43.".to_string()),
+        ]);
+        assert_eq!(points(&mut conn), 3 - 5);
+    }
+
+    #[tokio::test]
+    async fn only_the_last_count_matters() {
+        let (mut conn, ctlock, config) = setup(41);
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "42")).await.unwrap();
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message_from(2, OTHER, "43")).await.unwrap();
+        Counting::tamper(&discord, &mut conn, &ctlock, &config, GUILD, CHANNEL, MessageId::new(1), None).await.unwrap();
+        assert!(discord.messages().is_empty());
+
+        Counting::tamper(&discord, &mut conn, &ctlock, &config, GUILD, CHANNEL, MessageId::new(2), None).await.unwrap();
+        assert_eq!(discord.messages(), vec![
+            (CHANNEL, "<@5> deleted their count: 43, the next number is 44.".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn starts_from_1_in_a_new_guild() {
+        let mut conn = test_conn();
+        let ctlock = CountingLock::default();
+        let discord = FakeDiscord::default();
+
+        Counting::count(&discord, &mut conn, &ctlock, &CountingConfig::default(), GUILD, &message(1, "1")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn starts_from_1_in_a_new_unbounded_guild() {
+        let mut conn = test_conn();
+        let ctlock = CountingLock::default();
+        let discord = FakeDiscord::default();
+        let config = CountingConfig { cycle: None, ..Default::default() };
+
+        Counting::count(&discord, &mut conn, &ctlock, &config, GUILD, &message(1, "1")).await.unwrap();
+
+        assert_eq!(Counting::get_db_ct(&mut conn, GUILD).unwrap(), 1);
+        assert_eq!(discord.reactions(), vec![(MessageId::new(1), '✅')]);
+    }
 }
@@ -0,0 +1,252 @@
+//! The Discord side effects subsystems need
+//!
+//! Game logic goes through this instead of calling `ctx.http` directly,
+//! so it can be run against [`FakeDiscord`] in tests.
+
+use serenity::all::{
+    ChannelId, CreateEmbed, CreateMessage, EditMessage, GuildId, Member, MessageId, ReactionType,
+    RoleId, UserId,
+};
+use serenity::http::Http;
+
+use crate::error::{DungeonBotError, Result};
+
+pub trait Discord: Sync {
+    #[allow(async_fn_in_trait)]
+    async fn react(&self, channel_id: ChannelId, message_id: MessageId, emoji: char) -> Result<()>;
+
+    #[allow(async_fn_in_trait)]
+    async fn add_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> Result<()>;
+
+    #[allow(async_fn_in_trait)]
+    async fn remove_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> Result<()>;
+
+    #[allow(async_fn_in_trait)]
+    async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<()>;
+
+    #[allow(async_fn_in_trait)]
+    async fn fetch_member(&self, guild_id: GuildId, user_id: UserId) -> Result<Member>;
+
+    #[allow(async_fn_in_trait)]
+    async fn post_embed(&self, channel_id: ChannelId, content: &str, embed: CreateEmbed) -> Result<MessageId>;
+
+    /// Returns false if the message is gone.
+    #[allow(async_fn_in_trait)]
+    async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+        embed: CreateEmbed
+    ) -> Result<bool>;
+
+    /// Messages that are already gone count as deleted.
+    #[allow(async_fn_in_trait)]
+    async fn delete_message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<()>;
+}
+
+impl Discord for Http {
+    async fn react(&self, channel_id: ChannelId, message_id: MessageId, emoji: char) -> Result<()> {
+        self.create_reaction(channel_id, message_id, &ReactionType::from(emoji)).await
+            .map_err(DungeonBotError::from)
+    }
+
+    async fn add_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> Result<()> {
+        self.add_member_role(guild_id, user_id, role_id, None).await
+            .map_err(DungeonBotError::from)
+    }
+
+    async fn remove_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> Result<()> {
+        self.remove_member_role(guild_id, user_id, role_id, None).await
+            .map_err(DungeonBotError::from)
+    }
+
+    async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<()> {
+        channel_id.say(self, content).await
+            .map(|_| ())
+            .map_err(DungeonBotError::from)
+    }
+
+    async fn fetch_member(&self, guild_id: GuildId, user_id: UserId) -> Result<Member> {
+        guild_id.member(self, user_id).await
+            .map_err(DungeonBotError::from)
+    }
+
+    async fn post_embed(&self, channel_id: ChannelId, content: &str, embed: CreateEmbed) -> Result<MessageId> {
+        let msg = CreateMessage::new().content(content).embed(embed);
+        channel_id.send_message(self, msg).await
+            .map(|msg| msg.id)
+            .map_err(DungeonBotError::from)
+    }
+
+    async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+        embed: CreateEmbed
+    ) -> Result<bool> {
+        let edit = EditMessage::new().content(content).embed(embed);
+        match channel_id.edit_message(self, message_id, edit).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_not_found(&e) => Ok(false),
+            Err(e) => Err(DungeonBotError::from(e)),
+        }
+    }
+
+    async fn delete_message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<()> {
+        match channel_id.delete_message(self, message_id).await {
+            Err(e) if !is_not_found(&e) => Err(DungeonBotError::from(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_not_found(e: &serenity::Error) -> bool {
+    matches!(e, serenity::Error::Http(e) if e.status_code().map(|c| c.as_u16()) == Some(404))
+}
+
+/// Checks if two emoji are the same, ignoring the names
+/// of custom ones (which can be changed).
+pub fn same_emoji(a: &ReactionType, b: &ReactionType) -> bool {
+    match (a, b) {
+        (ReactionType::Custom { id: a, .. }, ReactionType::Custom { id: b, .. }) => a == b,
+        // Some clients leave out the variation selector
+        (ReactionType::Unicode(a), ReactionType::Unicode(b)) => 
+            a.trim_end_matches('\u{fe0f}') == b.trim_end_matches('\u{fe0f}'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeDiscord;
+
+#[cfg(test)]
+mod fake {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Records every side effect instead of talking to Discord.
+    #[derive(Debug, Default)]
+    pub struct FakeDiscord {
+        pub reactions: Mutex<Vec<(MessageId, char)>>,
+        pub roles: Mutex<HashSet<(GuildId, UserId, RoleId)>>,
+        pub messages: Mutex<Vec<(ChannelId, String)>>,
+        /// Messages with embeds that are still up, without the embeds
+        pub posts: Mutex<Vec<(ChannelId, MessageId, String)>>,
+        pub last_post_id: AtomicU64,
+        /// Makes adding and removing roles fail, like when DungeonBot
+        /// is missing permissions
+        pub fail_roles: AtomicBool,
+    }
+
+    impl FakeDiscord {
+        pub fn reactions(&self) -> Vec<(MessageId, char)> {
+            self.reactions.lock().unwrap().clone()
+        }
+
+        pub fn has_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> bool {
+            self.roles.lock().unwrap().contains(&(guild_id, user_id, role_id))
+        }
+
+        pub fn messages(&self) -> Vec<(ChannelId, String)> {
+            self.messages.lock().unwrap().clone()
+        }
+
+        pub fn posts(&self) -> Vec<(ChannelId, MessageId, String)> {
+            self.posts.lock().unwrap().clone()
+        }
+
+        pub fn set_fail_roles(&self, fail: bool) {
+            self.fail_roles.store(fail, Ordering::SeqCst);
+        }
+
+        fn check_roles(&self) -> Result<()> {
+            if self.fail_roles.load(Ordering::SeqCst) {
+                return Err(DungeonBotError::Other("Missing permissions".to_string()))
+            }
+            Ok(())
+        }
+    }
+
+    impl Discord for FakeDiscord {
+        async fn react(&self, _: ChannelId, message_id: MessageId, emoji: char) -> Result<()> {
+            self.reactions.lock().unwrap().push((message_id, emoji));
+            Ok(())
+        }
+
+        async fn add_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> Result<()> {
+            self.check_roles()?;
+            self.roles.lock().unwrap().insert((guild_id, user_id, role_id));
+            Ok(())
+        }
+
+        async fn remove_role(&self, guild_id: GuildId, user_id: UserId, role_id: RoleId) -> Result<()> {
+            self.check_roles()?;
+            self.roles.lock().unwrap().remove(&(guild_id, user_id, role_id));
+            Ok(())
+        }
+
+        async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<()> {
+            self.messages.lock().unwrap().push((channel_id, content.to_string()));
+            Ok(())
+        }
+
+        async fn fetch_member(&self, guild_id: GuildId, user_id: UserId) -> Result<Member> {
+            let mut member = Member::default();
+            member.guild_id = guild_id;
+            member.user.id = user_id;
+            member.user.name = format!("user{}", user_id);
+            member.roles = self.roles.lock().unwrap().iter()
+                .filter(|(g, u, _)| *g == guild_id && *u == user_id)
+                .map(|(_, _, role_id)| *role_id)
+                .collect();
+            Ok(member)
+        }
+
+        async fn post_embed(&self, channel_id: ChannelId, content: &str, _: CreateEmbed) -> Result<MessageId> {
+            let message_id = MessageId::new(self.last_post_id.fetch_add(1, Ordering::SeqCst) + 1);
+            self.posts.lock().unwrap().push((channel_id, message_id, content.to_string()));
+            Ok(message_id)
+        }
+
+        async fn edit_embed(
+            &self,
+            channel_id: ChannelId,
+            message_id: MessageId,
+            content: &str,
+            _: CreateEmbed
+        ) -> Result<bool> {
+            let mut posts = self.posts.lock().unwrap();
+            let Some(post) = posts.iter_mut()
+                .find(|(c, m, _)| *c == channel_id && *m == message_id) else { return Ok(false) };
+            post.2 = content.to_string();
+            Ok(true)
+        }
+
+        async fn delete_message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<()> {
+            self.posts.lock().unwrap().retain(|(c, m, _)| *c != channel_id || *m != message_id);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emoji_equality() {
+        let star = ReactionType::Unicode("⭐".to_string());
+        let star_vs = ReactionType::Unicode("⭐\u{fe0f}".to_string());
+        let custom = ReactionType::try_from("<:star:1234>").unwrap();
+        let renamed = ReactionType::try_from("<:shiny:1234>").unwrap();
+
+        assert!(same_emoji(&star, &star_vs));
+        assert!(same_emoji(&custom, &renamed));
+        assert!(!same_emoji(&star, &custom));
+    }
+}
@@ -1,19 +1,30 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use serenity::prelude::*;
-use serenity::all::{Message, UserId, RoleId, ChannelId, Member, Timestamp};
+use serenity::all::{Message, UserId, RoleId, ChannelId, GuildId, Member, Ready, Timestamp};
 
-use dotenvy::dotenv;
+use diesel::{Connection, SqliteConnection};
+use tracing::{error, info};
 
 use crate::error::DungeonBotError;
-use crate::{env_snowflake, hms};
-use crate::db::{db_conn, DbUser};
+use crate::hms;
+use crate::db::{AuraSource, ConfigKey, Db, DbUser, GuildConfig, LastMessageStreak, Schedule, ScheduledJob};
+use crate::db::models::StateVar;
 use crate::error::Result;
 
+use super::discord::Discord;
+use super::scheduler::Job;
 use super::subsystem::{Subsystem, AsyncRwLock};
 
 #[derive(Debug, Clone)]
 pub struct LastMessageData {
     memb: Member,
     timestamp: Timestamp,
+    /// Streak aura already credited
+    paid: i64,
+    /// Times the streak's aura is halved, for trading it back and forth
+    halvings: u32,
 }
 
 impl LastMessageData {
@@ -23,14 +34,64 @@ impl LastMessageData {
     pub fn timestamp(&self) -> Timestamp {
         self.timestamp
     }
+
+    /// Streak aura accrued by `now` that hasn't been credited yet.
+    fn owed(&self, now: i64) -> i64 {
+        let dt = now - self.timestamp.unix_timestamp();
+        let accrued = (dt/STREAK_MULTIPLIER).max(0) >> self.halvings;
+        (accrued - self.paid).max(0)
+    }
 }
 
 const STREAK_MULTIPLIER: i64 = 5;
 const STREAK_BONUS_MULTIPLIER: i64 = 40;
 
+/// Streaks at least this long (in seconds) are announced when
+/// they're broken
+const NOTABLE_STREAK: i64 = 300;
+
+pub const DEFAULT_MIN_LENGTH: usize = 1;
+pub const DEFAULT_COOLDOWN: i64 = 60;
+pub const DEFAULT_PAIR_WINDOW: i64 = 60 * 60;
+pub const DEFAULT_DELETE_GRACE: u64 = 3;
+
+/// How long (in seconds) a streak has to go on for to be announced
+/// while it's still going
+const STREAK_MILESTONES: [i64; 6] = [
+    60 * 60,
+    6 * 60 * 60,
+    24 * 60 * 60,
+    3 * 24 * 60 * 60,
+    7 * 24 * 60 * 60,
+    30 * 24 * 60 * 60,
+];
+
+/// How often ongoing streaks are checked for milestones
+const MILESTONE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often ongoing streaks are credited
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 /// The underlying async data structure that holds the
-/// last-message winner.
-type LMLock = AsyncRwLock<Option<LastMessageData>>;
+/// last-message winner of every guild.
+pub type LMLock = AsyncRwLock<HashMap<GuildId, LastMessageData>>;
+
+/// The last message settings of a guild, read from its config.
+#[derive(Debug, Clone)]
+pub struct LastMessageConfig {
+    pub channel: ChannelId,
+    /// Held by the current winner only
+    pub role: RoleId,
+    /// Letters or digits a message needs to take the last message
+    pub min_length: usize,
+    /// Seconds before someone can take back the last message they lost
+    pub cooldown: i64,
+    /// Seconds that the same two members trading the last message
+    /// is remembered for; every trade halves what the next one pays
+    pub pair_window: i64,
+    /// Seconds a message has to stay up before it takes the last message
+    pub delete_grace: u64,
+}
 
 // Holds the user id of the current Last Message Winner
 // Serenity uses unit structs to set up the type system for
@@ -42,25 +103,133 @@ impl TypeMapKey for LastMessage {
 }
 
 impl Subsystem for LastMessage {
-    type Data = Option<LastMessageData>;
+    type Data = HashMap<GuildId, LastMessageData>;
 
     async fn message_handler(ctx: &mut Context, msg: &Message) -> Result<()> {
-        dotenv().ok();
-        let lmchannel: ChannelId = 
-            env_snowflake("LAST_MESSAGE_CHANNEL_ID")?;
-        let connection = &mut db_conn()?;
+        let Some(guild_id) = msg.guild_id else { return Ok(()) };
+
+        // Last message isn't set up in this guild
+        let Some(lmchannel) = GuildConfig::get_snowflake::<ChannelId>(
+            ctx, guild_id, ConfigKey::LastMessageChannel
+        ).await? else { return Ok(()) };
 
         // Don't care if it's not in the right channel!
         if msg.channel_id != lmchannel { return Ok(()) }
 
-        // Retrieve guild user
-        let new = msg.member(&ctx.http).await?;
+        let config = Self::config(ctx, guild_id, lmchannel).await?;
+        let lmlock = Self::lock(ctx).await?;
 
-        // If winner isn't changing, no-op.
-        if !Self::is_new_winner(ctx, &new).await? {
-            return Ok(())
+        // Save everyone the wait (and the request) if it wouldn't count anyways
+        {
+            let connection = &mut Db::conn(ctx).await?;
+            if !Self::eligible(connection, &lmlock, &config, guild_id, msg).await? { return Ok(()) }
+        }
+
+        // Ignore messages that don't stick around
+        if config.delete_grace > 0 {
+            tokio::time::sleep(Duration::from_secs(config.delete_grace)).await;
+            match ctx.http.get_message(msg.channel_id, msg.id).await {
+                Ok(_) => {}
+                Err(serenity::Error::Http(e))
+                    if e.status_code().map(|c| c.as_u16()) == Some(404) => return Ok(()),
+                Err(e) => return Err(DungeonBotError::from(e)),
+            }
+        }
+
+        let connection = &mut Db::conn(ctx).await?;
+        Self::claim(ctx.http.as_ref(), connection, &lmlock, &config, guild_id, msg).await
+    }
+}
+
+impl LastMessage {
+    /// The last message settings of guild `guild_id`.
+    async fn config(ctx: &Context, guild_id: GuildId, channel: ChannelId) -> Result<LastMessageConfig> {
+        Ok(LastMessageConfig {
+            channel,
+            role: GuildConfig::snowflake(ctx, guild_id, ConfigKey::LastMessageRole).await?,
+            min_length: GuildConfig::get_as(ctx, guild_id, ConfigKey::LastMessageMinLength).await?
+                .unwrap_or(DEFAULT_MIN_LENGTH),
+            cooldown: GuildConfig::get_as(ctx, guild_id, ConfigKey::LastMessageCooldown).await?
+                .unwrap_or(DEFAULT_COOLDOWN),
+            pair_window: GuildConfig::get_as(ctx, guild_id, ConfigKey::LastMessagePairWindow).await?
+                .unwrap_or(DEFAULT_PAIR_WINDOW),
+            delete_grace: GuildConfig::get_as(ctx, guild_id, ConfigKey::LastMessageDeleteGrace).await?
+                .unwrap_or(DEFAULT_DELETE_GRACE),
+        })
+    }
+
+    /// Checks if `msg` is allowed to take the last message of guild `guild_id`.
+    pub async fn eligible(
+        conn: &mut SqliteConnection,
+        lmlock: &LMLock,
+        config: &LastMessageConfig,
+        guild_id: GuildId,
+        msg: &Message
+    ) -> Result<bool> {
+        if let Some(curr) = lmlock.read().await?.get(&guild_id) {
+            // If winner isn't changing, no-op.
+            if curr.id() == msg.author.id { return Ok(false) }
+            // Someone else already got a later message in
+            if curr.timestamp() > msg.timestamp { return Ok(false) }
+        }
+
+        if Self::content_length(&msg.content) < config.min_length { return Ok(false) }
+
+        if let Some(lost_at) = LastMessageStreak::last_held(conn, guild_id.into(), msg.author.id.into())? {
+            if msg.timestamp.unix_timestamp() - lost_at < config.cooldown { return Ok(false) }
         }
 
+        Ok(true)
+    }
+
+    /// The number of letters and digits in `content`, leaving out
+    /// custom emoji and mentions.
+    fn content_length(content: &str) -> usize {
+        let mut in_tag = false;
+        content.chars()
+            .filter(|&c| match c {
+                '<' => { in_tag = true; false }
+                '>' => { in_tag = false; false }
+                c => !in_tag && c.is_alphanumeric(),
+            })
+            .count()
+    }
+
+    /// Times aura is halved after the same two members traded the
+    /// last message `trades` times already.
+    fn halvings(trades: i64) -> u32 {
+        trades.clamp(0, 62) as u32
+    }
+
+    /// Makes the author of `msg` the new last message winner of
+    /// guild `guild_id`, paying out the streak they broke.
+    pub async fn claim<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        lmlock: &LMLock,
+        config: &LastMessageConfig,
+        guild_id: GuildId,
+        msg: &Message
+    ) -> Result<()> {
+        if !Self::eligible(conn, lmlock, config, guild_id, msg).await? { return Ok(()) }
+
+        // Retrieve guild user
+        let new = discord.fetch_member(guild_id, msg.author.id).await?;
+        DbUser::new(conn, guild_id.into(), new.user.id.into())?;
+
+        // Trading it back and forth pays less and less
+        let prev = lmlock.read().await?.get(&guild_id).map(LastMessageData::id);
+        let halvings = match prev {
+            Some(prev) => Self::halvings(LastMessageStreak::trades(
+                conn,
+                guild_id.into(),
+                prev.into(),
+                new.user.id.into(),
+                msg.timestamp.unix_timestamp() - config.pair_window
+            )?),
+            None => 0,
+        };
+
         /* Two steps (2 http requests) to update last message winner:
          *
          * a. Removing role from the previous winner
@@ -77,83 +246,112 @@ impl Subsystem for LastMessage {
          */
 
         // (a)
-        let lmdata = Self::pop(ctx).await?;
+        let lmdata = Self::pop(discord, conn, lmlock, config.role, guild_id).await?;
 
         // (b)
-        Self::push(ctx, new.clone(), msg.timestamp).await?;
+        Self::push(discord, conn, lmlock, config.role, guild_id, new.clone(), msg.timestamp, halvings).await?;
 
         /*
          * Then, once the Discord side is finished, the database side is much easier and much more
          * reliable, and we always try to do 
          *
-         * c. Hand out points to streak holder, and bonus to streak breaker
+         * c. Hand out the rest of the streak to its holder, and bonus to streak breaker
          */
 
         // (c)
-        if let Some(LastMessageData {
-            memb: curr,
-            timestamp,
-        }) = lmdata {
+        if let Some(lmdata) = lmdata {
+            let streak_pts = lmdata.owed(msg.timestamp.unix_timestamp());
+            let LastMessageData {
+                memb: curr,
+                timestamp,
+                ..
+            } = lmdata;
 
             let dt = {
                 let t0 = timestamp.timestamp();
                 let t1 = msg.timestamp.timestamp();
                 t1 - t0
             };
+            let bonus_pts = (dt/STREAK_BONUS_MULTIPLIER) >> halvings;
 
-            // Award streak to previous member
-            DbUser::add_points(connection, curr.user.id.into(), (dt/STREAK_MULTIPLIER) as i32)?;
+            // Award (the rest of the) streak to previous member
+            DbUser::new(conn, guild_id.into(), curr.user.id.into())?;
+            DbUser::add_points(
+                conn, 
+                guild_id.into(),
+                curr.user.id.into(), 
+                streak_pts,
+                AuraSource::LastMessage,
+                Some(new.user.id.into()),
+                &format!("{} last message streak", hms(dt))
+            )?;
 
             // Award streak break bonus to new member
-            DbUser::add_points(connection, new.user.id.into(), (dt/STREAK_BONUS_MULTIPLIER) as i32)?;
+            DbUser::add_points(
+                conn, 
+                guild_id.into(),
+                new.user.id.into(), 
+                bonus_pts,
+                AuraSource::LastMessage,
+                Some(curr.user.id.into()),
+                &format!("Broke a {} last message streak", hms(dt))
+            )?;
+
+            LastMessageStreak::record(
+                conn,
+                guild_id.into(),
+                curr.user.id.into(),
+                timestamp.unix_timestamp(),
+                msg.timestamp.unix_timestamp(),
+                Some(new.user.id.into())
+            )?;
 
-            if dt >= 300 {
-                Self::streak_message(ctx, &curr, &new, dt, lmchannel).await?;
+            if dt >= NOTABLE_STREAK {
+                Self::streak_message(discord, &curr, &new, dt, bonus_pts, config.channel).await?;
             }
         }
 
         Ok(())
     }
-}
 
-impl LastMessage {
-    pub async fn state(ctx: &Context) -> Result<Option<(Member, i64)>> {
+    pub async fn state(ctx: &Context, guild_id: GuildId) -> Result<Option<(Member, i64)>> {
         let lmlock = Self::lock(ctx).await?;
         let read_lock = lmlock.read().await?;
 
-        Ok(read_lock.as_ref()
-            .map(|LastMessageData { memb, timestamp }| 
+        Ok(read_lock.get(&guild_id)
+            .map(|LastMessageData { memb, timestamp, .. }| 
                  (memb.clone(), Timestamp::now().timestamp() - timestamp.timestamp())
                  ))
     }
 
-    pub async fn current_streak(ctx: &Context) -> Result<Option<i64>> {
+    pub async fn current_streak(ctx: &Context, guild_id: GuildId) -> Result<Option<i64>> {
         let lmlock = Self::lock(ctx).await?;
         let read_lock = lmlock.read().await?;
 
-        Ok(read_lock.as_ref()
+        Ok(read_lock.get(&guild_id)
             .map(|LastMessageData { timestamp, .. }| 
                 Timestamp::now().timestamp() - timestamp.timestamp()
             ))
     }
 
-    pub async fn get_winner(ctx: &Context) -> Result<Option<Member>> {
+    pub async fn get_winner(ctx: &Context, guild_id: GuildId) -> Result<Option<Member>> {
         let lmlock = Self::lock(ctx).await?;
         let read_lock = lmlock.read().await?;
 
-        Ok(read_lock.as_ref()
+        Ok(read_lock.get(&guild_id)
             .map(|LastMessageData { memb, .. }| memb.clone()))
     }
 
     pub async fn set_winner(
         ctx: &Context, 
+        guild_id: GuildId,
         memb: Member, 
         timestamp: Timestamp
     ) -> Result<()> {
         let lmlock = Self::lock(ctx).await?;
         let mut write_lock = lmlock.write().await?;
 
-        *write_lock = Some(LastMessageData { memb, timestamp });
+        write_lock.insert(guild_id, LastMessageData { memb, timestamp, paid: 0, halvings: 0 });
 
         Ok(())
     }
@@ -161,38 +359,42 @@ impl LastMessage {
     /// Checks if the current winner is the same as `new`.
     pub async fn is_new_winner(
         ctx: &Context, 
+        guild_id: GuildId,
         new: &Member
     ) -> Result<bool> {
-        if let Some(curr) = Self::get_winner(ctx).await? {
+        if let Some(curr) = Self::get_winner(ctx, guild_id).await? {
             Ok(curr.user.id != new.user.id)
         } else {
             Ok(true)
         }
     }
 
-    /// Attempts to remove the current winner, and gives them
-    /// the last message role
+    /// Attempts to remove the current winner, and takes away
+    /// their last message role
     ///
     /// This has to be done atomically, hence we write lock code in here
-    async fn pop(ctx: &Context) -> Result<Option<LastMessageData>> {
-        let lmrole: RoleId = 
-            env_snowflake("LAST_MESSAGE_ROLE_ID")?;
-
+    async fn pop<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        lmlock: &LMLock,
+        lmrole: RoleId,
+        guild_id: GuildId
+    ) -> Result<Option<LastMessageData>> {
         // Acquire lock
-        let lmlock = Self::lock(ctx).await?;
         let mut write_lock = lmlock.write().await?;
 
         // Get LastMessageData
-        let lmdata = write_lock.clone();
+        let lmdata = write_lock.get(&guild_id).cloned();
 
         // Remove previous winner from role
-        if let Some(LastMessageData{ memb: curr, timestamp: _ }) = lmdata.as_ref() {
-            curr.remove_role(&ctx.http, lmrole).await
-                .map_err(DungeonBotError::from)?;
+        if let Some(LastMessageData{ memb: curr, .. }) = lmdata.as_ref() {
+            discord.remove_role(guild_id, curr.user.id, lmrole).await?;
         }
 
         // Clear lock
-        *write_lock = None;
+        write_lock.remove(&guild_id);
+        Self::set_db_winner(conn, guild_id, None)?;
+        StateVar::delete(conn, guild_id.get(), "LAST_MESSAGE_MILESTONE")?;
 
         Ok(lmdata)
     }
@@ -200,37 +402,270 @@ impl LastMessage {
     /// Sets a new winner, and gives them the last message role
     ///
     /// This has to be done atomically, hence we write lock code in here
-    async fn push(
-        ctx: &mut Context, 
+    #[allow(clippy::too_many_arguments)]
+    async fn push<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        lmlock: &LMLock,
+        lmrole: RoleId,
+        guild_id: GuildId,
         memb: Member,
-        timestamp: Timestamp
+        timestamp: Timestamp,
+        halvings: u32
     ) -> Result<()> {
-        let lmrole: RoleId = 
-            env_snowflake("LAST_MESSAGE_ROLE_ID")?;
-
         // Acquire lock
-        let lmlock = Self::lock(ctx).await?;
         let mut write_lock = lmlock.write().await?;
 
         // Add new winner to role
-        memb.add_role(&ctx.http, lmrole).await
-            .map_err(DungeonBotError::from)?;
+        discord.add_role(guild_id, memb.user.id, lmrole).await?;
 
         // Update value in lock
-        *write_lock = Some(LastMessageData {
+        let lmdata = LastMessageData {
             memb,
-            timestamp
-        });
+            timestamp,
+            paid: 0,
+            halvings
+        };
+        Self::set_db_winner(conn, guild_id, Some(&lmdata))?;
+        write_lock.insert(guild_id, lmdata);
 
         Ok(())
     }
 
+    /// Restores the saved winner after a restart, and makes sure
+    /// they are the only member holding the last message role.
+    ///
+    /// If nothing was saved, whoever already holds the role keeps it
+    /// (with a fresh streak).
+    pub async fn restore(ctx: &Context, guild_id: GuildId) -> Result<()> {
+        // Last message isn't set up in this guild
+        let Some(lmrole) = GuildConfig::get_snowflake::<RoleId>(
+            ctx, guild_id, ConfigKey::LastMessageRole
+        ).await? else { return Ok(()) };
+        let connection = &mut Db::conn(ctx).await?;
+
+        // Acquire lock
+        let lmlock = Self::lock(ctx).await?;
+        let mut write_lock = lmlock.write().await?;
+
+        let holders = Self::role_holders(ctx, guild_id, lmrole).await?;
+
+        let saved = match Self::get_db_winner(connection, guild_id)? {
+            Some((user_id, timestamp, paid, halvings)) => {
+                match guild_id.member(&ctx.http, user_id).await {
+                    Ok(memb) => Some(LastMessageData { memb, timestamp, paid, halvings }),
+                    // Winner has since left the server
+                    Err(serenity::Error::Http(e)) 
+                        if e.status_code().map(|c| c.as_u16()) == Some(404) => None,
+                    Err(e) => return Err(DungeonBotError::from(e)),
+                }
+            }
+            None => None,
+        };
+        // A fresh streak starts from scratch
+        if saved.is_none() {
+            StateVar::delete(connection, guild_id.get(), "LAST_MESSAGE_MILESTONE")?;
+        }
+        let lmdata = saved.or_else(|| holders.first()
+            .map(|memb| LastMessageData { 
+                memb: memb.clone(), 
+                timestamp: Timestamp::now(),
+                paid: 0,
+                halvings: 0,
+            }));
+        let winner = lmdata.as_ref().map(LastMessageData::id);
+
+        // Take the role away from anyone who shouldn't have it...
+        for memb in holders.iter().filter(|memb| Some(memb.user.id) != winner) {
+            memb.remove_role(&ctx.http, lmrole).await
+                .map_err(DungeonBotError::from)?;
+        }
+
+        // ...and make sure the winner does
+        if let Some(LastMessageData { memb, .. }) = lmdata.as_ref() {
+            if !memb.roles.contains(&lmrole) {
+                memb.add_role(&ctx.http, lmrole).await
+                    .map_err(DungeonBotError::from)?;
+            }
+        }
+
+        // Whoever it is has to be able to get paid for it
+        if let Some(winner) = winner {
+            DbUser::new(connection, guild_id.get(), winner.get())?;
+        }
+        Self::set_db_winner(connection, guild_id, lmdata.as_ref())?;
+        match lmdata {
+            Some(lmdata) => write_lock.insert(guild_id, lmdata),
+            None => write_lock.remove(&guild_id),
+        };
+
+        Ok(())
+    }
+
+    /// Fetches every member of `guild_id` that has `role`.
+    async fn role_holders(
+        ctx: &Context,
+        guild_id: GuildId,
+        role: RoleId
+    ) -> Result<Vec<Member>> {
+        let mut holders = vec![];
+        let mut after = None;
+
+        loop {
+            let page = guild_id.members(&ctx.http, Some(1000), after).await
+                .map_err(DungeonBotError::from)?;
+            let Some(last) = page.last() else { break };
+            after = Some(last.user.id);
+
+            holders.extend(page.into_iter()
+                .filter(|memb| memb.roles.contains(&role)));
+        }
+
+        Ok(holders)
+    }
+
+    /// Gets the saved winner, the start of their streak, the streak
+    /// aura they've been credited so far and its halvings.
+    fn get_db_winner(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId
+    ) -> Result<Option<(UserId, Timestamp, i64, u32)>> {
+        let Some(saved) = StateVar::get(conn, guild_id.get(), "LAST_MESSAGE")? else {
+            return Ok(None)
+        };
+
+        let parse_error = || DungeonBotError::Other(
+            format!("Unable to parse saved last message winner `{}`", saved)
+        );
+
+        let mut fields = saved.split(' ');
+        let user_id = fields.next()
+            .and_then(|user_id| user_id.parse::<u64>().ok())
+            .ok_or_else(parse_error)?;
+        let timestamp = fields.next()
+            .and_then(|t| t.parse::<i64>().ok())
+            .and_then(|t| Timestamp::from_unix_timestamp(t).ok())
+            .ok_or_else(parse_error)?;
+        // Saved before streaks were credited as they went
+        let paid = fields.next().map_or(Ok(0), str::parse::<i64>)
+            .map_err(|_| parse_error())?;
+        let halvings = fields.next().map_or(Ok(0), str::parse::<u32>)
+            .map_err(|_| parse_error())?;
+
+        Ok(Some((UserId::new(user_id), timestamp, paid, halvings)))
+    }
+
+    /// Saves (or clears) the winner, the start of their streak, the
+    /// streak aura they've been credited so far and its halvings.
+    fn set_db_winner(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        lmdata: Option<&LastMessageData>
+    ) -> Result<usize> {
+        match lmdata {
+            Some(lmdata) => StateVar::set(
+                conn, 
+                guild_id.get(),
+                "LAST_MESSAGE", 
+                &format!(
+                    "{} {} {} {}",
+                    lmdata.id(),
+                    lmdata.timestamp().unix_timestamp(),
+                    lmdata.paid,
+                    lmdata.halvings
+                )
+            ),
+            None => StateVar::delete(conn, guild_id.get(), "LAST_MESSAGE"),
+        }
+    }
+
+    /// Credits the winner of guild `guild_id` with the streak aura
+    /// they've accrued by `now`.
+    pub async fn checkpoint(
+        conn: &mut SqliteConnection,
+        lmlock: &LMLock,
+        guild_id: GuildId,
+        now: i64
+    ) -> Result<()> {
+        let mut write_lock = lmlock.write().await?;
+        let Some(lmdata) = write_lock.get_mut(&guild_id) else { return Ok(()) };
+
+        let owed = lmdata.owed(now);
+        if owed == 0 { return Ok(()) }
+
+        let checkpoint = LastMessageData {
+            paid: lmdata.paid + owed,
+            ..lmdata.clone()
+        };
+        conn.transaction(|conn| {
+            DbUser::new(conn, guild_id.into(), checkpoint.id().into())?;
+            DbUser::add_points(
+                conn,
+                guild_id.into(),
+                checkpoint.id().into(),
+                owed,
+                AuraSource::LastMessage,
+                None,
+                &format!("{} last message streak so far", hms(now - checkpoint.timestamp().unix_timestamp()))
+            )?;
+            Self::set_db_winner(conn, guild_id, Some(&checkpoint))
+        })?;
+        *lmdata = checkpoint;
+
+        Ok(())
+    }
+
+    /// Announces the biggest milestone the current streak of guild
+    /// `guild_id` has passed by `now`, unless it already has been.
+    pub async fn announce_milestone<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        lmlock: &LMLock,
+        guild_id: GuildId,
+        channel: ChannelId,
+        now: i64
+    ) -> Result<()> {
+        let Some(lmdata) = lmlock.read().await?.get(&guild_id).cloned() else {
+            return Ok(())
+        };
+
+        let dt = now - lmdata.timestamp().unix_timestamp();
+        let Some(milestone) = STREAK_MILESTONES.into_iter()
+            .rev()
+            .find(|&milestone| milestone <= dt) else { return Ok(()) };
+
+        // Only the biggest one counts if several went by at once
+        if Self::get_db_milestone(conn, guild_id)? >= milestone { return Ok(()) }
+        Self::set_db_milestone(conn, guild_id, milestone)?;
+
+        let milestone_message = format!(
+            "⏳ {} has had the last message for {}! Can anyone stop them?",
+            lmdata.memb.display_name(),
+            hms(milestone)
+        );
+
+        discord.send_message(channel, &milestone_message).await
+    }
+
+    /// Gets the last milestone announced for the current streak.
+    fn get_db_milestone(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<i64> {
+        StateVar::get(conn, guild_id.get(), "LAST_MESSAGE_MILESTONE")?
+            .map_or(Ok(0), |milestone| milestone.parse::<i64>())
+            .map_err(|_| DungeonBotError::Other("Unable to parse saved last message milestone".to_string()))
+    }
+
+    /// Saves the last milestone announced for the current streak.
+    fn set_db_milestone(conn: &mut SqliteConnection, guild_id: GuildId, milestone: i64) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "LAST_MESSAGE_MILESTONE", &format!("{}", milestone))
+    }
+
     /// Sends a streak message
-    async fn streak_message(
-        ctx: &mut Context, 
+    async fn streak_message<D: Discord>(
+        discord: &D,
         curr: &Member,
         new: &Member,
         dt: i64,
+        bonus: i64,
         channel: ChannelId
     ) -> Result<()> {
 
@@ -239,13 +674,54 @@ impl LastMessage {
             new.display_name(),
             curr.display_name(),
             hms(dt),
-            dt/STREAK_BONUS_MULTIPLIER
+            bonus
         );
 
-        channel.say(&ctx.http, streak_message).await     
-            .map_err(DungeonBotError::from)?;
+        discord.send_message(channel, &streak_message).await
+    }
+}
 
-        Ok(())
+/// Credits the ongoing streak of a guild, every `CHECKPOINT_INTERVAL`.
+pub struct StreakCheckpoint;
+
+impl Job for StreakCheckpoint {
+    const KIND: &'static str = "last_message_checkpoint";
+
+    fn default_schedule() -> Option<Schedule> {
+        Some(Schedule::Every(CHECKPOINT_INTERVAL.as_secs() as i64))
+    }
+
+    async fn run(ctx: &Context, job: &ScheduledJob) -> Result<()> {
+        let guild_id = GuildId::new(job.guild_id as u64);
+        let lmlock = LastMessage::lock(ctx).await?;
+        let conn = &mut Db::conn(ctx).await?;
+
+        LastMessage::checkpoint(conn, &lmlock, guild_id, Timestamp::now().unix_timestamp()).await
+    }
+}
+
+/// Announces the milestones of a guild's ongoing streak, every `MILESTONE_INTERVAL`.
+pub struct StreakMilestone;
+
+impl Job for StreakMilestone {
+    const KIND: &'static str = "last_message_milestone";
+
+    fn default_schedule() -> Option<Schedule> {
+        Some(Schedule::Every(MILESTONE_INTERVAL.as_secs() as i64))
+    }
+
+    async fn run(ctx: &Context, job: &ScheduledJob) -> Result<()> {
+        let guild_id = GuildId::new(job.guild_id as u64);
+        let Some(channel) = GuildConfig::get_snowflake::<ChannelId>(
+            ctx, guild_id, ConfigKey::LastMessageChannel
+        ).await? else { return Ok(()) };
+
+        let lmlock = LastMessage::lock(ctx).await?;
+        let conn = &mut Db::conn(ctx).await?;
+
+        LastMessage::announce_milestone(
+            ctx.http.as_ref(), conn, &lmlock, guild_id, channel, Timestamp::now().unix_timestamp()
+        ).await
     }
 }
 
@@ -253,6 +729,15 @@ use serenity::async_trait;
 
 #[async_trait]
 impl EventHandler for LastMessage {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        for guild in ready.guilds {
+            info!(guild_id = %guild.id, "Restoring last message winner");
+            if let Err(err) = Self::restore(&ctx, guild.id).await {
+                error!(guild_id = %guild.id, ?err, "Unable to restore last message winner");
+            }
+        }
+    }
+
     async fn message(&self, mut ctx: Context, msg: Message) {
 
         if msg.author.bot { return }
@@ -262,3 +747,280 @@ impl EventHandler for LastMessage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::MessageId;
+
+    use super::*;
+    use crate::db::test_conn;
+    use crate::subsystems::discord::FakeDiscord;
+
+    const GUILD: GuildId = GuildId::new(1);
+    // No anti-farming rules
+    const CONFIG: LastMessageConfig = LastMessageConfig {
+        channel: ChannelId::new(2),
+        role: RoleId::new(3),
+        min_length: 0,
+        cooldown: 0,
+        pair_window: 0,
+        delete_grace: 0,
+    };
+    const STRICT: LastMessageConfig = LastMessageConfig {
+        min_length: 3,
+        cooldown: 60,
+        pair_window: 3600,
+        ..CONFIG
+    };
+    const ALICE: UserId = UserId::new(4);
+    const BOB: UserId = UserId::new(5);
+
+    fn message(author: UserId, at: i64) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(at as u64);
+        msg.channel_id = CONFIG.channel;
+        msg.guild_id = Some(GUILD);
+        msg.author.id = author;
+        msg.timestamp = Timestamp::from_unix_timestamp(at).unwrap();
+        msg
+    }
+
+    fn message_with(author: UserId, at: i64, content: &str) -> Message {
+        let mut msg = message(author, at);
+        msg.content = content.to_string();
+        msg
+    }
+
+    fn setup() -> (SqliteConnection, LMLock) {
+        let mut conn = test_conn();
+        DbUser::new(&mut conn, GUILD.get(), ALICE.get()).unwrap();
+        DbUser::new(&mut conn, GUILD.get(), BOB.get()).unwrap();
+        (conn, LMLock::default())
+    }
+
+    fn points(conn: &mut SqliteConnection, user_id: UserId) -> i64 {
+        DbUser::get_points(conn, GUILD.get(), user_id.get()).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn first_message_claims_role() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+
+        assert!(discord.has_role(GUILD, ALICE, CONFIG.role));
+        assert_eq!(lmlock.read().await.unwrap()[&GUILD].id(), ALICE);
+        assert_eq!(points(&mut conn, ALICE), 0);
+        assert_eq!(
+            LastMessage::get_db_winner(&mut conn, GUILD).unwrap(),
+            Some((ALICE, Timestamp::from_unix_timestamp(1000).unwrap(), 0, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn breaking_a_streak_pays_out() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(BOB, 1400)).await.unwrap();
+
+        // 400 seconds
+        assert_eq!(points(&mut conn, ALICE), 400 / STREAK_MULTIPLIER);
+        assert_eq!(points(&mut conn, BOB), 400 / STREAK_BONUS_MULTIPLIER);
+
+        assert!(!discord.has_role(GUILD, ALICE, CONFIG.role));
+        assert!(discord.has_role(GUILD, BOB, CONFIG.role));
+
+        let messages = discord.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, CONFIG.channel);
+        assert!(messages[0].1.contains("00:06:40"), "{}", messages[0].1);
+
+        let streaks = LastMessageStreak::top(&mut conn, GUILD.get(), 10).unwrap();
+        assert_eq!(streaks.len(), 1);
+        assert_eq!((streaks[0].user_id, streaks[0].duration()), (ALICE.get() as i64, 400));
+        assert_eq!(streaks[0].broken_by, Some(BOB.get() as i64));
+    }
+
+    #[tokio::test]
+    async fn short_streaks_are_not_announced() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(BOB, 1100)).await.unwrap();
+
+        assert_eq!(points(&mut conn, ALICE), 100 / STREAK_MULTIPLIER);
+        assert_eq!(points(&mut conn, BOB), 100 / STREAK_BONUS_MULTIPLIER);
+        assert!(discord.messages().is_empty());
+        // Still on the record, for cooldowns and trades
+        assert_eq!(LastMessageStreak::top(&mut conn, GUILD.get(), 10).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn winner_keeps_their_streak() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 5000)).await.unwrap();
+
+        assert_eq!(points(&mut conn, ALICE), 0);
+        assert_eq!(lmlock.read().await.unwrap()[&GUILD].timestamp().unix_timestamp(), 1000);
+    }
+
+    #[tokio::test]
+    async fn milestones_are_announced_once() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+
+        // Not even an hour yet
+        LastMessage::announce_milestone(&discord, &mut conn, &lmlock, GUILD, CONFIG.channel, 1000 + 3599).await.unwrap();
+        assert!(discord.messages().is_empty());
+
+        LastMessage::announce_milestone(&discord, &mut conn, &lmlock, GUILD, CONFIG.channel, 1000 + 3600).await.unwrap();
+        LastMessage::announce_milestone(&discord, &mut conn, &lmlock, GUILD, CONFIG.channel, 1000 + 3660).await.unwrap();
+        assert_eq!(discord.messages().len(), 1);
+        assert!(discord.messages()[0].1.contains("01:00:00"), "{}", discord.messages()[0].1);
+
+        // Skipping past 6 hours straight to a day only announces the day
+        LastMessage::announce_milestone(&discord, &mut conn, &lmlock, GUILD, CONFIG.channel, 1000 + 86400).await.unwrap();
+        assert_eq!(discord.messages().len(), 2);
+        assert!(discord.messages()[1].1.contains("24:00:00"), "{}", discord.messages()[1].1);
+    }
+
+    #[tokio::test]
+    async fn new_streaks_start_over() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+        LastMessage::announce_milestone(&discord, &mut conn, &lmlock, GUILD, CONFIG.channel, 1000 + 3600).await.unwrap();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(BOB, 5000)).await.unwrap();
+        LastMessage::announce_milestone(&discord, &mut conn, &lmlock, GUILD, CONFIG.channel, 5000 + 3600).await.unwrap();
+
+        let messages = discord.messages();
+        // Alice's hour, her broken streak, then Bob's hour
+        assert_eq!(messages.len(), 3);
+        assert!(messages[2].1.contains("01:00:00"), "{}", messages[2].1);
+    }
+
+    #[test]
+    fn emoji_and_mentions_are_not_content() {
+        assert_eq!(LastMessage::content_length("hi there"), 7);
+        assert_eq!(LastMessage::content_length("😂😂😂"), 0);
+        assert_eq!(LastMessage::content_length("<:kekw:1250998996596555817> <@1234>"), 0);
+        assert_eq!(LastMessage::content_length("ok <a:dance:42>"), 2);
+    }
+
+    #[tokio::test]
+    async fn short_messages_do_not_count() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(ALICE, 1000, "hello")).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(BOB, 2000, "🔥🔥🔥")).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(BOB, 2001, "k")).await.unwrap();
+
+        assert_eq!(lmlock.read().await.unwrap()[&GUILD].id(), ALICE);
+        assert!(!discord.has_role(GUILD, BOB, STRICT.role));
+    }
+
+    #[tokio::test]
+    async fn cooldown_before_taking_it_back() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(ALICE, 1000, "mine")).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(BOB, 2000, "nope mine")).await.unwrap();
+
+        // Alice lost it at 2000
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(ALICE, 2059, "mine again")).await.unwrap();
+        assert_eq!(lmlock.read().await.unwrap()[&GUILD].id(), BOB);
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(ALICE, 2060, "mine again")).await.unwrap();
+        assert_eq!(lmlock.read().await.unwrap()[&GUILD].id(), ALICE);
+    }
+
+    #[tokio::test]
+    async fn trading_back_and_forth_pays_less() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        for (i, author) in [ALICE, BOB, ALICE, BOB].into_iter().enumerate() {
+            let at = 1000 + 400 * i as i64;
+            LastMessage::claim(&discord, &mut conn, &lmlock, &STRICT, GUILD, &message_with(author, at, "my turn")).await.unwrap();
+        }
+
+        // Three 400 second streaks: every trade halves the bonus
+        // and the streak of whoever took it
+        let full = 400 / STREAK_MULTIPLIER;
+        let bonus = 400 / STREAK_BONUS_MULTIPLIER;
+        assert_eq!(points(&mut conn, ALICE), full + full / 2 + bonus / 2);
+        assert_eq!(points(&mut conn, BOB), full + bonus + bonus / 4);
+    }
+
+    #[tokio::test]
+    async fn late_messages_do_not_count() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(BOB, 999)).await.unwrap();
+
+        assert_eq!(lmlock.read().await.unwrap()[&GUILD].id(), ALICE);
+    }
+
+    #[tokio::test]
+    async fn streaks_are_credited_as_they_go() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(ALICE, 1000)).await.unwrap();
+
+        LastMessage::checkpoint(&mut conn, &lmlock, GUILD, 1600).await.unwrap();
+        assert_eq!(points(&mut conn, ALICE), 600 / STREAK_MULTIPLIER);
+        assert_eq!(
+            LastMessage::get_db_winner(&mut conn, GUILD).unwrap(),
+            Some((ALICE, Timestamp::from_unix_timestamp(1000).unwrap(), 600 / STREAK_MULTIPLIER, 0))
+        );
+
+        // Nothing new to credit
+        LastMessage::checkpoint(&mut conn, &lmlock, GUILD, 1600).await.unwrap();
+        assert_eq!(points(&mut conn, ALICE), 600 / STREAK_MULTIPLIER);
+
+        // Breaking it only pays the rest
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(BOB, 2000)).await.unwrap();
+        assert_eq!(points(&mut conn, ALICE), 1000 / STREAK_MULTIPLIER);
+        assert_eq!(points(&mut conn, BOB), 1000 / STREAK_BONUS_MULTIPLIER);
+    }
+
+    #[tokio::test]
+    async fn restored_holders_without_aura_still_get_paid() {
+        let (mut conn, lmlock) = setup();
+        let discord = FakeDiscord::default();
+
+        // Restored from the role, and never had any aura
+        const CAROL: UserId = UserId::new(6);
+        let memb = discord.fetch_member(GUILD, CAROL).await.unwrap();
+        let lmdata = LastMessageData {
+            memb,
+            timestamp: Timestamp::from_unix_timestamp(1000).unwrap(),
+            paid: 0,
+            halvings: 0,
+        };
+        lmlock.write().await.unwrap().insert(GUILD, lmdata);
+
+        LastMessage::checkpoint(&mut conn, &lmlock, GUILD, 1600).await.unwrap();
+        assert_eq!(points(&mut conn, CAROL), 600 / STREAK_MULTIPLIER);
+
+        LastMessage::claim(&discord, &mut conn, &lmlock, &CONFIG, GUILD, &message(BOB, 2000)).await.unwrap();
+        assert_eq!(points(&mut conn, CAROL), 1000 / STREAK_MULTIPLIER);
+        assert_eq!(points(&mut conn, BOB), 1000 / STREAK_BONUS_MULTIPLIER);
+    }
+}
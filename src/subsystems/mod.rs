@@ -1,11 +1,26 @@
 mod subsystem;
 pub use subsystem::{Subsystem, SubsystemError};
 
+pub mod discord;
+pub use discord::Discord;
+
 pub mod lastmessage;
 pub mod tax;
+pub mod arithmetic;
 pub mod counting;
 pub mod wordladder;
+pub mod shop;
+pub mod starboard;
+pub mod reactionroles;
+pub mod scheduler;
+pub mod season;
 
-pub use lastmessage::LastMessage;
-pub use counting::Counting;
-pub use tax::Tax;
+pub use lastmessage::{LastMessage, StreakCheckpoint, StreakMilestone};
+pub use counting::{Counting, CountingMode};
+pub use tax::{Tax, TaxPayoutMode};
+pub use wordladder::WordLadder;
+pub use shop::{Shop, ShopOrder};
+pub use starboard::Starboard;
+pub use reactionroles::ReactionRoles;
+pub use scheduler::{Job, Scheduler};
+pub use season::{SeasonEnd, SeasonEndPayload};
@@ -0,0 +1,283 @@
+//! Reaction roles
+//!
+//! Owners bind emoji on a message to roles (see `/reactionrole`), and
+//! members react with them to get the role, or unreact to give it back
+//! (if that's how they got it).
+//! Some roles cost aura to claim.
+
+use serenity::all::{GuildId, Reaction, ReactionType, RoleId, UserId};
+use serenity::async_trait;
+use serenity::prelude::*;
+use diesel::SqliteConnection;
+
+use crate::db::{AuraSource, Db, DbUser, ReactionRole};
+use crate::error::{DungeonBotError, Result};
+
+use super::discord::{same_emoji, Discord};
+use super::subsystem::{AsyncRwLock, Subsystem};
+
+/// What came of reacting for a role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleClaim {
+    Granted,
+    /// They can't afford it
+    TooPoor,
+    /// They already have it, or are a bot
+    Skipped,
+}
+
+/// Only one claim at a time, so a double click can't charge twice.
+type ReactionRolesLock = AsyncRwLock<()>;
+
+pub struct ReactionRoles;
+impl TypeMapKey for ReactionRoles {
+    type Value = ReactionRolesLock;
+}
+
+impl Subsystem for ReactionRoles {
+    type Data = ();
+
+    async fn reaction_handler(ctx: &mut Context, reaction: &Reaction) -> Result<()> {
+        let (Some(guild_id), Some(user_id)) = (reaction.guild_id, reaction.user_id) else {
+            return Ok(())
+        };
+        let Some(binding) = Self::binding(ctx, reaction).await? else { return Ok(()) };
+
+        let lock = Self::lock(ctx).await?;
+        let _write_lock = lock.write().await?;
+
+        let conn = &mut Db::conn(ctx).await?;
+        if Self::claim(ctx.http.as_ref(), conn, &binding, guild_id, user_id).await? == RoleClaim::TooPoor {
+            reaction.delete(&ctx.http).await?;
+            Self::notify(ctx, guild_id, user_id, &format!(
+                "You need {} aura to claim that role.",
+                binding.cost
+            )).await;
+        }
+
+        Ok(())
+    }
+
+    async fn reaction_remove_handler(ctx: &mut Context, reaction: &Reaction) -> Result<()> {
+        let (Some(guild_id), Some(user_id)) = (reaction.guild_id, reaction.user_id) else {
+            return Ok(())
+        };
+        // The reaction we put there to click on
+        if user_id == ctx.cache.current_user().id { return Ok(()) }
+        let Some(binding) = Self::binding(ctx, reaction).await? else { return Ok(()) };
+        let role_id = RoleId::new(binding.role_id as u64);
+
+        let conn = &mut Db::conn(ctx).await?;
+        Self::unclaim(ctx.http.as_ref(), conn, guild_id, user_id, role_id).await?;
+
+        Ok(())
+    }
+}
+
+impl ReactionRoles {
+    /// Gives user `user_id` the role of `binding`, charging them for it if it
+    /// costs aura. They're refunded if the role can't be given after all.
+    pub async fn claim<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        binding: &ReactionRole,
+        guild_id: GuildId,
+        user_id: UserId
+    ) -> Result<RoleClaim> {
+        let role_id = RoleId::new(binding.role_id as u64);
+
+        let member = discord.fetch_member(guild_id, user_id).await?;
+        if member.user.bot || member.roles.contains(&role_id) { return Ok(RoleClaim::Skipped) }
+
+        if binding.cost > 0 {
+            DbUser::new(conn, guild_id.get(), user_id.get())?;
+
+            let paid = DbUser::spend(
+                conn,
+                guild_id.get(),
+                user_id.get(),
+                binding.cost.into(),
+                AuraSource::ReactionRole,
+                None,
+                &format!("Claimed <@&{}>", role_id)
+            );
+
+            match paid {
+                Ok(_) => (),
+                Err(DungeonBotError::InsufficientAuraError { .. }) => return Ok(RoleClaim::TooPoor),
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Don't charge for roles they didn't get
+        if let Err(err) = discord.add_role(guild_id, user_id, role_id).await {
+            if binding.cost > 0 {
+                DbUser::add_points(
+                    conn,
+                    guild_id.get(),
+                    user_id.get(),
+                    binding.cost.into(),
+                    AuraSource::ReactionRole,
+                    None,
+                    &format!("Refund for <@&{}>", role_id)
+                )?;
+            }
+            return Err(err)
+        }
+        ReactionRole::grant(conn, guild_id.get(), user_id.get(), role_id.get())?;
+
+        Ok(RoleClaim::Granted)
+    }
+
+    /// Takes role `role_id` back from user `user_id`, if they got it by reacting.
+    /// Returns false if they didn't.
+    pub async fn unclaim<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId
+    ) -> Result<bool> {
+        if !ReactionRole::granted(conn, guild_id.get(), user_id.get(), role_id.get())? {
+            return Ok(false)
+        }
+
+        // Only forget the grant once the role is actually gone,
+        // so unreacting again can still take it back
+        discord.remove_role(guild_id, user_id, role_id).await?;
+        ReactionRole::revoke(conn, guild_id.get(), user_id.get(), role_id.get())?;
+
+        Ok(true)
+    }
+
+    /// Gets the binding `reaction` was made on, if any.
+    async fn binding(ctx: &Context, reaction: &Reaction) -> Result<Option<ReactionRole>> {
+        let conn = &mut Db::conn(ctx).await?;
+
+        Ok(ReactionRole::for_message(conn, reaction.message_id.get())?
+            .into_iter()
+            .find(|binding| ReactionType::try_from(binding.emoji.as_str())
+                .is_ok_and(|emoji| same_emoji(&emoji, &reaction.emoji))))
+    }
+
+    /// Lets a member know why they didn't get a role.
+    /// It's fine if they don't accept DMs.
+    async fn notify(ctx: &Context, guild_id: GuildId, user_id: UserId, content: &str) {
+        let guild_name = guild_id.name(&ctx.cache)
+            .unwrap_or("the server".to_string());
+
+        if let Ok(channel) = user_id.create_dm_channel(&ctx.http).await {
+            let _ = channel.say(&ctx.http, format!("{} ({})", content, guild_name)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for ReactionRoles {
+    async fn reaction_add(&self, mut ctx: Context, add_reaction: Reaction) {
+        if let Err(err) = Self::reaction_handler(&mut ctx, &add_reaction).await {
+            Self::reaction_error_handler(&mut ctx, &add_reaction, err).await;
+        }
+    }
+
+    async fn reaction_remove(&self, mut ctx: Context, removed_reaction: Reaction) {
+        if let Err(err) = Self::reaction_remove_handler(&mut ctx, &removed_reaction).await {
+            Self::reaction_error_handler(&mut ctx, &removed_reaction, err).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+    use crate::subsystems::discord::FakeDiscord;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const USER: UserId = UserId::new(2);
+    const ROLE: RoleId = RoleId::new(3);
+
+    fn binding(cost: i32) -> ReactionRole {
+        ReactionRole {
+            message_id: 4,
+            emoji: "⭐".to_string(),
+            guild_id: GUILD.get() as i64,
+            channel_id: 5,
+            role_id: ROLE.get() as i64,
+            cost,
+        }
+    }
+
+    fn setup(points: i64) -> SqliteConnection {
+        let mut conn = test_conn();
+        DbUser::new(&mut conn, GUILD.get(), USER.get()).unwrap();
+        DbUser::add_points(&mut conn, GUILD.get(), USER.get(), points, AuraSource::Counting, None, "test").unwrap();
+        conn
+    }
+
+    fn points(conn: &mut SqliteConnection) -> i64 {
+        DbUser::get_points(conn, GUILD.get(), USER.get()).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn paid_roles_are_charged_once() {
+        let conn = &mut setup(100);
+        let discord = FakeDiscord::default();
+
+        assert_eq!(ReactionRoles::claim(&discord, conn, &binding(30), GUILD, USER).await.unwrap(), RoleClaim::Granted);
+        assert!(discord.has_role(GUILD, USER, ROLE));
+        assert_eq!(points(conn), 70);
+
+        // Reacting again while they have it is free
+        assert_eq!(ReactionRoles::claim(&discord, conn, &binding(30), GUILD, USER).await.unwrap(), RoleClaim::Skipped);
+        assert_eq!(points(conn), 70);
+    }
+
+    #[tokio::test]
+    async fn roles_they_cant_afford_are_not_given() {
+        let conn = &mut setup(10);
+        let discord = FakeDiscord::default();
+
+        assert_eq!(ReactionRoles::claim(&discord, conn, &binding(30), GUILD, USER).await.unwrap(), RoleClaim::TooPoor);
+        assert!(!discord.has_role(GUILD, USER, ROLE));
+        assert_eq!(points(conn), 10);
+    }
+
+    #[tokio::test]
+    async fn roles_that_cant_be_given_are_refunded() {
+        let conn = &mut setup(100);
+        let discord = FakeDiscord::default();
+        discord.set_fail_roles(true);
+
+        assert!(ReactionRoles::claim(&discord, conn, &binding(30), GUILD, USER).await.is_err());
+        assert!(!discord.has_role(GUILD, USER, ROLE));
+        assert_eq!(points(conn), 100);
+        assert!(!ReactionRole::granted(conn, GUILD.get(), USER.get(), ROLE.get()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_roles_given_by_reacting_are_taken_back() {
+        let conn = &mut setup(0);
+        let discord = FakeDiscord::default();
+
+        // They had it some other way
+        discord.add_role(GUILD, USER, ROLE).await.unwrap();
+        assert!(!ReactionRoles::unclaim(&discord, conn, GUILD, USER, ROLE).await.unwrap());
+        assert!(discord.has_role(GUILD, USER, ROLE));
+    }
+
+    #[tokio::test]
+    async fn failed_removals_can_be_retried() {
+        let conn = &mut setup(0);
+        let discord = FakeDiscord::default();
+        ReactionRoles::claim(&discord, conn, &binding(0), GUILD, USER).await.unwrap();
+
+        discord.set_fail_roles(true);
+        assert!(ReactionRoles::unclaim(&discord, conn, GUILD, USER, ROLE).await.is_err());
+        assert!(discord.has_role(GUILD, USER, ROLE));
+
+        discord.set_fail_roles(false);
+        assert!(ReactionRoles::unclaim(&discord, conn, GUILD, USER, ROLE).await.unwrap());
+        assert!(!discord.has_role(GUILD, USER, ROLE));
+    }
+}
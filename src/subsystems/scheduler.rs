@@ -0,0 +1,159 @@
+//! Runs timed jobs
+//!
+//! Jobs are saved in the database, so they survive restarts: anything
+//! that came due while DungeonBot was down is run (or made up for) as
+//! soon as it's back.
+
+use std::sync::Once;
+use std::time::Duration;
+
+use diesel::SqliteConnection;
+use tracing::{error, info};
+
+use serenity::async_trait;
+use serenity::prelude::*;
+use serenity::all::{Guild, GuildId, Ready};
+
+use crate::db::{Db, Schedule, ScheduledJob};
+use crate::error::{DungeonBotError, Result};
+use crate::unix_now;
+
+use super::{SeasonEnd, Shop, StreakCheckpoint, StreakMilestone, Tax};
+
+/// How often the scheduler looks for jobs that are due
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Something the scheduler can run.
+pub trait Job {
+    /// What this job is saved as
+    const KIND: &'static str;
+
+    /// The schedule every guild gets this job on by default, if any
+    fn default_schedule() -> Option<Schedule> {
+        None
+    }
+
+    #[allow(async_fn_in_trait)]
+    async fn run(ctx: &Context, job: &ScheduledJob) -> Result<()>;
+}
+
+pub struct Scheduler;
+
+impl Scheduler {
+    /// Schedules every job that runs by default in guild `guild_id`
+    /// (unless it already was).
+    pub fn ensure_defaults(conn: &mut SqliteConnection, guild_id: GuildId, now: i64) -> Result<()> {
+        Self::ensure::<Tax>(conn, guild_id, now)?;
+        Self::ensure::<Shop>(conn, guild_id, now)?;
+        Self::ensure::<StreakCheckpoint>(conn, guild_id, now)?;
+        Self::ensure::<StreakMilestone>(conn, guild_id, now)
+    }
+
+    fn ensure<J: Job>(conn: &mut SqliteConnection, guild_id: GuildId, now: i64) -> Result<()> {
+        let Some(schedule) = J::default_schedule() else { return Ok(()) };
+        let Some(first_run) = schedule.next(now) else { return Ok(()) };
+
+        if ScheduledJob::ensure(conn, guild_id.get(), J::KIND, schedule, first_run)? {
+            info!(%guild_id, kind = J::KIND, "Scheduled job");
+        }
+
+        Ok(())
+    }
+
+    /// Runs `job` with whichever `Job` it belongs to.
+    async fn dispatch(ctx: &Context, job: &ScheduledJob) -> Result<()> {
+        match job.kind.as_str() {
+            Tax::KIND => Tax::run(ctx, job).await,
+            Shop::KIND => Shop::run(ctx, job).await,
+            StreakCheckpoint::KIND => StreakCheckpoint::run(ctx, job).await,
+            StreakMilestone::KIND => StreakMilestone::run(ctx, job).await,
+            SeasonEnd::KIND => SeasonEnd::run(ctx, job).await,
+            kind => Err(DungeonBotError::Other(format!("Unknown job kind `{}`", kind))),
+        }
+    }
+
+    /// Runs every job that's due by `now`, and schedules their next runs.
+    async fn run_due(ctx: &Context, conn: &mut SqliteConnection, now: i64) -> Result<()> {
+        for job in ScheduledJob::due(conn, now)? {
+            info!(job_id = job.id, kind = job.kind, "Running job");
+
+            // A job that fails still moves on to its next run,
+            // so it doesn't fail over and over again
+            if let Err(err) = Self::dispatch(ctx, &job).await {
+                error!(job_id = job.id, ?err, "Job failed");
+            }
+
+            let next = match job.schedule() {
+                Ok(schedule) => schedule.after(job.next_run, now),
+                Err(err) => {
+                    error!(job_id = job.id, ?err, "Unable to reschedule job");
+                    None
+                }
+            };
+            // Nor does it hold up the rest
+            if let Err(err) = job.reschedule(conn, now, next) {
+                error!(job_id = job.id, ?err, "Unable to reschedule job");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs jobs as they come due, every `SCHEDULER_INTERVAL`.
+    async fn scheduler_loop(ctx: Context) {
+        let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let conn = &mut match Db::conn(&ctx).await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    error!(?err, "Unable to connect to database");
+                    continue
+                }
+            };
+
+            if let Err(err) = Self::run_due(&ctx, conn, unix_now()).await {
+                error!(?err, "Unable to run scheduled jobs");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Scheduler {
+    /// Fires for every guild on startup, and whenever DungeonBot joins one.
+    async fn guild_create(&self, ctx: Context, guild: Guild, _: Option<bool>) {
+        let conn = &mut match Db::conn(&ctx).await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!(?err, "Unable to connect to database");
+                return
+            }
+        };
+
+        if let Err(err) = Self::ensure_defaults(conn, guild.id, unix_now()) {
+            error!(guild_id = %guild.id, ?err, "Unable to schedule default jobs");
+        }
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        match Db::conn(&ctx).await {
+            Ok(mut conn) => {
+                for guild in ready.guilds {
+                    if let Err(err) = Self::ensure_defaults(&mut conn, guild.id, unix_now()) {
+                        error!(guild_id = %guild.id, ?err, "Unable to schedule default jobs");
+                    }
+                }
+            }
+            Err(err) => error!(?err, "Unable to connect to database"),
+        }
+
+        // Ready fires again on reconnects; only ever start one loop
+        static SCHEDULER_LOOP: Once = Once::new();
+        SCHEDULER_LOOP.call_once(|| {
+            tokio::spawn(Self::scheduler_loop(ctx));
+        });
+    }
+}
@@ -0,0 +1,198 @@
+//! Ending seasons
+//!
+//! Seasons are kept in the database (see `db::season`); this moves the
+//! season role over to the winners and announces them, whether the
+//! season is ended by hand or by a job scheduled with `/season schedule`.
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use diesel::SqliteConnection;
+use serenity::all::{ChannelId, GuildId, RoleId, UserId};
+use serenity::prelude::*;
+use tracing::info;
+
+use crate::db::{ConfigKey, Db, GuildConfig, ScheduledJob, Season};
+use crate::error::{DungeonBotError, Result};
+
+use super::discord::Discord;
+use super::scheduler::Job;
+
+/// Winners of a season, unless configured otherwise
+pub const DEFAULT_WINNERS: i64 = 3;
+
+/// The season settings of a guild, read from its config.
+#[derive(Debug, Clone, Copy)]
+pub struct SeasonConfig {
+    /// How many of the top earners win
+    pub winners: i64,
+    /// Aura for each winner
+    pub reward: i64,
+    /// Held by the winners of the last season only
+    pub role: Option<RoleId>,
+}
+
+/// How a scheduled season end should go, saved as its job's payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeasonEndPayload {
+    /// Where the results are announced
+    pub channel: ChannelId,
+    /// Percentage of everyone's aura to keep, or None to keep it all
+    pub keep_percent: Option<i32>,
+}
+
+impl fmt::Display for SeasonEndPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.keep_percent {
+            Some(keep_percent) => write!(f, "{} {}", self.channel, keep_percent),
+            None => write!(f, "{}", self.channel),
+        }
+    }
+}
+
+impl FromStr for SeasonEndPayload {
+    type Err = DungeonBotError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || DungeonBotError::Other(format!("Invalid season end payload `{}`", s));
+
+        let mut parts = s.split_whitespace();
+        let channel = parts.next()
+            .and_then(|channel| channel.parse::<u64>().ok())
+            .filter(|channel| *channel != 0)
+            .map(ChannelId::new)
+            .ok_or_else(invalid)?;
+        let keep_percent = parts.next()
+            .map(|keep_percent| keep_percent.parse::<i32>().map_err(|_| invalid()))
+            .transpose()?;
+        if parts.next().is_some() { return Err(invalid()) }
+
+        Ok(SeasonEndPayload { channel, keep_percent })
+    }
+}
+
+pub struct SeasonEnd;
+
+impl SeasonEnd {
+    /// Reads the season settings of guild `guild_id`.
+    pub async fn config(ctx: &Context, guild_id: GuildId) -> Result<SeasonConfig> {
+        Ok(SeasonConfig {
+            winners: GuildConfig::get_as(ctx, guild_id, ConfigKey::SeasonWinners).await?
+                .unwrap_or(DEFAULT_WINNERS),
+            reward: GuildConfig::get_as(ctx, guild_id, ConfigKey::SeasonReward).await?
+                .unwrap_or(0),
+            role: GuildConfig::get_snowflake(ctx, guild_id, ConfigKey::SeasonRole).await?,
+        })
+    }
+
+    /// Closes the current season of guild `guild_id` (see `Season::close`),
+    /// and moves the season role over to the winners.
+    ///
+    /// Returns the announcement of the results.
+    pub async fn close<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        config: &SeasonConfig,
+        guild_id: GuildId,
+        keep_percent: Option<i32>
+    ) -> Result<String> {
+        let (season, winners) = Season::close(conn, guild_id.into(), config.winners, config.reward, keep_percent)?;
+
+        // The role goes from last season's winners to this one's
+        if let Some(role_id) = config.role {
+            if let Some(last) = Season::get(conn, guild_id.into(), season.number - 1)? {
+                for standing in last.standings(conn, config.winners, 0)? {
+                    let _ = discord.remove_role(guild_id, UserId::new(standing.user_id as u64), role_id).await;
+                }
+            }
+            for winner in &winners {
+                // They might have left
+                let _ = discord.add_role(guild_id, UserId::new(winner.user_id as u64), role_id).await;
+            }
+        }
+
+        let mut announcement = format!("🏆 Season {} is over!", season.number);
+        for winner in &winners {
+            write!(announcement, "\n{}. <@{}> ({} aura earned)", winner.rank, winner.user_id, winner.earned).unwrap();
+        }
+        if config.reward > 0 && !winners.is_empty() {
+            write!(announcement, "\nThe top {} get {} aura each.", winners.len(), config.reward).unwrap();
+        }
+        if let Some(keep_percent) = keep_percent {
+            write!(announcement, "\nEveryone keeps {}% of their aura.", keep_percent).unwrap();
+        }
+        write!(announcement, "\nSeason {} starts now.", season.number + 1).unwrap();
+
+        Ok(announcement)
+    }
+}
+
+/// Ends the season when scheduled to, with `/season schedule`.
+impl Job for SeasonEnd {
+    const KIND: &'static str = "season_end";
+
+    async fn run(ctx: &Context, job: &ScheduledJob) -> Result<()> {
+        let guild_id = GuildId::new(job.guild_id as u64);
+        let payload: SeasonEndPayload = job.payload.parse()?;
+
+        info!(%guild_id, "Ending season");
+        let config = Self::config(ctx, guild_id).await?;
+        let conn = &mut Db::conn(ctx).await?;
+        let announcement = Self::close(ctx.http.as_ref(), conn, &config, guild_id, payload.keep_percent).await?;
+
+        payload.channel.say(&ctx.http, announcement).await
+            .map_err(DungeonBotError::from)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{test_conn, AuraSource, DbUser};
+    use crate::subsystems::discord::FakeDiscord;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const ROLE: RoleId = RoleId::new(2);
+    const ALICE: UserId = UserId::new(3);
+    const BOB: UserId = UserId::new(4);
+
+    fn earn(conn: &mut SqliteConnection, user_id: UserId, pts: i64) {
+        DbUser::new(conn, GUILD.get(), user_id.get()).unwrap();
+        DbUser::add_points(conn, GUILD.get(), user_id.get(), pts, AuraSource::Counting, None, "test").unwrap();
+    }
+
+    #[test]
+    fn payloads_round_trip() {
+        for payload in [
+            SeasonEndPayload { channel: ChannelId::new(5), keep_percent: None },
+            SeasonEndPayload { channel: ChannelId::new(5), keep_percent: Some(50) },
+        ] {
+            assert_eq!(payload.to_string().parse::<SeasonEndPayload>().unwrap(), payload);
+        }
+        assert!("".parse::<SeasonEndPayload>().is_err());
+        assert!("5 half".parse::<SeasonEndPayload>().is_err());
+    }
+
+    #[tokio::test]
+    async fn role_moves_to_the_new_winners() {
+        let conn = &mut test_conn();
+        let discord = FakeDiscord::default();
+        let config = SeasonConfig { winners: 1, reward: 10, role: Some(ROLE) };
+
+        earn(conn, ALICE, 100);
+        SeasonEnd::close(&discord, conn, &config, GUILD, None).await.unwrap();
+        assert!(discord.has_role(GUILD, ALICE, ROLE));
+
+        earn(conn, BOB, 50);
+        let announcement = SeasonEnd::close(&discord, conn, &config, GUILD, Some(50)).await.unwrap();
+        assert!(!discord.has_role(GUILD, ALICE, ROLE));
+        assert!(discord.has_role(GUILD, BOB, ROLE));
+        assert_eq!(announcement, format!(
+            "🏆 Season 2 is over!\n1. <@{}> (50 aura earned)\nThe top 1 get 10 aura each.\n\
+            Everyone keeps 50% of their aura.\nSeason 3 starts now.",
+            BOB
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+//! The aura shop
+//!
+//! Items and purchases are kept in the database (see `db::shop`);
+//! this carries purchases out on Discord, and takes temporary
+//! roles away again once they run out.
+
+use std::time::Duration;
+
+use diesel::SqliteConnection;
+use serenity::all::{
+    parse_message_url, ChannelId, Colour, EditChannel, EditMember, EditRole, GuildChannel,
+    Guild, GuildId, MessageId, RoleId, User, UserId,
+};
+use serenity::async_trait;
+use serenity::prelude::*;
+use tracing::{error, info};
+
+use crate::db::{CountingStats, Db, Purchase, Schedule, ScheduledJob, ShopItem, ShopItemKind};
+use crate::error::{DungeonBotError, Result};
+use crate::unix_now;
+
+use super::{scheduler::Job, Counting};
+
+/// How often to check for temporary roles that ran out
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What a purchase should be used on.
+#[derive(Debug, Clone)]
+pub enum ShopOrder {
+    RoleColor(Colour),
+    Nickname(UserId, String),
+    Topic(ChannelId, String),
+    Pin(ChannelId, MessageId),
+    Unpin(ChannelId, MessageId),
+    TempRole,
+    CountingSave,
+    ServerSave,
+}
+
+impl ShopOrder {
+    /// Reads the `/shop buy` options for an item of `kind` bought in guild `guild_id`.
+    /// Returns None if they don't make sense for it.
+    pub fn parse(
+        kind: ShopItemKind,
+        guild_id: GuildId,
+        member: Option<UserId>,
+        channel: Option<&GuildChannel>,
+        text: Option<&str>
+    ) -> Option<Self> {
+        let text = text.map(str::trim);
+
+        // Only messages in this server
+        let message = || text
+            .and_then(parse_message_url)
+            .filter(|(link_guild_id, _, _)| *link_guild_id == guild_id)
+            .map(|(_, channel_id, message_id)| (channel_id, message_id));
+
+        match kind {
+            ShopItemKind::RoleColor => {
+                let hex = text?.trim_start_matches('#');
+                if hex.len() != 6 { return None }
+                u32::from_str_radix(hex, 16).ok()
+                    .map(|rgb| ShopOrder::RoleColor(Colour::new(rgb)))
+            }
+            ShopItemKind::Nickname => Some(ShopOrder::Nickname(member?, text?.to_string())),
+            ShopItemKind::Topic => channel
+                .filter(|channel| channel.guild_id == guild_id)
+                .map(|channel| ShopOrder::Topic(channel.id, text.unwrap_or_default().to_string())),
+            ShopItemKind::Pin => message().map(|(c, m)| ShopOrder::Pin(c, m)),
+            ShopItemKind::Unpin => message().map(|(c, m)| ShopOrder::Unpin(c, m)),
+            ShopItemKind::TempRole => Some(ShopOrder::TempRole),
+            ShopItemKind::CountingSave => Some(ShopOrder::CountingSave),
+            ShopItemKind::ServerSave => Some(ShopOrder::ServerSave),
+        }
+    }
+
+    /// What the order is used on in guild `guild_id`, for the purchase record.
+    pub fn target(&self, guild_id: GuildId) -> String {
+        match self {
+            ShopOrder::RoleColor(colour) => format!("#{}", colour.hex()),
+            ShopOrder::Nickname(user_id, nick) => format!("<@{}> → {}", user_id, nick),
+            ShopOrder::Topic(channel_id, topic) => format!("<#{}> → {}", channel_id, topic),
+            ShopOrder::Pin(channel_id, message_id)
+                | ShopOrder::Unpin(channel_id, message_id) => message_id.link(*channel_id, Some(guild_id)),
+            ShopOrder::TempRole
+                | ShopOrder::CountingSave
+                | ShopOrder::ServerSave => String::new(),
+        }
+    }
+}
+
+pub struct Shop;
+
+impl Shop {
+    /// Carries out `purchase` (of `item`, by `buyer`) on Discord.
+    pub async fn deliver(
+        ctx: &Context,
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        buyer: &User,
+        item: &ShopItem,
+        purchase: &Purchase,
+        order: &ShopOrder
+    ) -> Result<()> {
+        match order {
+            ShopOrder::RoleColor(colour) => {
+                let role_id = match Purchase::color_role(conn, guild_id.get(), buyer.id.get())? {
+                    Some(role_id) => Self::recolor(ctx, guild_id, RoleId::new(role_id), *colour).await?,
+                    None => None,
+                };
+                let role_id = match role_id {
+                    Some(role_id) => role_id,
+                    None => {
+                        let role = EditRole::new()
+                            .name(format!("{}'s color", buyer.name))
+                            .colour(*colour);
+                        guild_id.create_role(&ctx.http, role).await?.id
+                    }
+                };
+
+                ctx.http.add_member_role(guild_id, buyer.id, role_id, Some("Bought a role color")).await?;
+                Purchase::set_role(conn, purchase.id, role_id.get(), None)?;
+            }
+            ShopOrder::Nickname(user_id, nick) => {
+                guild_id.edit_member(&ctx.http, user_id, EditMember::new().nickname(nick)).await?;
+            }
+            ShopOrder::Topic(channel_id, topic) => {
+                channel_id.edit(&ctx.http, EditChannel::new().topic(topic)).await?;
+            }
+            ShopOrder::Pin(channel_id, message_id) => {
+                channel_id.pin(&ctx.http, message_id).await?;
+            }
+            ShopOrder::Unpin(channel_id, message_id) => {
+                channel_id.unpin(&ctx.http, message_id).await?;
+            }
+            ShopOrder::TempRole => {
+                let (Some(role_id), Some(duration)) = (item.role_id, item.duration) else {
+                    return Err(DungeonBotError::Other(
+                        format!("Shop item `{}` is missing its role or duration", item.name)
+                    ))
+                };
+                let role_id = RoleId::new(role_id as u64);
+
+                ctx.http.add_member_role(guild_id, buyer.id, role_id, Some("Bought a temporary role")).await?;
+                Purchase::set_role(conn, purchase.id, role_id.get(), Some(unix_now() + duration))?;
+            }
+            ShopOrder::CountingSave => {
+                CountingStats::add_saves(conn, guild_id.get(), buyer.id.get(), 1)?;
+            }
+            ShopOrder::ServerSave => {
+                Counting::add_db_save_pool(conn, guild_id, 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the color of an existing personal role.
+    /// Returns None if the role no longer exists.
+    async fn recolor(
+        ctx: &Context,
+        guild_id: GuildId,
+        role_id: RoleId,
+        colour: Colour
+    ) -> Result<Option<RoleId>> {
+        match guild_id.edit_role(&ctx.http, role_id, EditRole::new().colour(colour)).await {
+            Ok(role) => Ok(Some(role.id)),
+            // Role has since been deleted
+            Err(serenity::Error::Http(e))
+                if e.status_code().map(|c| c.as_u16()) == Some(404) => Ok(None),
+            Err(e) => Err(DungeonBotError::from(e)),
+        }
+    }
+
+    /// Refunds purchase `purchase_id` in guild `guild_id`, taking
+    /// back its temporary role or counting save if it still has one.
+    /// Returns None if there's nothing to refund.
+    pub async fn refund(
+        ctx: &Context,
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        purchase_id: i32,
+        refunded_by: Option<UserId>
+    ) -> Result<Option<Purchase>> {
+        let Some(purchase) = Purchase::refund(
+            conn,
+            guild_id.get(),
+            purchase_id,
+            refunded_by.map(UserId::get)
+        )? else { return Ok(None) };
+
+        if purchase.expires_at.is_some() && !purchase.expired {
+            Self::expire(ctx, conn, &purchase).await?;
+        }
+
+        // Saves that were already used up stay used
+        match ShopItem::get(conn, purchase.item_id)?.kind()? {
+            ShopItemKind::CountingSave => {
+                CountingStats::add_saves(conn, guild_id.get(), purchase.user_id as u64, -1)?;
+            }
+            ShopItemKind::ServerSave => {
+                Counting::add_db_save_pool(conn, guild_id, -1)?;
+            }
+            _ => {}
+        }
+
+        Ok(Some(purchase))
+    }
+
+    /// Takes away `purchase`'s temporary role.
+    async fn expire(ctx: &Context, conn: &mut SqliteConnection, purchase: &Purchase) -> Result<()> {
+        if let Some(role_id) = purchase.role_id {
+            let removed = ctx.http.remove_member_role(
+                GuildId::new(purchase.guild_id as u64),
+                UserId::new(purchase.user_id as u64),
+                RoleId::new(role_id as u64),
+                Some("Temporary role ran out")
+            ).await;
+
+            match removed {
+                Ok(()) => {}
+                // Member (or role) is gone anyways
+                Err(serenity::Error::Http(e))
+                    if e.status_code().map(|c| c.as_u16()) == Some(404) => {}
+                Err(e) => return Err(DungeonBotError::from(e)),
+            }
+        }
+
+        Purchase::set_expired(conn, purchase.id)?;
+
+        Ok(())
+    }
+}
+
+/// Takes away temporary roles as they run out, every `EXPIRY_INTERVAL`.
+impl Job for Shop {
+    const KIND: &'static str = "shop_expiry";
+
+    fn default_schedule() -> Option<Schedule> {
+        Some(Schedule::Every(EXPIRY_INTERVAL.as_secs() as i64))
+    }
+
+    async fn run(ctx: &Context, job: &ScheduledJob) -> Result<()> {
+        let conn = &mut Db::conn(ctx).await?;
+
+        for purchase in Purchase::due(conn, job.guild_id as u64, unix_now())? {
+            info!(purchase_id = purchase.id, "Temporary role ran out");
+            if let Err(err) = Self::expire(ctx, conn, &purchase).await {
+                error!(purchase_id = purchase.id, ?err, "Unable to take away temporary role");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventHandler for Shop {
+    /// Fires for every guild on startup, and whenever DungeonBot joins one.
+    async fn guild_create(&self, ctx: Context, guild: Guild, _: Option<bool>) {
+        let conn = &mut match Db::conn(&ctx).await {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!(?err, "Unable to connect to database");
+                return
+            }
+        };
+
+        match ShopItem::add_defaults(conn, guild.id.get()) {
+            Ok(0) => {}
+            Ok(added) => info!(guild_id = %guild.id, added, "Stocked default shop items"),
+            Err(err) => error!(guild_id = %guild.id, ?err, "Unable to stock default shop items"),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! Starboard
+//!
+//! Messages that get enough stars (or whichever emoji the guild picked)
+//! are reposted in the starboard channel, and their authors earn aura
+//! for every star. The repost is kept up to date as stars come and go
+//! and as the original is edited.
+
+use std::collections::HashMap;
+
+use serenity::all::{
+    ChannelId, CreateEmbed, CreateEmbedAuthor, GuildId, Message, MessageId, MessageUpdateEvent,
+    Reaction, ReactionType, UserId,
+};
+use diesel::{Connection, SqliteConnection};
+use serenity::async_trait;
+use serenity::prelude::*;
+use tracing::error;
+
+use crate::db::{AuraSource, ConfigKey, Db, DbUser, GuildConfig, StarredMessage};
+use crate::error::Result;
+
+use super::discord::{same_emoji, Discord};
+use super::subsystem::{AsyncRwLock, Subsystem, SyncRwLock};
+
+/// Used when `starboard.emoji` isn't set
+const DEFAULT_EMOJI: &str = "⭐";
+/// Used when `starboard.threshold` isn't set
+const DEFAULT_THRESHOLD: i64 = 3;
+/// Aura earned by the author for each star
+const STAR_REWARD: i64 = 5;
+
+/// Only one update at a time in each guild, so two stars at once can't both repost.
+type StarboardLock = SyncRwLock<HashMap<GuildId, AsyncRwLock<()>>>;
+
+/// The starboard settings of a guild, read from its config.
+#[derive(Debug, Clone)]
+pub struct StarboardConfig {
+    pub channel: ChannelId,
+    pub emoji: ReactionType,
+    /// Stars needed to make it onto the starboard
+    pub threshold: i64,
+}
+
+pub struct Starboard;
+impl TypeMapKey for Starboard {
+    type Value = StarboardLock;
+}
+
+impl Subsystem for Starboard {
+    type Data = HashMap<GuildId, AsyncRwLock<()>>;
+
+    async fn reaction_handler(ctx: &mut Context, reaction: &Reaction) -> Result<()> {
+        Self::on_reaction(ctx, reaction).await
+    }
+
+    async fn reaction_remove_handler(ctx: &mut Context, reaction: &Reaction) -> Result<()> {
+        Self::on_reaction(ctx, reaction).await
+    }
+}
+
+impl Starboard {
+    /// Gets the starboard settings of guild `guild_id`.
+    /// Returns None if there is no starboard.
+    pub async fn config(ctx: &Context, guild_id: GuildId) -> Result<Option<StarboardConfig>> {
+        let Some(channel) = GuildConfig::get_snowflake::<ChannelId>(
+            ctx, guild_id, ConfigKey::StarboardChannel
+        ).await? else { return Ok(None) };
+
+        let emoji = GuildConfig::get_as::<ReactionType>(ctx, guild_id, ConfigKey::StarboardEmoji).await?
+            .unwrap_or(ReactionType::Unicode(DEFAULT_EMOJI.to_string()));
+        let threshold = GuildConfig::get_as::<i64>(ctx, guild_id, ConfigKey::StarboardThreshold).await?
+            .unwrap_or(DEFAULT_THRESHOLD);
+
+        Ok(Some(StarboardConfig { channel, emoji, threshold }))
+    }
+
+    async fn on_reaction(ctx: &Context, reaction: &Reaction) -> Result<()> {
+        let Some(guild_id) = reaction.guild_id else { return Ok(()) };
+
+        // Starboard isn't set up in this guild
+        let Some(config) = Self::config(ctx, guild_id).await? else { return Ok(()) };
+
+        if !same_emoji(&reaction.emoji, &config.emoji) { return Ok(()) }
+
+        Self::update(ctx, &config, guild_id, reaction.channel_id, reaction.message_id).await
+    }
+
+    /// Recounts the stars on a message, pays its author for any new ones
+    /// (or takes back lost ones), and puts it on (or takes it off) the starboard.
+    async fn update(
+        ctx: &Context,
+        config: &StarboardConfig,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        message_id: MessageId
+    ) -> Result<()> {
+        // No starring the starboard
+        if channel_id == config.channel { return Ok(()) }
+
+        let guild_lock = Self::lock(ctx).await?
+            .write()?
+            .entry(guild_id)
+            .or_default()
+            .clone();
+        let _write_lock = guild_lock.write().await?;
+
+        let msg = channel_id.message(&ctx.http, message_id).await?;
+        if msg.author.bot { return Ok(()) }
+
+        let stars = Self::count(ctx, &msg, &config.emoji).await?;
+
+        let conn = &mut Db::conn(ctx).await?;
+        Self::apply(ctx.http.as_ref(), conn, config, guild_id, &msg, stars).await
+    }
+
+    /// Pays the author of `msg` for the stars it gained since it was last
+    /// seen (or takes back lost ones), and puts it on (or takes it off)
+    /// the starboard, now that it has `stars` stars.
+    pub async fn apply<D: Discord>(
+        discord: &D,
+        conn: &mut SqliteConnection,
+        config: &StarboardConfig,
+        guild_id: GuildId,
+        msg: &Message,
+        stars: i32
+    ) -> Result<()> {
+        let mut starred = StarredMessage::get(conn, msg.id.get())?
+            .unwrap_or(StarredMessage {
+                message_id: msg.id.get() as i64,
+                guild_id: guild_id.get() as i64,
+                channel_id: msg.channel_id.get() as i64,
+                author_id: msg.author.id.get() as i64,
+                stars: 0,
+                repost_id: None,
+            });
+
+        let gained = stars - starred.stars;
+        if gained != 0 {
+            // Saving the stars with the payout keeps them from being
+            // paid again if updating the repost fails
+            starred.stars = stars;
+            conn.transaction(|conn| {
+                DbUser::new(conn, guild_id.get(), msg.author.id.get())?;
+                DbUser::add_points(
+                    conn,
+                    guild_id.get(),
+                    msg.author.id.get(),
+                    i64::from(gained) * STAR_REWARD,
+                    AuraSource::Starboard,
+                    None,
+                    &format!("{:+} {} on {}", gained, config.emoji, msg.link())
+                )?;
+                starred.save(conn)
+            })?;
+        }
+
+        let (content, embed) = Self::repost(msg, stars, &config.emoji);
+        let repost_id = starred.repost_id.map(|id| MessageId::new(id as u64));
+
+        starred.repost_id = match (stars as i64 >= config.threshold, repost_id) {
+            (true, Some(repost_id)) => {
+                let edited = discord.edit_embed(config.channel, repost_id, &content, embed).await?;
+                // Repost was deleted by hand; the next star reposts it
+                edited.then_some(repost_id)
+            }
+            (true, None) => Some(discord.post_embed(config.channel, &content, embed).await?),
+            (false, Some(repost_id)) => {
+                discord.delete_message(config.channel, repost_id).await?;
+                None
+            }
+            (false, None) => None,
+        }.map(|id| id.get() as i64);
+
+        starred.save(conn)?;
+
+        Ok(())
+    }
+
+    /// Counts the stars on `msg`, not counting bots or the author.
+    async fn count(ctx: &Context, msg: &Message, emoji: &ReactionType) -> Result<i32> {
+        let mut stars = 0;
+        let mut after: Option<UserId> = None;
+
+        loop {
+            let page = msg.reaction_users(&ctx.http, emoji.clone(), Some(100), after).await?;
+            let Some(last) = page.last() else { break };
+            after = Some(last.id);
+
+            stars += page.iter()
+                .filter(|user| !user.bot && user.id != msg.author.id)
+                .count() as i32;
+        }
+
+        Ok(stars)
+    }
+
+    /// The starboard repost of `msg`.
+    fn repost(msg: &Message, stars: i32, emoji: &ReactionType) -> (String, CreateEmbed) {
+        let content = format!("{} **{}** <#{}>", emoji, stars, msg.channel_id);
+
+        let author = CreateEmbedAuthor::new(&msg.author.name)
+            .icon_url(msg.author.face());
+        let mut embed = CreateEmbed::new()
+            .author(author)
+            .description(&msg.content)
+            .field("Source", format!("[Jump to message]({})", msg.link()), false)
+            .timestamp(msg.timestamp);
+
+        let image = msg.attachments.iter()
+            .find(|attachment| attachment.content_type.as_deref()
+                .is_some_and(|t| t.starts_with("image/")));
+        if let Some(image) = image {
+            embed = embed.image(&image.url);
+        }
+
+        (content, embed)
+    }
+}
+
+#[async_trait]
+impl EventHandler for Starboard {
+    async fn reaction_add(&self, mut ctx: Context, add_reaction: Reaction) {
+        if let Err(err) = Self::reaction_handler(&mut ctx, &add_reaction).await {
+            Self::reaction_error_handler(&mut ctx, &add_reaction, err).await;
+        }
+    }
+
+    async fn reaction_remove(&self, mut ctx: Context, removed_reaction: Reaction) {
+        if let Err(err) = Self::reaction_remove_handler(&mut ctx, &removed_reaction).await {
+            Self::reaction_error_handler(&mut ctx, &removed_reaction, err).await;
+        }
+    }
+
+    /// Keeps reposts in sync with edits to the original
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _: Option<Message>,
+        _: Option<Message>,
+        event: MessageUpdateEvent
+    ) {
+        let Some(guild_id) = event.guild_id else { return };
+
+        let starred = match Db::conn(&ctx).await
+            .and_then(|mut conn| StarredMessage::get(&mut conn, event.id.get())) {
+            Ok(Some(starred)) if starred.repost_id.is_some() => starred,
+            Ok(_) => return,
+            Err(err) => {
+                error!(?err, "Unable to load starred message");
+                return
+            }
+        };
+
+        let result = match Self::config(&ctx, guild_id).await {
+            Ok(Some(config)) => Self::update(
+                &ctx,
+                &config,
+                guild_id,
+                ChannelId::new(starred.channel_id as u64),
+                event.id
+            ).await,
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            error!(?err, "Unable to update starboard repost");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+    use crate::subsystems::discord::FakeDiscord;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const STARBOARD: ChannelId = ChannelId::new(2);
+    const CHANNEL: ChannelId = ChannelId::new(3);
+    const AUTHOR: UserId = UserId::new(4);
+
+    fn config() -> StarboardConfig {
+        StarboardConfig {
+            channel: STARBOARD,
+            emoji: ReactionType::Unicode(DEFAULT_EMOJI.to_string()),
+            threshold: 2,
+        }
+    }
+
+    fn message() -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(5);
+        msg.channel_id = CHANNEL;
+        msg.author.id = AUTHOR;
+        msg.content = "hello".to_string();
+        msg
+    }
+
+    fn aura(conn: &mut SqliteConnection) -> i64 {
+        DbUser::get_points(conn, GUILD.get(), AUTHOR.get()).unwrap().unwrap()
+    }
+
+    fn repost_id(conn: &mut SqliteConnection) -> Option<MessageId> {
+        StarredMessage::get(conn, 5).unwrap().unwrap()
+            .repost_id
+            .map(|id| MessageId::new(id as u64))
+    }
+
+    #[tokio::test]
+    async fn unstarring_takes_the_aura_back() {
+        let conn = &mut test_conn();
+        let discord = FakeDiscord::default();
+        let msg = message();
+
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 1).await.unwrap();
+        assert_eq!(aura(conn), STAR_REWARD);
+
+        // Seeing the same stars again doesn't pay twice
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 1).await.unwrap();
+        assert_eq!(aura(conn), STAR_REWARD);
+
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 0).await.unwrap();
+        assert_eq!(aura(conn), 0);
+        assert!(discord.posts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reposted_at_the_threshold() {
+        let conn = &mut test_conn();
+        let discord = FakeDiscord::default();
+        let msg = message();
+
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 2).await.unwrap();
+        let posts = discord.posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].0, STARBOARD);
+        assert_eq!(posts[0].2, format!("⭐ **2** <#{}>", CHANNEL));
+        assert_eq!(repost_id(conn), Some(posts[0].1));
+
+        // More stars edit the repost
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 3).await.unwrap();
+        let posts = discord.posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].2, format!("⭐ **3** <#{}>", CHANNEL));
+
+        // Falling under it takes it down
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 1).await.unwrap();
+        assert!(discord.posts().is_empty());
+        assert_eq!(repost_id(conn), None);
+        assert_eq!(aura(conn), STAR_REWARD);
+    }
+
+    #[tokio::test]
+    async fn reposts_deleted_by_hand_come_back() {
+        let conn = &mut test_conn();
+        let discord = FakeDiscord::default();
+        let msg = message();
+
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 2).await.unwrap();
+        discord.posts.lock().unwrap().clear();
+
+        // The lost repost is forgotten...
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 3).await.unwrap();
+        assert_eq!(repost_id(conn), None);
+        assert!(discord.posts().is_empty());
+
+        // ...and the next star reposts it
+        Starboard::apply(&discord, conn, &config(), GUILD, &msg, 4).await.unwrap();
+        let posts = discord.posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(repost_id(conn), Some(posts[0].1));
+        assert_eq!(aura(conn), 4 * STAR_REWARD);
+    }
+}
@@ -2,6 +2,7 @@
 //!
 
 use serenity::prelude::*;
+use tracing::error;
 
 use crate::error::{DungeonBotError, Result};
 
@@ -29,13 +30,13 @@ impl<T> SyncRwLock<T>
 where 
     T: Data
 {
-    pub fn read(&self) -> Result<std::sync::RwLockReadGuard<T>> {
+    pub fn read(&self) -> Result<std::sync::RwLockReadGuard<'_, T>> {
         self.0.read()
             .map_err(|e| SubsystemError::LockReadError(format!("{}", e)))
             .map_err(DungeonBotError::from)
     }
 
-    pub fn write(&self) -> Result<std::sync::RwLockWriteGuard<T>> {
+    pub fn write(&self) -> Result<std::sync::RwLockWriteGuard<'_, T>> {
         self.0.write()
             .map_err(|e| SubsystemError::LockWriteError(format!("{}", e)))
             .map_err(DungeonBotError::from)
@@ -51,18 +52,18 @@ impl<T> AsyncRwLock<T>
 where 
     T: Data
 {
-    pub async fn read<'a>(&'a self) -> Result<tokio::sync::RwLockReadGuard<T>> {
+    pub async fn read(&self) -> Result<tokio::sync::RwLockReadGuard<'_, T>> {
         Ok(self.0.read().await)
     }
 
-    pub async fn write<'a>(&'a self) -> Result<tokio::sync::RwLockWriteGuard<T>> {
+    pub async fn write(&self) -> Result<tokio::sync::RwLockWriteGuard<'_, T>> {
         Ok(self.0.write().await)
     }
 }
 
 impl<T> SubsystemLock<T> for AsyncRwLock<T> where T: Data {}
 
-use serenity::all::Message;
+use serenity::all::{Message, Reaction};
 
 pub trait Subsystem: TypeMapKey + Sized
 where 
@@ -88,27 +89,48 @@ where
     }
 
     #[allow(async_fn_in_trait, unused_variables)]
-    async fn reaction_handler(ctx: &mut Context, msg: &Message) -> Result<()> {
+    async fn reaction_handler(ctx: &mut Context, reaction: &Reaction) -> Result<()> {
+        Ok(())
+    }
+
+    #[allow(async_fn_in_trait, unused_variables)]
+    async fn reaction_remove_handler(ctx: &mut Context, reaction: &Reaction) -> Result<()> {
         Ok(())
     }
 
     #[allow(async_fn_in_trait)]
     async fn error_handler(ctx: &mut Context, msg: &Message, err: DungeonBotError) {
-        let header = 
-            "Oh noes, an error \
-            <:flabbergasted:1250998996596555817>. \
-            Please let Jasper know about this immediately.\n";
-
-        let mut reply = header.to_string();
         if let Ok(channel_id) = msg.channel(&ctx.http).await {
-            reply.push_str("```\n");
-            reply.push_str("[Subsystem Error]\n");
-            reply.push_str(&format!("{}\n", err));
-            reply.push_str(&format!("{:?}\n", err));
-            reply.push_str(&format!("{:?}\n", err.source()));
-            reply.push_str("```");
-            channel_id.id().say(&ctx.http, reply).await
+            channel_id.id().say(&ctx.http, error_reply(&err)).await
                 .expect("Unable to send error handler reply");
         }
     }
+
+    /// Reactions can come from any channel, including ones DungeonBot
+    /// can't talk in, so their errors are only logged.
+    #[allow(async_fn_in_trait, unused_variables)]
+    async fn reaction_error_handler(ctx: &mut Context, reaction: &Reaction, err: DungeonBotError) {
+        error!(
+            channel_id = %reaction.channel_id,
+            message_id = %reaction.message_id,
+            ?err,
+            "Unable to handle reaction"
+        );
+    }
+}
+
+fn error_reply(err: &DungeonBotError) -> String {
+    let header = 
+        "Oh noes, an error \
+        <:flabbergasted:1250998996596555817>. \
+        Please let Jasper know about this immediately.\n";
+
+    let mut reply = header.to_string();
+    reply.push_str("```\n");
+    reply.push_str("[Subsystem Error]\n");
+    reply.push_str(&format!("{}\n", err));
+    reply.push_str(&format!("{:?}\n", err));
+    reply.push_str(&format!("{:?}\n", err.source()));
+    reply.push_str("```");
+    reply
 }
@@ -1,18 +1,61 @@
 use std::collections::HashMap;
+use std::time::Duration;
+
 use rand::prelude::*;
+use tracing::info;
+
+use diesel::prelude::*;
+use diesel::SqliteConnection;
 
-use crate::db::{db_conn, DbUser};
-use crate::error::Result;
-use super::{subsystem::SyncRwLock, Subsystem};
+use crate::db::{AuraSource, ConfigKey, Db, DbUser, GuildConfig, Schedule, ScheduledJob};
+use crate::db::models::{StateVar, TaxContribution};
+use crate::error::{DungeonBotError, Result};
+use super::{scheduler::Job, subsystem::SyncRwLock, Subsystem};
 
 use serenity::prelude::*;
-use serenity::all::{UserId, Message};
+use serenity::all::{ChannelId, GuildId, UserId, Message};
 
-type TaxData = HashMap<UserId, i32>;
-type TaxLock = SyncRwLock<TaxData>;
+/// Number of messages sent by each user of each guild since the last payout.
+type TaxData = HashMap<GuildId, HashMap<UserId, i32>>;
+pub type TaxLock = SyncRwLock<TaxData>;
 
 pub const TAX_RATE: f64 = 0.1;
 
+/// How often the treasury automatically pays out
+pub const TAX_PAYOUT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How the treasury returns collected tax to the economy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum TaxPayoutMode {
+    /// One random active user wins the whole pool
+    #[name = "Lottery"]
+    Lottery,
+    /// The pool is split equally between active users
+    #[name = "Dividend"]
+    Dividend,
+    /// Only paid out by hand, with `/tax grant`
+    #[name = "Grant"]
+    Grant,
+}
+
+impl TaxPayoutMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaxPayoutMode::Lottery => "lottery",
+            TaxPayoutMode::Dividend => "dividend",
+            TaxPayoutMode::Grant => "grant",
+        }
+    }
+}
+
+/// The result of a treasury payout.
+#[derive(Debug, Clone)]
+pub enum TaxPayout {
+    Lottery { winner: UserId, amount: i64 },
+    Dividend { recipients: usize, each: i64 },
+    Nothing,
+}
+
 pub struct Tax;
 impl TypeMapKey for Tax {
     type Value = TaxLock;
@@ -21,30 +64,308 @@ impl Subsystem for Tax {
     type Data = TaxData;
 
     async fn message_handler(ctx: &mut Context, msg: &Message) -> Result<()> {
+        let Some(guild_id) = msg.guild_id else { return Ok(()) };
 
         let collect_tax = rand::thread_rng().gen::<f64>() < TAX_RATE;
 
+        let lock = Self::lock(ctx).await?;
+        let conn = &mut Db::conn(ctx).await?;
+        Self::tally(conn, &lock, guild_id, msg.author.id, collect_tax)
+    }
+}
+
+impl Tax {
+    /// Counts a message by `user_id` towards the next payout, and taxes
+    /// them if `collect_tax` is set.
+    pub fn tally(
+        conn: &mut SqliteConnection,
+        lock: &TaxLock,
+        guild_id: GuildId,
+        user_id: UserId,
+        collect_tax: bool
+    ) -> Result<()> {
+        // Keep track of who's active for payouts
+        {
+            let mut write_lock = lock.write()?;
+
+            let active = write_lock.entry(guild_id).or_default();
+            let curr = active.get(&user_id)
+                .cloned()
+                .unwrap_or(0);
+
+            active.insert(user_id, curr+1);
+        }
+
         if collect_tax {
+            Self::collect(conn, guild_id, user_id.get(), 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes `amount` aura from user `uid` and puts it in guild `gid`'s treasury.
+    pub fn collect(conn: &mut SqliteConnection, gid: GuildId, uid: u64, amount: i64) -> Result<()> {
+        use crate::db::schema::tax_contributions::dsl::*;
+
+        conn.transaction(|conn| {
+            let collected = DbUser::add_points(
+                conn,
+                gid.get(),
+                uid,
+                -amount,
+                AuraSource::Tax,
+                None,
+                "Message tax"
+            );
+
+            // Nobody to collect from
+            if let Err(DungeonBotError::DbUserNotFoundError(_)) = collected { return Ok(()) }
+            collected?;
+
+            Self::add_db_pool(conn, gid, amount)?;
+
+            let contribution = TaxContribution {
+                guild_id: gid.get() as i64,
+                user_id: uid as i64,
+                total: amount,
+            };
+            diesel::insert_into(tax_contributions)
+                .values(&contribution)
+                .on_conflict((guild_id, user_id))
+                .do_update()
+                .set(total.eq(total + amount))
+                .execute(conn)?;
 
-            let conn = &mut db_conn()?;
-            DbUser::add_points(conn, msg.author.id.get(), -1)?;
+            Ok(())
+        })
+    }
+
+    /// Pays out guild `guild_id`'s treasury to the users active since
+    /// the last payout, according to the saved payout mode.
+    pub async fn payout(ctx: &Context, guild_id: GuildId) -> Result<TaxPayout> {
+        let lock = Self::lock(ctx).await?;
+        let conn = &mut Db::conn(ctx).await?;
+        Self::payout_with(conn, &lock, guild_id)
+    }
+
+    /// Like `payout`, with the active users behind `lock`.
+    pub fn payout_with(
+        conn: &mut SqliteConnection,
+        lock: &TaxLock,
+        guild_id: GuildId
+    ) -> Result<TaxPayout> {
+        let mode = Self::get_db_mode(conn, guild_id)?;
+
+        let mut write_lock = lock.write()?;
+
+        let active: Vec<UserId> = write_lock.get(&guild_id)
+            .map(|active| active.keys().cloned().collect())
+            .unwrap_or_default();
+        let payout = Self::distribute(conn, guild_id, mode, &active)?;
+
+        if !matches!(payout, TaxPayout::Nothing) {
+            write_lock.remove(&guild_id);
+        }
+
+        Ok(payout)
+    }
+
+    fn distribute(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        mode: TaxPayoutMode,
+        active: &[UserId]
+    ) -> Result<TaxPayout> {
+        conn.transaction(|conn| {
+            let pool = Self::get_db_pool(conn, guild_id)?;
+            if pool <= 0 || active.is_empty() {
+                return Ok(TaxPayout::Nothing)
+            }
+
+            match mode {
+                TaxPayoutMode::Lottery => {
+                    let winner = *active.choose(&mut rand::thread_rng())
+                        .expect("active users should not be empty");
+
+                    DbUser::new(conn, guild_id.get(), winner.get())?;
+                    DbUser::add_points(
+                        conn,
+                        guild_id.get(),
+                        winner.get(),
+                        pool,
+                        AuraSource::Tax,
+                        None,
+                        "Won the tax lottery"
+                    )?;
+                    Self::add_db_pool(conn, guild_id, -pool)?;
 
-            {
-                let lock = Self::lock(ctx).await?;
-                let mut write_lock = lock.write()?;
+                    Ok(TaxPayout::Lottery { winner, amount: pool })
+                }
+                TaxPayoutMode::Dividend => {
+                    let each = pool / active.len() as i64;
+                    if each == 0 {
+                        return Ok(TaxPayout::Nothing)
+                    }
 
-                let curr = write_lock.get(&msg.author.id)
-                    .cloned()
-                    .unwrap_or(0);
+                    for user_id in active {
+                        DbUser::new(conn, guild_id.get(), user_id.get())?;
+                        DbUser::add_points(
+                            conn,
+                            guild_id.get(),
+                            user_id.get(),
+                            each,
+                            AuraSource::Tax,
+                            None,
+                            "Tax dividend"
+                        )?;
+                    }
+                    Self::add_db_pool(conn, guild_id, -each * active.len() as i64)?;
 
-                write_lock.insert(msg.author.id, curr+1);
+                    Ok(TaxPayout::Dividend { recipients: active.len(), each })
+                }
+                TaxPayoutMode::Grant => Ok(TaxPayout::Nothing),
             }
+        })
+    }
+
+    /// Grants `amount` aura from guild `guild_id`'s treasury to user `to_id`.
+    /// Returns false if the treasury doesn't have enough.
+    pub fn grant(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        to_id: u64,
+        from_id: u64,
+        amount: i64
+    ) -> Result<bool> {
+        conn.transaction(|conn| {
+            if Self::get_db_pool(conn, guild_id)? < amount {
+                return Ok(false)
+            }
+
+            DbUser::new(conn, guild_id.get(), to_id)?;
+            DbUser::add_points(
+                conn,
+                guild_id.get(),
+                to_id,
+                amount,
+                AuraSource::Tax,
+                Some(from_id),
+                "Tax grant"
+            )?;
+            Self::add_db_pool(conn, guild_id, -amount)?;
+
+            Ok(true)
+        })
+    }
+
+    pub fn get_db_pool(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<i64> {
+        StateVar::get_or_insert(conn, guild_id.get(), "TAX_POOL", "0")?
+            .parse::<i64>()
+            .map_err(|_| DungeonBotError::Other("Unable to parse saved tax pool".to_string()))
+    }
+
+    fn add_db_pool(conn: &mut SqliteConnection, guild_id: GuildId, amount: i64) -> Result<usize> {
+        let pool = Self::get_db_pool(conn, guild_id)?;
+        StateVar::set(conn, guild_id.get(), "TAX_POOL", &format!("{}", pool + amount))
+    }
+
+    pub fn get_db_mode(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<TaxPayoutMode> {
+        match StateVar::get_or_insert(conn, guild_id.get(), "TAX_PAYOUT_MODE", "lottery")?.as_str() {
+            "lottery" => Ok(TaxPayoutMode::Lottery),
+            "dividend" => Ok(TaxPayoutMode::Dividend),
+            "grant" => Ok(TaxPayoutMode::Grant),
+            other => Err(DungeonBotError::Other(format!("Unknown tax payout mode `{}`", other))),
+        }
+    }
+
+    pub fn set_db_mode(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        mode: TaxPayoutMode
+    ) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "TAX_PAYOUT_MODE", mode.as_str())
+    }
+
+    /// Gets user `uid`'s lifetime tax contributions in guild `gid`.
+    pub fn get_db_contribution(conn: &mut SqliteConnection, gid: GuildId, uid: u64) -> Result<i64> {
+        use crate::db::schema::tax_contributions::dsl::*;
+
+        tax_contributions
+            .find((gid.get() as i64, uid as i64))
+            .select(total)
+            .first(conn)
+            .optional()
+            .map(Option::unwrap_or_default)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Retrieves the `lim` biggest lifetime contributors in guild `gid`.
+    pub fn top_contributors(
+        conn: &mut SqliteConnection,
+        gid: GuildId,
+        lim: i64
+    ) -> Result<Vec<TaxContribution>> {
+        use crate::db::schema::tax_contributions::dsl::*;
+
+        tax_contributions
+            .filter(guild_id.eq(gid.get() as i64))
+            .order_by(total.desc())
+            .limit(lim)
+            .select(TaxContribution::as_select())
+            .load(conn)
+            .map_err(DungeonBotError::from)
+    }
+
+    /// Announces a payout in the tax channel, if there is one.
+    async fn announce(ctx: &Context, guild_id: GuildId, payout: &TaxPayout) -> Result<()> {
+        let Some(channel) = GuildConfig::get_snowflake::<ChannelId>(
+            ctx, guild_id, ConfigKey::TaxChannel
+        ).await? else { return Ok(()) };
+
+        if let Some(announcement) = payout.announcement() {
+            channel.say(&ctx.http, announcement).await
+                .map_err(DungeonBotError::from)?;
         }
 
         Ok(())
     }
 }
 
+impl TaxPayout {
+    pub fn announcement(&self) -> Option<String> {
+        match self {
+            TaxPayout::Lottery { winner, amount } => Some(format!(
+                "💰 <@{}> won the tax lottery, and takes home {} aura!",
+                winner,
+                amount
+            )),
+            TaxPayout::Dividend { recipients, each } => Some(format!(
+                "💰 The treasury paid a dividend of {} aura to {} active members!",
+                each,
+                recipients
+            )),
+            TaxPayout::Nothing => None,
+        }
+    }
+}
+
+/// Automatically pays out the treasury every `TAX_PAYOUT_INTERVAL`.
+impl Job for Tax {
+    const KIND: &'static str = "tax_payout";
+
+    fn default_schedule() -> Option<Schedule> {
+        Some(Schedule::Every(TAX_PAYOUT_INTERVAL.as_secs() as i64))
+    }
+
+    async fn run(ctx: &Context, job: &ScheduledJob) -> Result<()> {
+        let guild_id = GuildId::new(job.guild_id as u64);
+
+        info!(%guild_id, "Paying out tax");
+        let payout = Self::payout(ctx, guild_id).await?;
+        Self::announce(ctx, guild_id, &payout).await
+    }
+}
+
 use serenity::async_trait;
 
 #[async_trait]
@@ -58,3 +379,110 @@ impl EventHandler for Tax {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{test_conn, DbTransaction};
+
+    const GUILD: GuildId = GuildId::new(1);
+    const ALICE: UserId = UserId::new(2);
+    const BOB: UserId = UserId::new(3);
+    const CAROL: UserId = UserId::new(4);
+
+    fn setup() -> (SqliteConnection, TaxLock) {
+        let mut conn = test_conn();
+        for user_id in [ALICE, BOB, CAROL] {
+            DbUser::new(&mut conn, GUILD.get(), user_id.get()).unwrap();
+        }
+        (conn, TaxLock::default())
+    }
+
+    fn points(conn: &mut SqliteConnection, user_id: UserId) -> i64 {
+        DbUser::get_points(conn, GUILD.get(), user_id.get()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn collects_into_the_pool() {
+        let (mut conn, lock) = setup();
+
+        Tax::tally(&mut conn, &lock, GUILD, ALICE, true).unwrap();
+        Tax::tally(&mut conn, &lock, GUILD, ALICE, true).unwrap();
+        Tax::tally(&mut conn, &lock, GUILD, BOB, false).unwrap();
+
+        assert_eq!(points(&mut conn, ALICE), -2);
+        assert_eq!(points(&mut conn, BOB), 0);
+        assert_eq!(Tax::get_db_pool(&mut conn, GUILD).unwrap(), 2);
+        assert_eq!(Tax::get_db_contribution(&mut conn, GUILD, ALICE.get()).unwrap(), 2);
+        assert_eq!(Tax::get_db_contribution(&mut conn, GUILD, BOB.get()).unwrap(), 0);
+        assert_eq!(
+            DbTransaction::count_for(&mut conn, GUILD.get(), ALICE.get(), Some(AuraSource::Tax)).unwrap(),
+            2
+        );
+
+        let active = &lock.read().unwrap()[&GUILD];
+        assert_eq!(active[&ALICE], 2);
+        assert_eq!(active[&BOB], 1);
+    }
+
+    #[test]
+    fn unknown_users_are_not_taxed() {
+        let (mut conn, lock) = setup();
+
+        Tax::tally(&mut conn, &lock, GUILD, UserId::new(99), true).unwrap();
+
+        assert_eq!(Tax::get_db_pool(&mut conn, GUILD).unwrap(), 0);
+    }
+
+    #[test]
+    fn lottery_pays_out_whole_pool() {
+        let (mut conn, lock) = setup();
+        for _ in 0..5 {
+            Tax::tally(&mut conn, &lock, GUILD, ALICE, true).unwrap();
+        }
+
+        let payout = Tax::payout_with(&mut conn, &lock, GUILD).unwrap();
+
+        assert!(matches!(payout, TaxPayout::Lottery { winner: ALICE, amount: 5 }));
+        assert_eq!(points(&mut conn, ALICE), 0);
+        assert_eq!(Tax::get_db_pool(&mut conn, GUILD).unwrap(), 0);
+        assert!(!lock.read().unwrap().contains_key(&GUILD));
+    }
+
+    #[test]
+    fn dividend_keeps_the_remainder() {
+        let (mut conn, lock) = setup();
+        Tax::set_db_mode(&mut conn, GUILD, TaxPayoutMode::Dividend).unwrap();
+        for _ in 0..7 {
+            Tax::tally(&mut conn, &lock, GUILD, ALICE, true).unwrap();
+        }
+        Tax::tally(&mut conn, &lock, GUILD, BOB, false).unwrap();
+        Tax::tally(&mut conn, &lock, GUILD, CAROL, false).unwrap();
+
+        let payout = Tax::payout_with(&mut conn, &lock, GUILD).unwrap();
+
+        assert!(matches!(payout, TaxPayout::Dividend { recipients: 3, each: 2 }));
+        assert_eq!(points(&mut conn, ALICE), -7 + 2);
+        assert_eq!(points(&mut conn, BOB), 2);
+        assert_eq!(points(&mut conn, CAROL), 2);
+        assert_eq!(Tax::get_db_pool(&mut conn, GUILD).unwrap(), 1);
+    }
+
+    #[test]
+    fn grant_mode_waits_for_grants() {
+        let (mut conn, lock) = setup();
+        Tax::set_db_mode(&mut conn, GUILD, TaxPayoutMode::Grant).unwrap();
+        for _ in 0..3 {
+            Tax::tally(&mut conn, &lock, GUILD, ALICE, true).unwrap();
+        }
+
+        let payout = Tax::payout_with(&mut conn, &lock, GUILD).unwrap();
+        assert!(matches!(payout, TaxPayout::Nothing));
+        assert!(lock.read().unwrap().contains_key(&GUILD));
+
+        assert!(!Tax::grant(&mut conn, GUILD, BOB.get(), ALICE.get(), 4).unwrap());
+        assert!(Tax::grant(&mut conn, GUILD, BOB.get(), ALICE.get(), 3).unwrap());
+        assert_eq!(points(&mut conn, BOB), 3);
+        assert_eq!(Tax::get_db_pool(&mut conn, GUILD).unwrap(), 0);
+    }
+}
@@ -0,0 +1,248 @@
+//! Word ladder game
+//!
+//! Every (five letter) dictionary word in the word ladder channel must
+//! differ from the previous accepted word by exactly one letter, and
+//! can't repeat a word from the current chain. Anything else is chat.
+//!
+//! Word list taken from the `wordle` crate (MIT).
+
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+use serenity::{async_trait, prelude::*};
+use serenity::all::{ChannelId, GuildId, Message};
+
+use diesel::SqliteConnection;
+
+use crate::db::{AuraSource, ConfigKey, Db, DbUser, GuildConfig};
+use crate::db::models::StateVar;
+use crate::error::{DungeonBotError, Result};
+
+use super::subsystem::{Subsystem, SyncRwLock};
+
+static WORDS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    include_str!("../../assets/words.txt")
+        .lines()
+        .collect()
+});
+
+const WORD_LENGTH: usize = 5;
+const START_WORD: &str = "words";
+
+const WORD_REWARD: i64 = 3;
+const WORD_PENALTY: i64 = -10;
+
+/// Number of words in a finished chain.
+const CHAIN_LENGTH: usize = 20;
+const CHAIN_BONUS: i64 = 100;
+
+/// The current chain of every guild, loaded from
+/// the database the first time it's needed.
+///
+/// Chains hold the accepted words, oldest first. They are never
+/// empty; the last one is the current word.
+#[derive(Debug, Clone, Default)]
+pub struct WordLadderData {
+    pub chains: HashMap<GuildId, Vec<String>>,
+}
+
+type WordLadderLock = SyncRwLock<WordLadderData>;
+
+pub struct WordLadder;
+impl TypeMapKey for WordLadder {
+    type Value = WordLadderLock;
+}
+
+/// Outcome of trying a word against the current chain.
+#[derive(Debug, PartialEq, Eq)]
+enum Attempt {
+    Accepted,
+    ChainComplete,
+    Rejected,
+}
+
+impl Subsystem for WordLadder {
+    type Data = WordLadderData;
+
+    async fn message_handler(ctx: &mut Context, msg: &Message) -> Result<()> {
+        if msg.author.bot { return Ok(()) }
+
+        let Some(guild_id) = msg.guild_id else { return Ok(()) };
+
+        // Word ladder isn't set up in this guild
+        let Some(wlchannel) = GuildConfig::get_snowflake::<ChannelId>(
+            ctx, guild_id, ConfigKey::WordLadderChannel
+        ).await? else { return Ok(()) };
+
+        // Don't care if it's not in the right channel!
+        if msg.channel_id != wlchannel { return Ok(()) }
+
+        // Only look at the first word, and ignore anything that
+        // isn't a guess (i.e. chatting)
+        let Some(word) = msg.content
+            .split_whitespace()
+            .next()
+            .map(str::to_lowercase) else { return Ok(()) };
+        if word.len() != WORD_LENGTH || !WORDS.contains(word.as_str()) {
+            return Ok(())
+        }
+
+        let connection = &mut Db::conn(ctx).await?;
+
+        // Make sure this guild's chain is loaded
+        let wllock = Self::lock(ctx).await?;
+        if !wllock.read()?.chains.contains_key(&guild_id) {
+            let chain = Self::get_db_chain(connection, guild_id)?;
+            wllock.write()?.chains.entry(guild_id).or_insert(chain);
+        }
+
+        // Check and update the chain behind the lock
+        let (attempt, chain) = {
+            let mut write_lock = wllock.write()?;
+            let chain = write_lock.chains.entry(guild_id).or_default();
+
+            let attempt = Self::attempt(chain, word);
+            (attempt, chain.clone())
+        };
+
+        DbUser::new(connection, guild_id.into(), msg.author.id.into())?;
+        match attempt {
+            Attempt::Accepted => {
+                Self::set_db_chain(connection, guild_id, &chain)?;
+                DbUser::add_points(connection, guild_id.into(), msg.author.id.into(), WORD_REWARD, AuraSource::WordLadder, None, "Word ladder word")?;
+                msg.react(&ctx.http, '✅').await
+                    .map_err(DungeonBotError::from)?;
+            }
+            Attempt::ChainComplete => {
+                Self::set_db_chain(connection, guild_id, &chain)?;
+                DbUser::add_points(connection, guild_id.into(), msg.author.id.into(), CHAIN_BONUS, AuraSource::WordLadder, None, "Completed a word ladder chain")?;
+                msg.react(&ctx.http, '🏆').await
+                    .map_err(DungeonBotError::from)?;
+            }
+            Attempt::Rejected => {
+                DbUser::add_points(connection, guild_id.into(), msg.author.id.into(), WORD_PENALTY, AuraSource::WordLadder, None, "Invalid word ladder word")?;
+                msg.react(&ctx.http, '❌').await
+                    .map_err(DungeonBotError::from)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl WordLadder {
+    /// Tries to extend the chain with `word`.
+    ///
+    /// A completed chain starts over from its last word.
+    fn attempt(chain: &mut Vec<String>, word: String) -> Attempt {
+        let current = chain.last()
+            .map(String::as_str)
+            .unwrap_or(START_WORD);
+
+        let is_valid = WORDS.contains(word.as_str())
+            && is_one_letter_apart(current, &word)
+            && !chain.contains(&word);
+
+        if !is_valid {
+            return Attempt::Rejected
+        }
+
+        chain.push(word);
+
+        if chain.len() >= CHAIN_LENGTH {
+            chain.drain(..chain.len() - 1);
+            Attempt::ChainComplete
+        } else {
+            Attempt::Accepted
+        }
+    }
+
+    pub fn get_db_chain(conn: &mut SqliteConnection, guild_id: GuildId) -> Result<Vec<String>> {
+        let chain = StateVar::get_or_insert(conn, guild_id.get(), "WORDLADDER", START_WORD)?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+
+        if chain.is_empty() {
+            Ok(vec![START_WORD.to_string()])
+        } else {
+            Ok(chain)
+        }
+    }
+
+    pub fn set_db_chain(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        chain: &[String]
+    ) -> Result<usize> {
+        StateVar::set(conn, guild_id.get(), "WORDLADDER", &chain.join(" "))
+    }
+}
+
+/// Checks if `a` and `b` are the same length and differ in exactly one place.
+fn is_one_letter_apart(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.chars()
+            .zip(b.chars())
+            .filter(|(x, y)| x != y)
+            .count() == 1
+}
+
+#[async_trait]
+impl EventHandler for WordLadder {
+    async fn message(&self, mut ctx: Context, msg: Message) {
+
+        if msg.author.bot { return }
+
+        if let Err(err) = Self::message_handler(&mut ctx, &msg).await {
+            Self::error_handler(&mut ctx, &msg, err).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn one_letter_apart() {
+        assert!(is_one_letter_apart("words", "wards"));
+        assert!(!is_one_letter_apart("words", "words"));
+        assert!(!is_one_letter_apart("words", "cards"));
+        assert!(!is_one_letter_apart("words", "word"));
+    }
+
+    #[test]
+    fn words_must_follow_the_chain() {
+        let mut ladder = chain(&["words"]);
+
+        assert_eq!(WordLadder::attempt(&mut ladder, "wards".to_string()), Attempt::Accepted);
+        assert_eq!(ladder, chain(&["words", "wards"]));
+
+        // Not one letter off, not a word, and already used
+        for word in ["lords", "wardz", "words"] {
+            assert_eq!(WordLadder::attempt(&mut ladder, word.to_string()), Attempt::Rejected);
+        }
+        assert_eq!(ladder, chain(&["words", "wards"]));
+    }
+
+    #[test]
+    fn finished_chains_start_over_from_the_last_word() {
+        let mut ladder = vec![String::new(); CHAIN_LENGTH - 2];
+        ladder.push("words".to_string());
+
+        assert_eq!(WordLadder::attempt(&mut ladder, "cords".to_string()), Attempt::ChainComplete);
+        assert_eq!(ladder, chain(&["cords"]));
+    }
+
+    #[test]
+    fn empty_chains_start_from_the_start_word() {
+        let mut ladder = Vec::new();
+
+        assert_eq!(WordLadder::attempt(&mut ladder, "wards".to_string()), Attempt::Accepted);
+    }
+}